@@ -1,57 +1,286 @@
-use super::build_value::value_to_asm;
-use super::gen_riscv::*;
+use super::build_value::{params_to_asm, value_to_asm};
+use super::machine::{BinOp, Frame, FrameObjectKind, MachineFunc};
+use super::regalloc::{graph_coloring, linear_scan, Allocation, Location, ALLOC_REGS};
+use super::runtime;
 use super::util::*;
 use super::Asm;
+use super::AsmConfig;
 use super::GenerateAsm;
+use super::Reg;
+use super::RegAllocKind;
 use super::REG_LIST;
+use crate::opt::util::points_into_frame;
 use koopa::ir::entities::ValueData;
 use koopa::ir::{FunctionData, Program, TypeKind, Value, ValueKind};
-use std::cmp::max;
+use std::cmp::{max, min};
+use std::collections::{HashMap, HashSet};
 
 pub struct FuncContext<'a> {
     pub func_data: &'a FunctionData,
     pub stack_size: usize,
-    pub current_offset: usize,
+    // objects of the frame from sp up, the saved registers at the top
+    pub frame: Frame,
     pub value_table: ValueTable,
     pub has_call: bool,
     pub max_param_num: i32,
+    // offset of the area used for parallel copies of block arguments
+    pub scratch_offset: i32,
+    // calls lowered to `tail`, and the `ret`s following them
+    pub tail_calls: HashSet<Value>,
+    pub tail_rets: HashSet<Value>,
     pub program: &'a Program,
+    // where the linear scan put each value, None with the greedy ValueTable
+    pub locations: Option<HashMap<Value, Location>>,
+    // home slots of register values living across calls
+    pub homes: HashMap<Value, i32>,
+    // register values to reload after each call
+    pub reloads: HashMap<Value, Vec<Value>>,
+    // scratch registers taken by the current instruction, and the spills after it
+    pub scratch_used: usize,
+    pub pending_stores: Vec<(Reg, i32)>,
+    // registers saved by the prologue at the top of the frame: ra, s0, then s1 ~ s11
+    pub saved_regs: Vec<Reg>,
+    pub frame_pointer: bool,
 }
 
 impl<'a> FuncContext<'a> {
-    pub fn new(func_data: &'a FunctionData, prog: &'a Program) -> Self {
+    pub fn new(func_data: &'a FunctionData, prog: &'a Program, config: &AsmConfig) -> Self {
         let mut func_context = FuncContext {
-            func_data: func_data,
+            func_data,
             stack_size: 0,
-            current_offset: 0,
+            frame: Frame::default(),
             value_table: ValueTable::new(),
             has_call: false,
             max_param_num: 0,
+            scratch_offset: 0,
+            tail_calls: HashSet::new(),
+            tail_rets: HashSet::new(),
             program: prog,
+            locations: None,
+            homes: HashMap::new(),
+            reloads: HashMap::new(),
+            scratch_used: 0,
+            pending_stores: Vec::new(),
+            saved_regs: Vec::new(),
+            frame_pointer: config.frame_pointer,
         };
-        let stack_size = Self::get_stack_size(func_data, &mut func_context);
-        func_context.stack_size = stack_size;
-        // 初始化value_table为param
+        func_context.scan_calls();
+        if func_context.has_call {
+            func_context.saved_regs.push("ra");
+        }
+        if func_context.frame_pointer {
+            func_context.saved_regs.push("s0");
+        }
+        match config.regalloc.unwrap_or(RegAllocKind::Greedy) {
+            RegAllocKind::Greedy => func_context.greedy_frame(),
+            RegAllocKind::LinearScan => {
+                func_context.allocated_frame(linear_scan(func_data, &ALLOC_REGS))
+            }
+            RegAllocKind::GraphColoring => {
+                func_context.allocated_frame(graph_coloring(func_data, &ALLOC_REGS))
+            }
+        }
+        for (i, &reg) in func_context.saved_regs.clone().iter().enumerate() {
+            let offset = func_context.saved_reg_offset(i);
+            func_context
+                .frame
+                .place(FrameObjectKind::SavedReg(reg), offset, 4);
+        }
+        // 初始化全局变量
+        for &globl_var in prog.inst_layout() {
+            func_context.value_table.alloc_value(globl_var, GLOBL_ADDR);
+        }
+        func_context
+    }
+
+    fn greedy_frame(&mut self) {
+        let func_data = self.func_data;
+        let stack_size = Self::get_stack_size(func_data, self);
+        self.stack_size = stack_size;
+        // outgoing arguments are at the bottom of the frame
+        self.outgoing_args();
+        // 初始化value_table为param, 寄存器参数也有自己的栈槽, 溢出时写回
         for (i, param) in func_data.params().iter().enumerate() {
             let value = *param;
             let value_data = func_data.dfg().value(value);
             if i < 8 {
-                func_context.value_table.alloc_value(value, PARAM_ADDR);
-                func_context
-                    .value_table
+                let offset = self.frame.alloc(FrameObjectKind::Spill, 4);
+                self.value_table.alloc_value(value, offset);
+                self.value_table
                     .set_value_to_reg(&value, value_data, &REG_LIST[i]);
-                func_context.value_table.unlock_reg(&REG_LIST[i]);
+                self.value_table.unlock_reg(&REG_LIST[i]);
             } else {
-                func_context
-                    .value_table
+                self.value_table
                     .alloc_value(value, (stack_size + (i - 8) * 4) as i32);
             }
         }
-        // 初始化全局变量
-        for &globl_var in prog.inst_layout() {
-            func_context.value_table.alloc_value(globl_var, GLOBL_ADDR);
+        // every value and block parameter gets its slot up front
+        for (&bb, node) in func_data.layout().bbs() {
+            for &param in func_data.dfg().bb(bb).params() {
+                let offset = self.frame.alloc(FrameObjectKind::Spill, 4);
+                self.value_table.alloc_value(param, offset);
+            }
+            for &inst in node.insts().keys() {
+                let size = Self::get_value_stack_size(func_data, inst);
+                if size > 0 {
+                    let kind = match func_data.dfg().value(inst).kind() {
+                        ValueKind::Alloc(_) => FrameObjectKind::Alloc(inst),
+                        _ => FrameObjectKind::Spill,
+                    };
+                    let offset = self.frame.alloc(kind, size);
+                    self.value_table.alloc_value(inst, offset);
+                }
+            }
+        }
+        self.scratch_offset = self.frame.alloc(
+            FrameObjectKind::BlockArgs,
+            4 * Self::max_block_args(func_data),
+        );
+    }
+
+    /*
+        Frame of the linear scan and graph coloring allocators, from sp up:
+            outgoing arguments | allocs | spill and home slots | saved registers
+        Spilled stack parameters stay in the caller's outgoing area, the
+        values coalesced with them get a slot of their own.
+    */
+    fn allocated_frame(&mut self, allocation: Allocation) {
+        let func_data = self.func_data;
+        self.outgoing_args();
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let inst_data = func_data.dfg().value(inst);
+                if let ValueKind::Alloc(_) = inst_data.kind() {
+                    let offset = self
+                        .frame
+                        .alloc(FrameObjectKind::Alloc(inst), get_alloc_size(inst_data));
+                    self.value_table.alloc_value(inst, offset);
+                }
+            }
+        }
+        let mut locations: HashMap<Value, Location> = allocation
+            .regs
+            .iter()
+            .map(|(&value, &reg)| (value, Location::Reg(reg)))
+            .collect();
+        let stack_param_index = |value: Value| match func_data.dfg().value(value).kind() {
+            ValueKind::FuncArgRef(arg) if arg.index() >= 8 => Some(arg.index()),
+            _ => None,
+        };
+        for group in allocation.spilled.iter() {
+            let mut values = group
+                .iter()
+                .filter(|&&value| stack_param_index(value).is_none());
+            if let Some(&first) = values.next() {
+                let offset = self.frame.alloc(FrameObjectKind::Spill, 4);
+                locations.insert(first, Location::Stack(offset));
+                for &value in values {
+                    locations.insert(value, Location::Stack(offset));
+                }
+            }
+        }
+        for &value in allocation.saved.iter() {
+            let offset = self.frame.alloc(FrameObjectKind::Home(value), 4);
+            self.homes.insert(value, offset);
+        }
+        self.saved_regs.extend(allocation.callee_saved());
+        self.stack_size = (self.frame.used + 4 * self.saved_regs.len()).div_ceil(16) * 16;
+        for &value in allocation.spilled.iter().flatten() {
+            if let Some(i) = stack_param_index(value) {
+                let offset = (self.stack_size + (i - 8) * 4) as i32;
+                locations.insert(value, Location::Stack(offset));
+            }
+        }
+        self.locations = Some(locations);
+        self.reloads = allocation.live_across;
+    }
+
+    /*
+        A call directly followed by `ret` of its result can jump to the callee
+        with the frame popped, if:
+            - no argument points into the frame
+            - stack arguments fit in our own incoming argument area, and none
+              of the arguments is read from that area
+    */
+    fn find_tail_calls(&mut self) {
+        let func_data = self.func_data;
+        let own_params = func_data.params();
+        // parameters passed on our stack
+        let own_stack_params = &own_params[min(own_params.len(), 8)..];
+        for (_, node) in func_data.layout().bbs() {
+            let ret = match node.insts().back_key() {
+                Some(&ret) => ret,
+                None => continue,
+            };
+            let call = match node.insts().cursor(ret).prev_key() {
+                Some(&call) => call,
+                None => continue,
+            };
+            let args = match func_data.dfg().value(call).kind() {
+                ValueKind::Call(call) => call.args(),
+                _ => continue,
+            };
+            let returns_call = match func_data.dfg().value(ret).kind() {
+                ValueKind::Return(ret) => match ret.value() {
+                    Some(value) => value == call,
+                    None => func_data.dfg().value(call).used_by().is_empty(),
+                },
+                _ => false,
+            };
+            let stack_args_fit = args.len() <= 8
+                || (args.len() <= own_params.len()
+                    && args.iter().all(|arg| !own_stack_params.contains(arg)));
+            if returns_call
+                && stack_args_fit
+                && args.iter().all(|&arg| !points_into_frame(func_data, arg))
+            {
+                self.tail_calls.insert(call);
+                self.tail_rets.insert(ret);
+            }
+        }
+    }
+
+    // whether the function calls, and the most parameters of a callee
+    fn scan_calls(&mut self) {
+        for (_, node) in self.func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if let ValueKind::Call(call) = self.func_data.dfg().value(inst).kind() {
+                    self.has_call = true;
+                    let param_num = self.program.func(call.callee()).params().len() as i32;
+                    self.max_param_num = max(self.max_param_num, param_num);
+                }
+            }
+        }
+    }
+
+    // ---- prologue / epilogue ----
+    fn saved_reg_offset(&self, i: usize) -> i32 {
+        (self.stack_size - 4 * (i + 1)) as i32
+    }
+
+    // save ra and the callee-saved registers, s0 then points to the caller's sp
+    fn save_regs_to_asm(&self, asm: &mut MachineFunc) {
+        for (i, &reg) in self.saved_regs.iter().enumerate() {
+            asm.sw(reg, "sp", self.saved_reg_offset(i));
+        }
+        if self.frame_pointer {
+            asm.bin_imm(BinOp::Add, "s0", "sp", self.stack_size as i32);
+        }
+    }
+
+    // restore the saved registers and pop the frame, before `ret` or `tail`
+    pub fn epilogue_to_asm(&self, asm: &mut MachineFunc) {
+        for (i, &reg) in self.saved_regs.iter().enumerate() {
+            asm.lw(reg, "sp", self.saved_reg_offset(i));
+        }
+        asm.bin_imm(BinOp::Add, "sp", "sp", self.stack_size as i32);
+    }
+
+    fn outgoing_args(&mut self) {
+        let size = max(self.max_param_num - 8, 0) as usize * 4;
+        if size > 0 {
+            self.frame.alloc(FrameObjectKind::OutgoingArgs, size);
         }
-        func_context
     }
 
     pub fn get_value_data(&self, value: Value) -> &ValueData {
@@ -59,36 +288,47 @@ impl<'a> FuncContext<'a> {
     }
 
     fn get_stack_size(func_data: &FunctionData, func_context: &mut FuncContext) -> usize {
-        let mut stack_size = 0;
-        for (_, node) in func_data.layout().bbs() {
+        let mut stack_size = 4 * min(func_data.params().len(), 8);
+        for (&bb, node) in func_data.layout().bbs() {
+            stack_size += 4 * func_data.dfg().bb(bb).params().len();
             for &inst in node.insts().keys() {
                 stack_size += Self::get_value_stack_size(func_data, inst);
-                let valuedata = func_data.dfg().value(inst);
-                if let ValueKind::Call(call) = valuedata.kind() {
-                    func_context.has_call = true;
-                    let callee = call.callee();
-                    func_context.max_param_num = max(
-                        func_context.max_param_num,
-                        func_context.program.func(callee).params().len() as i32,
-                    );
-                }
             }
         }
-        if func_context.has_call {
-            stack_size += 4;
-        }
+        stack_size += 4 * Self::max_block_args(func_data);
+        stack_size += 4 * func_context.saved_regs.len();
         stack_size += (max(func_context.max_param_num - 8, 0) * 4) as usize;
         // align to 16
-        stack_size = (stack_size + 15) / 16 * 16;
-        return stack_size;
+        stack_size = stack_size.div_ceil(16) * 16;
+        stack_size
+    }
+
+    // the most arguments passed along one edge
+    fn max_block_args(func_data: &FunctionData) -> usize {
+        let mut max_block_args = 0;
+        for (_, node) in func_data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                match func_data.dfg().value(inst).kind() {
+                    ValueKind::Jump(jump) => max_block_args = max(max_block_args, jump.args().len()),
+                    ValueKind::Branch(branch) => {
+                        max_block_args = max(
+                            max_block_args,
+                            max(branch.true_args().len(), branch.false_args().len()),
+                        )
+                    }
+                    _ => {}
+                }
+            }
+        }
+        max_block_args
     }
 
     pub fn get_value_stack_size(func_data: &FunctionData, value: Value) -> usize {
         let valuedata = func_data.dfg().value(value);
-        if let ValueKind::Alloc(_) = valuedata.kind() {
-            get_alloc_size(valuedata)
-        } else {
-            valuedata.ty().size()
+        match valuedata.kind() {
+            ValueKind::Alloc(_) => get_alloc_size(valuedata),
+            ValueKind::Integer(_) => 0,
+            _ => valuedata.ty().size(),
         }
     }
 
@@ -109,38 +349,62 @@ pub fn get_alloc_size(value_data: &ValueData) -> usize {
 
 // ================= FunctionData to Asm =======================
 
+pub fn frame_sizes(prog: &Program, config: &AsmConfig) -> Vec<(String, usize)> {
+    prog.func_layout()
+        .iter()
+        .map(|&func| prog.func(func))
+        .filter(|func_data| func_data.layout().entry_bb().is_some())
+        .map(|func_data| {
+            let func_context = FuncContext::new(func_data, prog, config);
+            (func_data.name()[1..].to_string(), func_context.stack_size)
+        })
+        .collect()
+}
+
 impl GenerateAsm for FunctionData {
-    fn to_asm(&self, prog: &Program) -> Asm {
-        if self.layout().bbs().len() == 0 {
+    fn to_asm(&self, prog: &Program, config: &AsmConfig) -> Asm {
+        if self.layout().bbs().is_empty() {
             return Asm::new();
         }
-        let mut asm = String::new();
+        let func_name = &self.name()[1..];
+        let mut asm = MachineFunc::new(func_name);
 
-        let mut func_context = FuncContext::new(self, prog);
+        let mut func_context = FuncContext::new(self, prog, config);
+        if config.tail_calls == Some(true) {
+            func_context.find_tail_calls();
+        }
         // ------------- prologue --------------
+        if config.stack_check && func_name == "main" {
+            runtime::stack_limit_init_to_asm(&mut asm);
+        }
         // update sp
-        asm.push_str(&format!("{}:\n", &self.name()[1..]));
-        riscv_bin_op_imm(
-            "add",
-            "sp",
-            "sp",
-            -(func_context.stack_size as i32),
-            &mut asm,
-        );
-        if func_context.has_call {
-            riscv_sw("ra", "sp", func_context.stack_size as i32 - 4, &mut asm);
+        asm.bin_imm(BinOp::Add, "sp", "sp", -(func_context.stack_size as i32));
+        if config.stack_check {
+            runtime::stack_check_to_asm(func_name, &mut asm);
+        }
+        func_context.save_regs_to_asm(&mut asm);
+        if func_context.locations.is_some() {
+            params_to_asm(&mut asm, &mut func_context);
         }
-        func_context.current_offset = max(func_context.max_param_num - 8, 0) as usize * 4;
         // body
+        let entry = self.layout().entry_bb().unwrap();
         for (&bb, node) in self.layout().bbs() {
             let bb_name = get_bb_name(self, bb);
-            if !bb_name.starts_with("entry") {
-                asm.push_str(&format!("\n{}:\n", bb_name));
+            if bb != entry {
+                asm.label(bb_name);
+                func_context.value_table.reset_regs();
+            }
+            // block parameters living across calls are stored to their home slots first
+            for &param in self.dfg().bb(bb).params() {
+                func_context.save_home(param, &mut asm);
             }
             for &inst in node.insts().keys() {
                 value_to_asm(inst, &mut asm, &mut func_context);
             }
         }
-        return asm;
+        asm.frame = std::mem::take(&mut func_context.frame);
+        asm.stack_size = func_context.stack_size;
+        asm.optimize();
+        asm.to_string()
     }
 }
@@ -1,15 +1,26 @@
+use super::runtime;
 use super::util::aggregate_to_asm;
+use super::AsmConfig;
 use super::GenerateAsm;
+use crate::ir::{INIT_TEMPLATE_PREFIX, MEMCPY_FUNC, MEMSET_FUNC};
 use koopa::ir::{Program, ValueKind};
 
-pub fn prog_to_asm(prog: &Program) -> String {
+pub fn prog_to_asm(prog: &Program, config: &AsmConfig) -> String {
     let mut result = String::new();
 
     result += "  .data\n";
+    if config.stack_check {
+        result += &runtime::stack_data_to_asm(config);
+    }
 
     for &globl_var in prog.inst_layout() {
         let globl_var_data = prog.borrow_value(globl_var);
         let globl_name = &globl_var_data.name().as_ref().unwrap()[1..];
+        // templates of local arrays are never written
+        let read_only = globl_name.starts_with(INIT_TEMPLATE_PREFIX);
+        if read_only {
+            result += "  .section .rodata\n";
+        }
         result += &format!("  .global {}\n{}:\n", globl_name, globl_name);
         match globl_var_data.kind() {
             ValueKind::GlobalAlloc(globl_alloc) => {
@@ -31,13 +42,32 @@ pub fn prog_to_asm(prog: &Program) -> String {
             }
             _ => unreachable!(),
         }
+        if read_only {
+            result += "  .data\n";
+        }
         result += "\n";
     }
 
     result += "  .text\n";
     result += "  .globl main\n";
     for &func in prog.func_layout() {
-        result = result + &prog.func(func).to_asm(prog);
+        result = result + &prog.func(func).to_asm(prog, config);
+    }
+    if config.stack_check {
+        result += "\n";
+        result += &runtime::stack_overflow_to_asm();
+    }
+    if runtime::needs_func(prog, MEMSET_FUNC) {
+        result += "\n";
+        result += &runtime::memset_to_asm();
+    }
+    if runtime::needs_func(prog, MEMCPY_FUNC) {
+        result += "\n";
+        result += &runtime::memcpy_to_asm();
+    }
+    if runtime::needs_trap(prog) {
+        result += "\n";
+        result += &runtime::trap_to_asm();
     }
-    return result;
+    result
 }
@@ -1,271 +1,257 @@
 use core::panic;
-use std::cmp::min;
 
-use super::gen_riscv::*;
+use super::machine::{BinOp, MachineFunc, UnaryOp};
+use super::regalloc::{move_to, parallel_moves, Location, ARG_REGS, SCRATCH_REGS};
 use super::util::*;
-use super::{Asm, REG_LIST};
-use crate::asm::build_func::get_alloc_size;
+use super::{Reg, REG_LIST};
 use crate::asm::build_func::FuncContext;
-use koopa::ir::{BinaryOp, TypeKind, Value, ValueKind};
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, TypeKind, Value, ValueKind};
 
-pub fn value_to_asm(value: Value, asm: &mut Asm, func_ctx: &mut FuncContext) {
+pub fn value_to_asm(value: Value, asm: &mut MachineFunc, func_ctx: &mut FuncContext) {
     let func_data = func_ctx.func_data;
     let value_data = func_data.dfg().value(value);
     match value_data.kind() {
         ValueKind::Integer(_) => {
             unreachable!()
         }
+        ValueKind::Return(_) if func_ctx.tail_rets.contains(&value) => {
+            // the tail call before it already left the function
+        }
         ValueKind::Return(ret) => {
             let ret_value = ret.value();
             // compile return value
             if let Some(ret_value) = ret_value {
-                let ret_value_data = get_value_data(func_data, ret_value);
-                func_ctx.value_table.assign_value_to_specified_reg(
-                    &ret_value,
-                    ret_value_data,
-                    &"a0",
-                    asm,
-                );
+                if func_ctx.locations.is_some() {
+                    func_ctx.load_to("a0", ret_value, asm);
+                } else {
+                    let ret_value_data = get_value_data(func_data, ret_value);
+                    func_ctx.value_table.assign_value_to_specified_reg(
+                        &ret_value,
+                        ret_value_data,
+                        &"a0",
+                        asm,
+                    );
+                }
             }
             // epilogue
             // return
-            if func_ctx.has_call {
-                riscv_lw("ra", "sp", func_ctx.stack_size as i32 - 4, asm);
-            }
-            riscv_bin_op_imm("add", "sp", "sp", func_ctx.stack_size as i32, asm);
+            func_ctx.epilogue_to_asm(asm);
             func_ctx.value_table.unlock_reg(&"a0");
-            asm.push_str("  ret\n");
+            asm.ret();
         }
         ValueKind::Alloc(_) => {
-            let size = get_alloc_size(value_data);
-            let offset = func_ctx.current_offset as i32;
-            func_ctx.value_table.alloc_value(value, offset);
-            func_ctx.current_offset += size;
+            // the slot is assigned in FuncContext::new
         }
         ValueKind::Load(load) => {
-            let size = value_data.ty().size();
-            let offset = func_ctx.current_offset as i32;
-            func_ctx.value_table.alloc_value(value, offset);
-            func_ctx.current_offset += size;
-
-            let src_value = load.src();
-            let (src_value_data, src_is_global) =
-                if func_ctx.func_data.dfg().values().contains_key(&src_value) {
-                    (func_ctx.func_data.dfg().value(src_value).clone(), false)
-                } else {
-                    let value_data = func_ctx.program.borrow_value(src_value);
-                    (value_data.clone(), true)
-                };
-            let load_reg = func_ctx.value_table.allocate_value_to_reg(&value, asm);
-
-            if src_is_global {
-                let globl_name = &src_value_data.name().as_ref().unwrap()[1..];
-                let src_ptr_reg = func_ctx.value_table.allocate_value_to_reg(&src_value, asm);
-                riscv_la(src_ptr_reg, globl_name, asm);
-                riscv_lw(load_reg, src_ptr_reg, 0, asm);
-                func_ctx.value_table.unlock_reg(&src_ptr_reg);
-            } else {
-                if let ValueKind::Alloc(_) = src_value_data.kind() {
-                    // indicate this value is in alloc list; we can determine the offset directly
-                    let addr = func_ctx.value_table.get_value_addr(&src_value);
-                    if let Some(offset) = addr {
-                        riscv_lw(load_reg, "sp", offset, asm);
-                    } else {
-                        panic!("value is not in stack");
-                    }
-                } else {
-                    // indicate this value is a pointer; we need to load the value from the stack
-                    let src_ptr_reg =
-                        func_ctx
-                            .value_table
-                            .assign_value_to_reg(&src_value, &src_value_data, asm);
-                    riscv_lw(load_reg, src_ptr_reg, 0, asm);
-                    func_ctx.value_table.unlock_reg(&src_ptr_reg);
-                }
+            let (base, offset) = func_ctx.ptr_operand(load.src(), asm);
+            let load_reg = func_ctx.def_reg(value, &[], asm);
+            asm.lw(load_reg, base, offset);
+            func_ctx.end_inst(asm);
+        }
+        ValueKind::Binary(bin) if imm_operand(func_data, value).is_some() => {
+            let (src_value, imm) = imm_operand(func_data, value).unwrap();
+            let src_reg = func_ctx.use_reg(src_value, asm);
+            let dest_reg = func_ctx.def_reg(value, &[src_reg], asm);
+            match bin.op() {
+                BinaryOp::Mul => asm.mul_imm(dest_reg, src_reg, imm),
+                BinaryOp::Div => asm.div_imm(dest_reg, src_reg, imm),
+                BinaryOp::Mod => asm.rem_imm(dest_reg, src_reg, imm),
+                _ => unreachable!(),
             }
-            func_ctx.value_table.unlock_reg(&load_reg);
+            func_ctx.end_inst(asm);
         }
         ValueKind::Binary(bin) => {
-            let size = value_data.ty().size();
-            let offset = func_ctx.current_offset as i32;
-            func_ctx.value_table.alloc_value(value, offset);
-            func_ctx.current_offset += size;
-
             let op = bin.op();
-            let lhs_value = bin.lhs();
-            let lhs_value_data = get_value_data(func_data, lhs_value);
-            let rhs_value = bin.rhs();
-            let rhs_value_data = get_value_data(func_data, rhs_value);
-            let lhs_reg = func_ctx
-                .value_table
-                .assign_value_to_reg(&lhs_value, lhs_value_data, asm);
-            let rhs_reg = func_ctx
-                .value_table
-                .assign_value_to_reg(&rhs_value, rhs_value_data, asm);
-            let dest_reg = func_ctx.value_table.allocate_value_to_reg(&value, asm);
+            let lhs_reg = func_ctx.use_reg(bin.lhs(), asm);
+            let rhs_reg = func_ctx.use_reg(bin.rhs(), asm);
+            let dest_reg = func_ctx.def_reg(value, &[], asm);
             match op {
                 BinaryOp::Add => {
-                    riscv_bin_op("add", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::Add, dest_reg, lhs_reg, rhs_reg);
                 }
                 BinaryOp::Sub => {
-                    riscv_bin_op("sub", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::Sub, dest_reg, lhs_reg, rhs_reg);
                 }
                 BinaryOp::Mul => {
-                    riscv_bin_op("mul", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::Mul, dest_reg, lhs_reg, rhs_reg);
                 }
                 BinaryOp::Div => {
-                    riscv_bin_op("div", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::Div, dest_reg, lhs_reg, rhs_reg);
                 }
                 BinaryOp::Mod => {
-                    riscv_bin_op("rem", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::Rem, dest_reg, lhs_reg, rhs_reg);
                 }
                 BinaryOp::And => {
                     // bitwise and
-                    riscv_bin_op("and", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::And, dest_reg, lhs_reg, rhs_reg);
                 }
                 BinaryOp::Or => {
                     // bitwise or
-                    riscv_bin_op("or", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::Or, dest_reg, lhs_reg, rhs_reg);
                 }
                 BinaryOp::Eq => {
-                    riscv_bin_op("xor", dest_reg, lhs_reg, rhs_reg, asm);
-                    riscv_unary_op("seqz", dest_reg, dest_reg, asm);
+                    asm.bin(BinOp::Xor, dest_reg, lhs_reg, rhs_reg);
+                    asm.unary(UnaryOp::Seqz, dest_reg, dest_reg);
                 }
                 BinaryOp::NotEq => {
-                    riscv_bin_op("xor", dest_reg, lhs_reg, rhs_reg, asm);
-                    riscv_unary_op("snez", dest_reg, dest_reg, asm);
+                    asm.bin(BinOp::Xor, dest_reg, lhs_reg, rhs_reg);
+                    asm.unary(UnaryOp::Snez, dest_reg, dest_reg);
                 }
                 BinaryOp::Gt => {
-                    riscv_bin_op("slt", dest_reg, rhs_reg, lhs_reg, asm);
+                    asm.bin(BinOp::Slt, dest_reg, rhs_reg, lhs_reg);
                 }
                 BinaryOp::Lt => {
-                    riscv_bin_op("slt", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::Slt, dest_reg, lhs_reg, rhs_reg);
                 }
                 BinaryOp::Ge => {
-                    riscv_bin_op("slt", dest_reg, lhs_reg, rhs_reg, asm);
-                    riscv_bin_op_imm("xor", dest_reg, dest_reg, 1, asm);
+                    asm.bin(BinOp::Slt, dest_reg, lhs_reg, rhs_reg);
+                    asm.bin_imm(BinOp::Xor, dest_reg, dest_reg, 1);
                 }
                 BinaryOp::Le => {
-                    riscv_bin_op("slt", dest_reg, rhs_reg, lhs_reg, asm);
-                    riscv_bin_op_imm("xor", dest_reg, dest_reg, 1, asm);
+                    asm.bin(BinOp::Slt, dest_reg, rhs_reg, lhs_reg);
+                    asm.bin_imm(BinOp::Xor, dest_reg, dest_reg, 1);
                 }
                 BinaryOp::Xor => {
-                    riscv_bin_op("xor", dest_reg, lhs_reg, rhs_reg, asm);
+                    asm.bin(BinOp::Xor, dest_reg, lhs_reg, rhs_reg);
+                }
+                BinaryOp::Shl => {
+                    asm.bin(BinOp::Sll, dest_reg, lhs_reg, rhs_reg);
+                }
+                BinaryOp::Shr => {
+                    asm.bin(BinOp::Srl, dest_reg, lhs_reg, rhs_reg);
+                }
+                BinaryOp::Sar => {
+                    asm.bin(BinOp::Sra, dest_reg, lhs_reg, rhs_reg);
                 }
-                _ => unreachable!(),
             };
-            func_ctx.value_table.unlock_reg(&lhs_reg);
-            func_ctx.value_table.unlock_reg(&rhs_reg);
-            func_ctx.value_table.unlock_reg(&dest_reg);
+            func_ctx.end_inst(asm);
         }
         ValueKind::Store(store) => {
-            let store_value = store.value();
-            let store_value_data = get_value_data(func_data, store_value);
-            let store_value_reg =
-                func_ctx
-                    .value_table
-                    .assign_value_to_reg(&store_value, store_value_data, asm);
-
-            let store_dest = store.dest();
-            let (store_dest_data, dest_is_global) = {
-                let func_data = func_ctx.func_data;
-                let program = func_ctx.program;
-                if func_data.dfg().values().contains_key(&store_dest) {
-                    (func_data.dfg().value(store_dest).clone(), false)
-                } else {
-                    (program.borrow_value(store_dest).clone(), true)
-                }
-            };
-
-            if dest_is_global {
-                let globl_name = &store_dest_data.name().as_ref().unwrap()[1..];
-                let dest_ptr_reg = func_ctx.value_table.allocate_value_to_reg(&store_dest, asm);
-                riscv_la(dest_ptr_reg, globl_name, asm);
-                riscv_sw(store_value_reg, dest_ptr_reg, 0, asm);
-                func_ctx.value_table.unlock_reg(&dest_ptr_reg);
-            } else {
-                if let ValueKind::Alloc(_) = store_dest_data.kind() {
-                    // indicate this value is in alloc list; we can determine the offset directly
-                    let addr = func_ctx.value_table.get_value_addr(&store_dest);
-                    if let Some(offset) = addr {
-                        riscv_sw(store_value_reg, "sp", offset, asm);
-                    } else {
-                        panic!("value is not in stack");
-                    }
-                } else {
-                    // indicate this value is a pointer; we need to load the value from the stack
-                    let dest_ptr_reg = func_ctx.value_table.assign_value_to_reg(
-                        &store_dest,
-                        &store_dest_data,
-                        asm,
-                    );
-                    riscv_sw(store_value_reg, dest_ptr_reg, 0, asm);
-                    func_ctx.value_table.unlock_reg(&dest_ptr_reg);
-                }
-            }
-            func_ctx.value_table.unlock_reg(&store_value_reg);
+            let store_value_reg = func_ctx.use_reg(store.value(), asm);
+            let (base, offset) = func_ctx.ptr_operand(store.dest(), asm);
+            asm.sw(store_value_reg, base, offset);
+            func_ctx.end_inst(asm);
         }
         ValueKind::Jump(jump) => {
             let jump_bb = jump.target();
             let jump_bb_name = get_bb_name(func_ctx.func_data, jump_bb);
-            func_ctx.value_table.free_regs(&REG_LIST.to_vec(), asm);
-            asm.push_str(&format!("  j {}\n", jump_bb_name));
+            if func_ctx.locations.is_none() {
+                func_ctx.value_table.free_regs(&REG_LIST.to_vec(), asm);
+            }
+            block_args_to_asm(jump_bb, jump.args(), asm, func_ctx);
+            asm.j(jump_bb_name);
         }
         ValueKind::Branch(branch) => {
-            let cond_value = branch.cond();
-            let cond_value_data = get_value_data(func_data, cond_value);
-            let cond_reg =
-                func_ctx
-                    .value_table
-                    .assign_value_to_reg(&cond_value, cond_value_data, asm);
+            let cond_reg = func_ctx.use_reg(branch.cond(), asm);
             let true_bb = branch.true_bb();
             let false_bb = branch.false_bb();
             let true_bb_name = get_bb_name(func_ctx.func_data, true_bb);
             let false_bb_name = get_bb_name(func_ctx.func_data, false_bb);
-            func_ctx.value_table.unlock_reg(&cond_reg);
-            func_ctx.value_table.free_regs(
-                &REG_LIST
-                    .iter()
-                    .filter(|&&r| r != cond_reg)
-                    .cloned()
-                    .collect::<Vec<_>>(),
-                asm,
-            );
-            func_ctx.value_table.free_reg(&cond_reg, asm);
-            asm.push_str(&format!("  bnez {}, {}\n", cond_reg, true_bb_name));
-            asm.push_str(&format!("  j {}\n", false_bb_name));
+            if func_ctx.locations.is_some() {
+                func_ctx.end_inst(asm);
+            } else {
+                func_ctx.value_table.unlock_reg(&cond_reg);
+                func_ctx.value_table.free_regs(
+                    &REG_LIST
+                        .iter()
+                        .filter(|&&r| r != cond_reg)
+                        .cloned()
+                        .collect::<Vec<_>>(),
+                    asm,
+                );
+                func_ctx.value_table.free_reg(&cond_reg, asm);
+            }
+            if branch.true_args().is_empty() && branch.false_args().is_empty() {
+                asm.bnez(cond_reg, true_bb_name);
+                asm.j(false_bb_name);
+            } else {
+                // each edge passes its own arguments, the true edge through a new label
+                let current_bb = func_data.layout().parent_bb(value).unwrap();
+                let edge_name = format!(
+                    "{}_to_{}",
+                    get_bb_name(func_data, current_bb),
+                    true_bb_name
+                );
+                asm.bnez(cond_reg, &edge_name);
+                block_args_to_asm(false_bb, branch.false_args(), asm, func_ctx);
+                asm.j(false_bb_name);
+                asm.label(&edge_name);
+                block_args_to_asm(true_bb, branch.true_args(), asm, func_ctx);
+                asm.j(true_bb_name);
+            }
             func_ctx.value_table.unlock_reg(&cond_reg);
         }
         ValueKind::Call(call) => {
-            let size = value_data.ty().size();
-            let offset = func_ctx.current_offset as i32;
-            func_ctx.value_table.alloc_value(value, offset);
-            func_ctx.current_offset += size;
-
             let callee = call.callee();
             let args = call.args();
-            let reg_to_be_freed: Vec<&str> = REG_LIST[min(args.len(), 8)..].to_vec();
-            for (i, arg_value) in args.iter().enumerate() {
-                let arg_value_data = get_value_data(func_data, *arg_value);
-                if i < 8 {
-                    func_ctx.value_table.assign_value_to_specified_reg(
+            let linear = func_ctx.locations.is_some();
+            if !linear {
+                // caller-saved registers do not survive the call, spill everything first
+                func_ctx.value_table.free_regs(&REG_LIST.to_vec(), asm);
+            }
+            let is_tail = func_ctx.tail_calls.contains(&value);
+            // a tail call passes stack arguments in our own incoming area
+            let stack_args_base = if is_tail {
+                func_ctx.stack_size as i32
+            } else {
+                0
+            };
+            for (i, arg_value) in args.iter().enumerate().skip(8) {
+                let offset = stack_args_base + (i - 8) as i32 * 4;
+                if linear {
+                    let arg_reg = func_ctx.use_reg(*arg_value, asm);
+                    asm.sw(arg_reg, "sp", offset);
+                    func_ctx.end_inst(asm);
+                } else {
+                    let arg_value_data = get_value_data(func_data, *arg_value);
+                    func_ctx.value_table.load_value_to_unbound_reg(
+                        arg_value,
+                        arg_value_data,
+                        &"t0",
+                        asm,
+                    );
+                    asm.sw("t0", "sp", offset);
+                }
+            }
+            if linear {
+                let moves: Vec<(Location, Value)> = args
+                    .iter()
+                    .take(8)
+                    .enumerate()
+                    .map(|(i, &arg)| (Location::Reg(ARG_REGS[i]), arg))
+                    .collect();
+                func_ctx.moves_to_asm(&moves, asm);
+            } else {
+                for (i, arg_value) in args.iter().enumerate().take(8) {
+                    let arg_value_data = get_value_data(func_data, *arg_value);
+                    func_ctx.value_table.load_value_to_unbound_reg(
                         arg_value,
                         arg_value_data,
                         &REG_LIST[i],
                         asm,
                     );
-                } else {
-                    let arg_reg =
-                        func_ctx
-                            .value_table
-                            .assign_value_to_reg(arg_value, arg_value_data, asm);
-                    riscv_sw(arg_reg, "sp", (i - 8) as i32 * 4, asm);
-                    func_ctx.value_table.unlock_reg(&arg_reg);
                 }
             }
-            func_ctx.value_table.free_regs(&reg_to_be_freed, asm);
             let callee_data = func_ctx.program.func(callee);
-            asm.push_str(&format!("  call {}\n", &callee_data.name()[1..]));
+            if is_tail {
+                func_ctx.epilogue_to_asm(asm);
+                asm.tail(&callee_data.name()[1..]);
+                return;
+            }
+            asm.call(&callee_data.name()[1..]);
+            if linear {
+                // return value now in a0, then reload what the call clobbered
+                if let Some(&dest) = func_ctx.location(value) {
+                    move_to(dest, Location::Reg("a0"), asm);
+                }
+                func_ctx.save_home(value, asm);
+                for saved in func_ctx.reloads.get(&value).cloned().unwrap_or_default() {
+                    if let Some(&Location::Reg(reg)) = func_ctx.location(saved) {
+                        asm.lw(reg, "sp", func_ctx.homes[&saved]);
+                    }
+                }
+                return;
+            }
             if let TypeKind::Function(_, ret_type) = callee_data.ty().kind() {
                 if !ret_type.is_unit() {
                     // return value now in a0
@@ -274,110 +260,304 @@ pub fn value_to_asm(value: Value, asm: &mut Asm, func_ctx: &mut FuncContext) {
                         .set_value_to_reg(&value, value_data, &"a0");
                 }
             }
-            for i in 0..args.len() {
-                if i < 8 {
-                    let arg_reg = REG_LIST[i];
-                    func_ctx.value_table.unlock_reg(&arg_reg);
-                }
-            }
             func_ctx.value_table.unlock_reg(&"a0");
         }
 
         ValueKind::GetElemPtr(get_elem_ptr) => {
-            let size = value_data.ty().size();
-            let offset = func_ctx.current_offset as i32;
-            func_ctx.value_table.alloc_value(value, offset);
-            func_ctx.current_offset += size;
-
-            let index_value = get_elem_ptr.index();
-            let index_value_data = get_value_data(func_data, index_value);
-
             let src_value = get_elem_ptr.src();
-            let (src_value_data, src_is_global) =
-                if func_ctx.func_data.dfg().values().contains_key(&src_value) {
-                    (func_ctx.func_data.dfg().value(src_value).clone(), false)
-                } else {
-                    let value_data = func_ctx.program.borrow_value(src_value);
-                    (value_data.clone(), true)
-                };
+            let step = get_elem_ptr_step(&func_ctx.value_data_of(src_value)) as i32;
+            address_to_asm(value, src_value, get_elem_ptr.index(), step, asm, func_ctx);
+        }
+        ValueKind::GetPtr(get_ptr) => {
+            let src_value = get_ptr.src(); // must be local
+            let step = get_ptr_step(func_ctx.func_data.dfg().value(src_value)) as i32;
+            address_to_asm(value, src_value, get_ptr.index(), step, asm, func_ctx);
+        }
+        _ => {
+            panic!("unsupported value kind: {:?}", value_data.kind());
+        }
+    }
+    assert!(func_ctx.value_table.reg_all_unlocked());
+}
 
-            let index_reg =
-                func_ctx
-                    .value_table
-                    .assign_value_to_reg(&index_value, index_value_data, asm);
+// dest = src + index * step, a constant index is folded into the offset
+fn address_to_asm(
+    value: Value,
+    src_value: Value,
+    index_value: Value,
+    step: i32,
+    asm: &mut MachineFunc,
+    func_ctx: &mut FuncContext,
+) {
+    let const_index = match func_ctx.value_data_of(index_value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    };
+    if let Some(index) = const_index {
+        let (base, offset) = func_ctx.ptr_operand(src_value, asm);
+        let dest_reg = func_ctx.def_reg(value, &[base], asm);
+        asm.bin_imm(
+            BinOp::Add,
+            dest_reg,
+            base,
+            offset.wrapping_add(index.wrapping_mul(step)),
+        );
+    } else {
+        let index_reg = func_ctx.use_reg(index_value, asm);
+        let (base, offset) = func_ctx.ptr_operand(src_value, asm);
+        let dest_reg = func_ctx.def_reg(value, &[base], asm);
+        asm.mul_imm(dest_reg, index_reg, step);
+        asm.bin(BinOp::Add, dest_reg, dest_reg, base);
+        if offset != 0 {
+            asm.bin_imm(BinOp::Add, dest_reg, dest_reg, offset);
+        }
+    }
+    func_ctx.end_inst(asm);
+}
 
-            let dest_reg = func_ctx.value_table.allocate_value_to_reg(&value, asm);
+// (operand, constant) of a mul/div/mod by a constant, expanded by const_ops
+fn imm_operand(func_data: &FunctionData, value: Value) -> Option<(Value, i32)> {
+    let as_imm = |operand: Value| match func_data.dfg().value(operand).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    };
+    match func_data.dfg().value(value).kind() {
+        ValueKind::Binary(bin) => match (bin.op(), as_imm(bin.lhs()), as_imm(bin.rhs())) {
+            (BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod, None, Some(imm)) => {
+                Some((bin.lhs(), imm))
+            }
+            (BinaryOp::Mul, Some(imm), None) => Some((bin.rhs(), imm)),
+            _ => None,
+        },
+        _ => None,
+    }
+}
 
-            let step = get_elem_ptr_step(&src_value_data) as i32;
+// copy jump/branch arguments into the parameters of `target`
+fn block_args_to_asm(target: BasicBlock, args: &[Value], asm: &mut MachineFunc, func_ctx: &mut FuncContext) {
+    let func_data = func_ctx.func_data;
+    let params = func_data.dfg().bb(target).params();
+    if func_ctx.locations.is_some() {
+        let moves: Vec<(Location, Value)> = args
+            .iter()
+            .zip(params)
+            .filter_map(|(&arg, param)| func_ctx.location(*param).map(|&dest| (dest, arg)))
+            .collect();
+        func_ctx.moves_to_asm(&moves, asm);
+        return;
+    }
+    let moves: Vec<(Value, Value)> = args
+        .iter()
+        .zip(params)
+        .filter(|(arg, param)| arg != param)
+        .map(|(&arg, &param)| (arg, param))
+        .collect();
+    // an argument may be a parameter overwritten by an earlier move, go through the scratch area then
+    let overlap = moves.iter().any(|(arg, _)| params.contains(arg));
+    for (i, (arg, param)) in moves.iter().enumerate() {
+        let arg_data = get_value_data(func_data, *arg);
+        func_ctx
+            .value_table
+            .load_value_to_unbound_reg(arg, arg_data, &"t0", asm);
+        let dest = if overlap {
+            func_ctx.scratch_offset + i as i32 * 4
+        } else {
+            func_ctx.value_table.get_value_addr(param).unwrap()
+        };
+        asm.sw("t0", "sp", dest);
+    }
+    if overlap {
+        for (i, (_, param)) in moves.iter().enumerate() {
+            asm.lw("t0", "sp", func_ctx.scratch_offset + i as i32 * 4);
+            let dest = func_ctx.value_table.get_value_addr(param).unwrap();
+            asm.sw("t0", "sp", dest);
+        }
+    }
+}
 
-            if src_is_global {
-                let globl_name = &src_value_data.name().as_ref().unwrap()[1..];
-                let src_ptr_reg = func_ctx.value_table.allocate_value_to_reg(&src_value, asm);
-                riscv_la(src_ptr_reg, globl_name, asm);
-                asm.push_str(&format!("  li {}, {}\n", dest_reg, step));
-                riscv_bin_op("mul", dest_reg, index_reg, dest_reg, asm);
-                riscv_bin_op("add", dest_reg, dest_reg, src_ptr_reg, asm);
-                func_ctx.value_table.unlock_reg(&src_ptr_reg);
+// linear scan: move the parameters from where the caller passed them to their locations
+pub fn params_to_asm(asm: &mut MachineFunc, func_ctx: &mut FuncContext) {
+    let params = func_ctx.func_data.params();
+    let stack_size = func_ctx.stack_size as i32;
+    let moves: Vec<(Location, Location)> = params
+        .iter()
+        .enumerate()
+        .filter_map(|(i, param)| {
+            let src = if i < 8 {
+                Location::Reg(ARG_REGS[i])
             } else {
-                if let ValueKind::Alloc(_) = src_value_data.kind() {
-                    // indicate this value is in alloc list; we can determine the offset directly
-                    let addr = func_ctx.value_table.get_value_addr(&src_value);
-                    if let Some(src_value_offset) = addr {
-                        asm.push_str(&format!("  li {}, {}\n", dest_reg, step));
-                        riscv_bin_op("mul", dest_reg, index_reg, dest_reg, asm);
-                        riscv_bin_op("add", dest_reg, dest_reg, "sp", asm);
-                        riscv_bin_op_imm("add", dest_reg, dest_reg, src_value_offset, asm);
-                    } else {
-                        panic!("value is not in stack");
-                    }
-                } else {
-                    // indicate this value is a pointer; we need to load the value from the stack
-                    let src_ptr_reg =
-                        func_ctx
-                            .value_table
-                            .assign_value_to_reg(&src_value, &src_value_data, asm);
-                    asm.push_str(&format!("  li {}, {}\n", dest_reg, step));
-                    riscv_bin_op("mul", dest_reg, index_reg, dest_reg, asm);
-                    riscv_bin_op("add", dest_reg, dest_reg, src_ptr_reg, asm);
-                    func_ctx.value_table.unlock_reg(&src_ptr_reg);
+                Location::Stack(stack_size + (i - 8) as i32 * 4)
+            };
+            func_ctx.location(*param).map(|&dest| (dest, src))
+        })
+        .collect();
+    parallel_moves(&moves, asm);
+    for &param in params {
+        func_ctx.save_home(param, asm);
+    }
+}
+
+// ============ Operands ============
+/*
+    Instructions get their registers through these, whatever the allocator:
+    `use_reg` gives a register holding an operand, `def_reg` the register
+    receiving the result, and `end_inst` finishes the instruction. The greedy
+    ValueTable binds and locks registers on demand; with the linear scan,
+    spilled operands are loaded into t4/t5 and spilled results stored back
+    after the instruction. Values living across calls are also stored to their
+    home slots once defined.
+*/
+
+impl FuncContext<'_> {
+    pub fn location(&self, value: Value) -> Option<&Location> {
+        self.locations.as_ref().unwrap().get(&value)
+    }
+
+    // value data of a local value or a global
+    fn value_data_of(&self, value: Value) -> koopa::ir::entities::ValueData {
+        if value.is_global() {
+            self.program.borrow_value(value).clone()
+        } else {
+            self.func_data.dfg().value(value).clone()
+        }
+    }
+
+    fn next_scratch(&mut self) -> Reg {
+        let reg = SCRATCH_REGS[self.scratch_used];
+        self.scratch_used += 1;
+        reg
+    }
+
+    pub fn use_reg(&mut self, value: Value, asm: &mut MachineFunc) -> Reg {
+        if self.locations.is_none() {
+            if value.is_global() {
+                let name = self.value_data_of(value).name().clone().unwrap();
+                let reg = self.value_table.allocate_value_to_reg(&value, asm);
+                asm.la(reg, &name[1..]);
+                return reg;
+            }
+            let value_data = self.func_data.dfg().value(value);
+            return self
+                .value_table
+                .assign_value_to_reg(&value, value_data, asm);
+        }
+        if !value.is_global() {
+            if let ValueKind::Integer(int) = self.func_data.dfg().value(value).kind() {
+                if int.value() == 0 {
+                    return "x0";
                 }
             }
+            if let Some(&Location::Reg(reg)) = self.location(value) {
+                return reg;
+            }
+        }
+        let reg = self.next_scratch();
+        self.load_to(reg, value, asm);
+        reg
+    }
 
-            func_ctx.value_table.unlock_reg(&index_reg);
-            func_ctx.value_table.unlock_reg(&dest_reg);
+    // `avoid`: operands still read after the result is written
+    pub fn def_reg(&mut self, value: Value, avoid: &[Reg], asm: &mut MachineFunc) -> Reg {
+        if self.locations.is_none() {
+            return self.value_table.allocate_value_to_reg(&value, asm);
         }
-        ValueKind::GetPtr(get_ptr) => {
-            let size = value_data.ty().size();
-            let offset = func_ctx.current_offset as i32;
-            func_ctx.value_table.alloc_value(value, offset);
-            func_ctx.current_offset += size;
+        let scratch = SCRATCH_REGS
+            .iter()
+            .copied()
+            .find(|reg| !avoid.contains(reg))
+            .unwrap();
+        match self.location(value).copied() {
+            Some(Location::Reg(reg)) => {
+                if let Some(&home) = self.homes.get(&value) {
+                    self.pending_stores.push((reg, home));
+                }
+                reg
+            }
+            Some(Location::Stack(offset)) => {
+                self.pending_stores.push((scratch, offset));
+                scratch
+            }
+            // the result is unused
+            None => scratch,
+        }
+    }
 
-            let index_value = get_ptr.index();
-            let index_value_data = get_value_data(func_data, index_value);
+    pub fn end_inst(&mut self, asm: &mut MachineFunc) {
+        if self.locations.is_none() {
+            for reg in REG_LIST.iter() {
+                self.value_table.unlock_reg(reg);
+            }
+            return;
+        }
+        for (reg, offset) in std::mem::take(&mut self.pending_stores) {
+            asm.sw(reg, "sp", offset);
+        }
+        self.scratch_used = 0;
+    }
 
-            let src_value = get_ptr.src(); // must be local
-            let src_value_data = func_ctx.func_data.dfg().value(src_value);
-            let index_reg =
-                func_ctx
-                    .value_table
-                    .assign_value_to_reg(&index_value, index_value_data, asm);
-            let dest_reg = func_ctx.value_table.allocate_value_to_reg(&value, asm);
-            let step = get_ptr_step(&src_value_data) as i32;
+    // base register and offset of a pointer operand
+    fn ptr_operand(&mut self, ptr: Value, asm: &mut MachineFunc) -> (Reg, i32) {
+        if !ptr.is_global() {
+            if let ValueKind::Alloc(_) = self.func_data.dfg().value(ptr).kind() {
+                let offset = self.value_table.get_value_addr(&ptr);
+                return ("sp", offset.expect("value is not in stack"));
+            }
+        }
+        (self.use_reg(ptr, asm), 0)
+    }
 
-            let src_reg = func_ctx
-                .value_table
-                .assign_value_to_reg(&src_value, src_value_data, asm);
-            asm.push_str(&format!("  li {}, {}\n", dest_reg, step));
-            riscv_bin_op("mul", dest_reg, index_reg, dest_reg, asm);
-            riscv_bin_op("add", dest_reg, dest_reg, src_reg, asm);
+    // linear scan: load an operand into `reg`
+    pub fn load_to(&mut self, reg: Reg, value: Value, asm: &mut MachineFunc) {
+        if value.is_global() {
+            let name = self.value_data_of(value).name().clone().unwrap();
+            asm.la(reg, &name[1..]);
+            return;
+        }
+        match self.func_data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => asm.li(reg, int.value()),
+            ValueKind::Alloc(_) => {
+                let offset = self.value_table.get_value_addr(&value).unwrap();
+                asm.bin_imm(BinOp::Add, reg, "sp", offset);
+            }
+            _ => match self.location(value) {
+                Some(&src) => move_to(Location::Reg(reg), src, asm),
+                None => panic!("value has no location"),
+            },
+        }
+    }
 
-            func_ctx.value_table.unlock_reg(&src_reg);
-            func_ctx.value_table.unlock_reg(&index_reg);
-            func_ctx.value_table.unlock_reg(&dest_reg);
+    // linear scan: copy values to locations all at once
+    fn moves_to_asm(&mut self, moves: &[(Location, Value)], asm: &mut MachineFunc) {
+        let mut copies = Vec::new();
+        let mut consts = Vec::new();
+        for &(dest, value) in moves {
+            match self.location(value) {
+                Some(&src) => copies.push((dest, src)),
+                _ => consts.push((dest, value)),
+            }
         }
-        _ => {
-            panic!("unsupported value kind: {:?}", value_data.kind());
+        parallel_moves(&copies, asm);
+        // constants and the like have no location, they are written last
+        for (dest, value) in consts {
+            match dest {
+                Location::Reg(reg) => self.load_to(reg, value, asm),
+                Location::Stack(offset) => {
+                    self.load_to(SCRATCH_REGS[0], value, asm);
+                    asm.sw(SCRATCH_REGS[0], "sp", offset);
+                }
+            }
+        }
+    }
+
+    // linear scan: store a register value living across calls to its home slot
+    pub fn save_home(&mut self, value: Value, asm: &mut MachineFunc) {
+        if self.locations.is_none() {
+            return;
+        }
+        if let (Some(&home), Some(&Location::Reg(reg))) =
+            (self.homes.get(&value), self.location(value))
+        {
+            asm.sw(reg, "sp", home);
         }
     }
-    assert!(func_ctx.value_table.reg_all_unlocked());
 }
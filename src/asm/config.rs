@@ -0,0 +1,95 @@
+// ============ Backend Options ============
+
+// default stack size checked by -fstack-check, same as the usual 8 MiB ulimit
+pub const DEFAULT_STACK_SIZE: usize = 8 << 20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegAllocKind {
+    // values stay in registers within a block, everything is spilled at jumps and calls
+    Greedy,
+    // linear scan over live intervals, only spilled values get a stack slot
+    LinearScan,
+    // iterated register coalescing over an interference graph
+    GraphColoring,
+}
+
+impl RegAllocKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "greedy" => Some(RegAllocKind::Greedy),
+            "linear-scan" => Some(RegAllocKind::LinearScan),
+            "graph" => Some(RegAllocKind::GraphColoring),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct AsmConfig {
+    // check sp against __sysy_stack_limit in every prologue
+    pub stack_check: bool,
+    // default value of __sysy_stack_size, can be overridden at link time
+    pub stack_size: usize,
+    // emit `tail` for calls in tail position, None: enabled from -O1
+    pub tail_calls: Option<bool>,
+    // register allocator, -fregalloc=<kind>, None: graph coloring at -O2, greedy below
+    pub regalloc: Option<RegAllocKind>,
+    // keep s0 pointing at the top of the frame, for debuggers
+    pub frame_pointer: bool,
+}
+
+impl AsmConfig {
+    pub fn new() -> Self {
+        AsmConfig {
+            stack_check: false,
+            stack_size: DEFAULT_STACK_SIZE,
+            tail_calls: None,
+            regalloc: None,
+            frame_pointer: false,
+        }
+    }
+
+    // parse -fstack-check, -fno-stack-check, -fstack-size=<bytes>,
+    // -foptimize-sibling-calls, -fno-optimize-sibling-calls, -fregalloc=<kind>,
+    // -fno-omit-frame-pointer, -fomit-frame-pointer
+    pub fn parse_flag(&mut self, flag: &str) -> Result<(), String> {
+        match flag {
+            "-fstack-check" => self.stack_check = true,
+            "-fno-stack-check" => self.stack_check = false,
+            "-foptimize-sibling-calls" => self.tail_calls = Some(true),
+            "-fno-optimize-sibling-calls" => self.tail_calls = Some(false),
+            "-fno-omit-frame-pointer" => self.frame_pointer = true,
+            "-fomit-frame-pointer" => self.frame_pointer = false,
+            _ => {
+                if let Some(size) = flag.strip_prefix("-fstack-size=") {
+                    self.stack_size =
+                        parse_size(size).ok_or_else(|| format!("invalid stack size: {}", size))?;
+                } else if let Some(kind) = flag.strip_prefix("-fregalloc=") {
+                    self.regalloc = Some(
+                        RegAllocKind::from_name(kind)
+                            .ok_or_else(|| format!("unknown register allocator: {}", kind))?,
+                    );
+                } else {
+                    return Err(format!("unknown backend option: {}", flag));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for AsmConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// bytes, with an optional K/M suffix
+fn parse_size(size: &str) -> Option<usize> {
+    let (num, unit) = match size.as_bytes().last()? {
+        b'k' | b'K' => (&size[..size.len() - 1], 1 << 10),
+        b'm' | b'M' => (&size[..size.len() - 1], 1 << 20),
+        _ => (size, 1),
+    };
+    num.parse::<usize>().ok()?.checked_mul(unit)
+}
@@ -0,0 +1,320 @@
+use super::{BinOp, MachineFunc, UnaryOp};
+use crate::asm::Reg;
+
+// ============ Operations with a Constant Operand ============
+/*
+    Multiplication, division and remainder by a constant, using t6 as scratch.
+    Division by 2^k rounds towards zero by adding 2^k - 1 to negative
+    dividends before the shift; other divisors use a multiply-high by a
+    magic number (Hacker's Delight, 10-4). Division by zero keeps `div`/`rem`,
+    whose results RV32M defines.
+*/
+
+impl MachineFunc {
+    fn op_li(&mut self, op: BinOp, dest: Reg, src: Reg, imm: i32) {
+        self.li("t6", imm);
+        self.bin(op, dest, src, "t6");
+    }
+
+    pub fn mul_imm(&mut self, dest: Reg, src: Reg, imm: i32) {
+        let bits = imm as u32;
+        let low = bits.trailing_zeros();
+        match imm {
+            0 => self.mv(dest, "x0"),
+            1 => self.mv(dest, src),
+            -1 => self.unary(UnaryOp::Neg, dest, src),
+            _ if imm.unsigned_abs().is_power_of_two() => {
+                self.bin_imm(BinOp::Sll, dest, src, low as i32);
+                if imm < 0 && imm != i32::MIN {
+                    self.unary(UnaryOp::Neg, dest, dest);
+                }
+            }
+            // 2^a + 2^b
+            _ if bits.count_ones() == 2 => {
+                let high = 31 - bits.leading_zeros();
+                self.bin_imm(BinOp::Sll, "t6", src, high as i32);
+                if low == 0 {
+                    self.bin(BinOp::Add, dest, "t6", src);
+                } else {
+                    self.bin_imm(BinOp::Sll, dest, src, low as i32);
+                    self.bin(BinOp::Add, dest, "t6", dest);
+                }
+            }
+            // 2^a - 2^b
+            _ if (bits + (1 << low)).is_power_of_two() => {
+                let high = (bits + (1 << low)).trailing_zeros();
+                self.bin_imm(BinOp::Sll, "t6", src, high as i32);
+                if low == 0 {
+                    self.bin(BinOp::Sub, dest, "t6", src);
+                } else {
+                    self.bin_imm(BinOp::Sll, dest, src, low as i32);
+                    self.bin(BinOp::Sub, dest, "t6", dest);
+                }
+            }
+            _ => self.op_li(BinOp::Mul, dest, src, imm),
+        }
+    }
+
+    pub fn div_imm(&mut self, dest: Reg, src: Reg, imm: i32) {
+        match imm {
+            0 => self.op_li(BinOp::Div, dest, src, imm),
+            1 => self.mv(dest, src),
+            -1 => self.unary(UnaryOp::Neg, dest, src),
+            _ if imm.unsigned_abs().is_power_of_two() => {
+                let k = imm.unsigned_abs().trailing_zeros() as i32;
+                self.round_to_zero(src, k);
+                self.bin_imm(BinOp::Sra, dest, "t6", k);
+                if imm < 0 {
+                    self.unary(UnaryOp::Neg, dest, dest);
+                }
+            }
+            _ => {
+                self.div_magic(src, imm);
+                // q = t6 + (t6 < 0)
+                self.bin_imm(BinOp::Srl, dest, "t6", 31);
+                self.bin(BinOp::Add, dest, "t6", dest);
+            }
+        }
+    }
+
+    // dest must differ from src
+    pub fn rem_imm(&mut self, dest: Reg, src: Reg, imm: i32) {
+        // the sign of the remainder follows the dividend
+        match imm.unsigned_abs() {
+            0 => self.op_li(BinOp::Rem, dest, src, imm),
+            1 => self.mv(dest, "x0"),
+            divisor if divisor.is_power_of_two() => {
+                let k = divisor.trailing_zeros() as i32;
+                self.round_to_zero(src, k);
+                if k <= 11 {
+                    self.bin_imm(BinOp::And, "t6", "t6", -(1 << k));
+                } else {
+                    self.bin_imm(BinOp::Sra, "t6", "t6", k);
+                    self.bin_imm(BinOp::Sll, "t6", "t6", k);
+                }
+                self.bin(BinOp::Sub, dest, src, "t6");
+            }
+            divisor => {
+                let divisor = divisor as i32;
+                self.div_magic(src, divisor);
+                self.bin_imm(BinOp::Srl, dest, "t6", 31);
+                self.bin(BinOp::Add, "t6", "t6", dest);
+                // src - q * divisor
+                self.li(dest, divisor);
+                self.bin(BinOp::Mul, "t6", "t6", dest);
+                self.bin(BinOp::Sub, dest, src, "t6");
+            }
+        }
+    }
+
+    // t6 = src + (src < 0 ? 2^k - 1 : 0)
+    fn round_to_zero(&mut self, src: Reg, k: i32) {
+        if k == 1 {
+            self.bin_imm(BinOp::Srl, "t6", src, 31);
+        } else {
+            self.bin_imm(BinOp::Sra, "t6", src, 31);
+            self.bin_imm(BinOp::Srl, "t6", "t6", 32 - k);
+        }
+        self.bin(BinOp::Add, "t6", src, "t6");
+    }
+
+    // t6 = the quotient rounded towards negative infinity
+    fn div_magic(&mut self, src: Reg, divisor: i32) {
+        let (magic, shift) = magic_number(divisor);
+        self.li("t6", magic);
+        self.bin(BinOp::Mulh, "t6", src, "t6");
+        if divisor > 0 && magic < 0 {
+            self.bin(BinOp::Add, "t6", "t6", src);
+        } else if divisor < 0 && magic > 0 {
+            self.bin(BinOp::Sub, "t6", "t6", src);
+        }
+        if shift > 0 {
+            self.bin_imm(BinOp::Sra, "t6", "t6", shift);
+        }
+    }
+}
+
+// magic number and shift for signed division by `divisor`, |divisor| >= 2
+fn magic_number(divisor: i32) -> (i32, i32) {
+    const TWO31: u32 = 1 << 31;
+    let ad = divisor.unsigned_abs();
+    let t = TWO31 + ((divisor as u32) >> 31);
+    // absolute value of nc
+    let anc = t - 1 - t % ad;
+    let mut p = 31;
+    let (mut q1, mut r1) = (TWO31 / anc, TWO31 % anc);
+    let (mut q2, mut r2) = (TWO31 / ad, TWO31 % ad);
+    loop {
+        p += 1;
+        q1 = q1.wrapping_mul(2);
+        r1 = r1.wrapping_mul(2);
+        if r1 >= anc {
+            q1 = q1.wrapping_add(1);
+            r1 = r1.wrapping_sub(anc);
+        }
+        q2 = q2.wrapping_mul(2);
+        r2 = r2.wrapping_mul(2);
+        if r2 >= ad {
+            q2 = q2.wrapping_add(1);
+            r2 = r2.wrapping_sub(ad);
+        }
+        let delta = ad - r2;
+        if !(q1 < delta || (q1 == delta && r1 == 0)) {
+            break;
+        }
+    }
+    let magic = q2.wrapping_add(1) as i32;
+    let magic = if divisor < 0 {
+        magic.wrapping_neg()
+    } else {
+        magic
+    };
+    (magic, p - 32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm::machine::Inst;
+    use std::collections::HashMap;
+
+    const DIVIDENDS: [i32; 10] = [i32::MIN, i32::MAX, 0, -1, 1, 7, -7, 12345, -12345, 1000003];
+
+    // ±1, ±2^k, INT_MIN, odd and even divisors, and 0
+    fn divisors() -> Vec<i32> {
+        let mut divisors = vec![0, 1, -1, i32::MIN, i32::MAX, i32::MIN + 1];
+        for k in 1..31 {
+            divisors.push(1 << k);
+            divisors.push(-(1 << k));
+        }
+        for d in [
+            3, 5, 7, 9, 11, 641, 1000003, 6, 10, 12, 24, 100, 1000, 65534,
+        ] {
+            divisors.push(d);
+            divisors.push(-d);
+        }
+        divisors
+    }
+
+    // RV32M, x / 0 = -1 and x % 0 = x
+    fn div(a: i32, b: i32) -> i32 {
+        if b == 0 {
+            -1
+        } else {
+            a.wrapping_div(b)
+        }
+    }
+
+    fn rem(a: i32, b: i32) -> i32 {
+        if b == 0 {
+            a
+        } else {
+            a.wrapping_rem(b)
+        }
+    }
+
+    fn eval(op: BinOp, a: i32, b: i32) -> i32 {
+        match op {
+            BinOp::Add => a.wrapping_add(b),
+            BinOp::Sub => a.wrapping_sub(b),
+            BinOp::Mul => a.wrapping_mul(b),
+            BinOp::Mulh => ((a as i64 * b as i64) >> 32) as i32,
+            BinOp::Div => div(a, b),
+            BinOp::Rem => rem(a, b),
+            BinOp::And => a & b,
+            BinOp::Or => a | b,
+            BinOp::Xor => a ^ b,
+            BinOp::Sll => a.wrapping_shl(b as u32),
+            BinOp::Srl => (a as u32).wrapping_shr(b as u32) as i32,
+            BinOp::Sra => a.wrapping_shr(b as u32),
+            BinOp::Slt => (a < b) as i32,
+        }
+    }
+
+    // the value of `dest` after running the selected code with `src` = x
+    fn run(emit: impl Fn(&mut MachineFunc), dest: Reg, src: Reg, x: i32) -> i32 {
+        let mut func = MachineFunc::new("f");
+        emit(&mut func);
+        let mut regs: HashMap<Reg, i32> = HashMap::from([(src, x)]);
+        for inst in func.blocks[0].insts.iter() {
+            let get = |regs: &HashMap<Reg, i32>, reg: Reg| {
+                if reg == "x0" {
+                    0
+                } else {
+                    regs[reg]
+                }
+            };
+            let (rd, value) = match *inst {
+                Inst::Li { rd, imm } => (rd, imm),
+                Inst::Mv { rd, rs } => (rd, get(&regs, rs)),
+                Inst::Unary { op, rd, rs } => {
+                    let a = get(&regs, rs);
+                    let value = match op {
+                        UnaryOp::Neg => a.wrapping_neg(),
+                        UnaryOp::Seqz => (a == 0) as i32,
+                        UnaryOp::Snez => (a != 0) as i32,
+                    };
+                    (rd, value)
+                }
+                Inst::Bin { op, rd, rs1, rs2 } => (rd, eval(op, get(&regs, rs1), get(&regs, rs2))),
+                Inst::BinImm { op, rd, rs, imm } => {
+                    assert!(op.has_imm_form(), "{:?} has no immediate form", op);
+                    if matches!(op, BinOp::Sll | BinOp::Srl | BinOp::Sra) {
+                        assert!((0..32).contains(&imm), "shift by {}", imm);
+                    }
+                    (rd, eval(op, get(&regs, rs), imm))
+                }
+                ref inst => panic!("unexpected {:?}", inst),
+            };
+            regs.insert(rd, value);
+        }
+        regs[dest]
+    }
+
+    #[test]
+    fn magic_numbers() {
+        // Hacker's Delight, table 10-1
+        assert_eq!(magic_number(3), (0x55555556, 0));
+        assert_eq!(magic_number(5), (0x66666667, 1));
+        assert_eq!(magic_number(7), (0x92492493_u32 as i32, 2));
+        assert_eq!(magic_number(-5), (0x99999999_u32 as i32, 1));
+        assert_eq!(magic_number(-7), (0x6db6db6d, 2));
+    }
+
+    #[test]
+    fn div_by_constants() {
+        for d in divisors() {
+            for x in DIVIDENDS {
+                let q = run(|f| f.div_imm("a0", "a1", d), "a0", "a1", x);
+                assert_eq!(q, div(x, d), "{} / {}", x, d);
+                // the result may overwrite the operand
+                let q = run(|f| f.div_imm("a0", "a0", d), "a0", "a0", x);
+                assert_eq!(q, div(x, d), "{} / {} in place", x, d);
+            }
+        }
+    }
+
+    #[test]
+    fn rem_by_constants() {
+        for d in divisors() {
+            for x in DIVIDENDS {
+                let r = run(|f| f.rem_imm("a0", "a1", d), "a0", "a1", x);
+                assert_eq!(r, rem(x, d), "{} % {}", x, d);
+            }
+        }
+    }
+
+    #[test]
+    fn mul_by_constants() {
+        let mut factors = divisors();
+        factors.extend([(1 << 30) | 1, (1 << 30) - 1, 0x0ff0, -0x0ff0, 0x7fff0000]);
+        for c in factors {
+            for x in DIVIDENDS {
+                let p = run(|f| f.mul_imm("a0", "a1", c), "a0", "a1", x);
+                assert_eq!(p, x.wrapping_mul(c), "{} * {}", x, c);
+                let p = run(|f| f.mul_imm("a0", "a0", c), "a0", "a0", x);
+                assert_eq!(p, x.wrapping_mul(c), "{} * {} in place", x, c);
+            }
+        }
+    }
+}
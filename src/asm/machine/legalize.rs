@@ -0,0 +1,70 @@
+use super::{BinOp, Inst, MachineFunc, IMM_SCRATCH};
+use crate::asm::Reg;
+
+// ============ Legalize ============
+/*
+    Immediates and offsets must fit in 12 bits. Larger ones are loaded into
+    t6 first, which the selected code never keeps live across such an
+    instruction.
+*/
+
+fn fits(imm: i32) -> bool {
+    (-2048..=2047).contains(&imm)
+}
+
+pub fn run(func: &mut MachineFunc) {
+    for block in func.blocks.iter_mut() {
+        let mut insts = Vec::with_capacity(block.insts.len());
+        for inst in std::mem::take(&mut block.insts) {
+            match inst {
+                Inst::BinImm { op, rd, rs, imm } if !fits(imm) => {
+                    assert_ne!(rs, IMM_SCRATCH, "t6 is both operand and scratch");
+                    insts.push(Inst::Li {
+                        rd: IMM_SCRATCH,
+                        imm,
+                    });
+                    insts.push(Inst::Bin {
+                        op,
+                        rd,
+                        rs1: rs,
+                        rs2: IMM_SCRATCH,
+                    });
+                }
+                Inst::Lw { rd, base, offset } if !fits(offset) => {
+                    assert_ne!(base, IMM_SCRATCH, "t6 is both base and scratch");
+                    address(&mut insts, base, offset);
+                    insts.push(Inst::Lw {
+                        rd,
+                        base: IMM_SCRATCH,
+                        offset: 0,
+                    });
+                }
+                Inst::Sw { rs, base, offset } if !fits(offset) => {
+                    assert!(base != IMM_SCRATCH && rs != IMM_SCRATCH);
+                    address(&mut insts, base, offset);
+                    insts.push(Inst::Sw {
+                        rs,
+                        base: IMM_SCRATCH,
+                        offset: 0,
+                    });
+                }
+                inst => insts.push(inst),
+            }
+        }
+        block.insts = insts;
+    }
+}
+
+// t6 = base + offset
+fn address(insts: &mut Vec<Inst>, base: Reg, offset: i32) {
+    insts.push(Inst::Li {
+        rd: IMM_SCRATCH,
+        imm: offset,
+    });
+    insts.push(Inst::Bin {
+        op: BinOp::Add,
+        rd: IMM_SCRATCH,
+        rs1: IMM_SCRATCH,
+        rs2: base,
+    });
+}
@@ -0,0 +1,349 @@
+mod const_ops;
+mod legalize;
+mod peephole;
+mod print;
+
+use super::Reg;
+use koopa::ir::Value;
+
+// ============ Machine IR ============
+/*
+    Instruction selection lowers each Koopa function to a MachineFunc: machine
+    blocks of RISC-V instructions over registers, plus the frame objects. The
+    passes then rewrite it in place before it is printed:
+        peephole: local cleanups, see peephole.rs
+        legalize: immediates and offsets out of the 12-bit range go through t6
+    Operands are physical registers: the allocators run on the Koopa values
+    before selection, so no virtual registers are needed.
+*/
+
+pub const ZERO: Reg = "x0";
+pub const SP: Reg = "sp";
+// scratch of legalize and of the constant operations
+pub const IMM_SCRATCH: Reg = "t6";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Mulh,
+    Div,
+    Rem,
+    And,
+    Or,
+    Xor,
+    Sll,
+    Srl,
+    Sra,
+    Slt,
+}
+
+impl BinOp {
+    // whether `op` has a form with a 12-bit immediate (addi, andi, ...)
+    pub fn has_imm_form(self) -> bool {
+        matches!(
+            self,
+            BinOp::Add
+                | BinOp::And
+                | BinOp::Or
+                | BinOp::Xor
+                | BinOp::Sll
+                | BinOp::Srl
+                | BinOp::Sra
+                | BinOp::Slt
+        )
+    }
+
+    pub fn is_commutative(self) -> bool {
+        matches!(
+            self,
+            BinOp::Add | BinOp::Mul | BinOp::Mulh | BinOp::And | BinOp::Or | BinOp::Xor
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Seqz,
+    Snez,
+}
+
+// conditions of `b<cond> rs1, rs2, target`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cond {
+    Eq,
+    Ne,
+    Lt,
+    Ge,
+    Ltu,
+    Geu,
+}
+
+impl Cond {
+    pub fn negate(self) -> Self {
+        match self {
+            Cond::Eq => Cond::Ne,
+            Cond::Ne => Cond::Eq,
+            Cond::Lt => Cond::Ge,
+            Cond::Ge => Cond::Lt,
+            Cond::Ltu => Cond::Geu,
+            Cond::Geu => Cond::Ltu,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    Li {
+        rd: Reg,
+        imm: i32,
+    },
+    La {
+        rd: Reg,
+        symbol: String,
+    },
+    Mv {
+        rd: Reg,
+        rs: Reg,
+    },
+    Unary {
+        op: UnaryOp,
+        rd: Reg,
+        rs: Reg,
+    },
+    Bin {
+        op: BinOp,
+        rd: Reg,
+        rs1: Reg,
+        rs2: Reg,
+    },
+    // any i32 until legalized
+    BinImm {
+        op: BinOp,
+        rd: Reg,
+        rs: Reg,
+        imm: i32,
+    },
+    Lw {
+        rd: Reg,
+        base: Reg,
+        offset: i32,
+    },
+    Sw {
+        rs: Reg,
+        base: Reg,
+        offset: i32,
+    },
+    J {
+        target: String,
+    },
+    Branch {
+        cond: Cond,
+        rs1: Reg,
+        rs2: Reg,
+        target: String,
+    },
+    Call {
+        callee: String,
+    },
+    Tail {
+        callee: String,
+    },
+    Ret,
+}
+
+impl Inst {
+    // the register written, calls clobbering the caller-saved ones aside
+    pub fn def(&self) -> Option<Reg> {
+        match *self {
+            Inst::Li { rd, .. }
+            | Inst::La { rd, .. }
+            | Inst::Mv { rd, .. }
+            | Inst::Unary { rd, .. }
+            | Inst::Bin { rd, .. }
+            | Inst::BinImm { rd, .. }
+            | Inst::Lw { rd, .. } => Some(rd),
+            _ => None,
+        }
+    }
+
+    // the registers read, the arguments of calls aside
+    pub fn uses(&self) -> Vec<Reg> {
+        match *self {
+            Inst::Mv { rs, .. } | Inst::Unary { rs, .. } | Inst::BinImm { rs, .. } => vec![rs],
+            Inst::Bin { rs1, rs2, .. } | Inst::Branch { rs1, rs2, .. } => vec![rs1, rs2],
+            Inst::Lw { base, .. } => vec![base],
+            Inst::Sw { rs, base, .. } => vec![rs, base],
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn is_terminator(&self) -> bool {
+        matches!(self, Inst::J { .. } | Inst::Tail { .. } | Inst::Ret)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MachineBlock {
+    pub label: String,
+    pub insts: Vec<Inst>,
+}
+
+// ---- frame ----
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameObjectKind {
+    // arguments past the eighth of the calls made
+    OutgoingArgs,
+    Alloc(Value),
+    Spill,
+    // home slot of a register value living across calls
+    Home(Value),
+    // parallel copies of block arguments (greedy allocator)
+    BlockArgs,
+    SavedReg(Reg),
+}
+
+// offsets from sp
+#[derive(Debug, Clone, Copy)]
+pub struct FrameObject {
+    pub kind: FrameObjectKind,
+    pub offset: i32,
+    pub size: usize,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Frame {
+    // from sp up
+    pub objects: Vec<FrameObject>,
+    // bytes taken by the objects, before alignment
+    pub used: usize,
+}
+
+impl Frame {
+    pub fn alloc(&mut self, kind: FrameObjectKind, size: usize) -> i32 {
+        let offset = self.used as i32;
+        self.objects.push(FrameObject { kind, offset, size });
+        self.used += size;
+        offset
+    }
+
+    pub fn place(&mut self, kind: FrameObjectKind, offset: i32, size: usize) {
+        self.objects.push(FrameObject { kind, offset, size });
+    }
+}
+
+// ---- function ----
+
+pub struct MachineFunc {
+    pub name: String,
+    // the first block is the entry, labeled with the function name
+    pub blocks: Vec<MachineBlock>,
+    pub frame: Frame,
+    pub stack_size: usize,
+}
+
+impl MachineFunc {
+    pub fn new(name: &str) -> Self {
+        MachineFunc {
+            name: name.to_string(),
+            blocks: vec![MachineBlock {
+                label: name.to_string(),
+                insts: Vec::new(),
+            }],
+            frame: Frame::default(),
+            stack_size: 0,
+        }
+    }
+
+    // the passes after instruction selection
+    pub fn optimize(&mut self) {
+        peephole::run(self);
+        legalize::run(self);
+    }
+
+    // ---- instruction selection ----
+
+    pub fn push(&mut self, inst: Inst) {
+        self.blocks.last_mut().unwrap().insts.push(inst);
+    }
+
+    // start a new block
+    pub fn label(&mut self, label: &str) {
+        self.blocks.push(MachineBlock {
+            label: label.to_string(),
+            insts: Vec::new(),
+        });
+    }
+
+    pub fn li(&mut self, rd: Reg, imm: i32) {
+        self.push(Inst::Li { rd, imm });
+    }
+
+    pub fn la(&mut self, rd: Reg, symbol: &str) {
+        let symbol = symbol.to_string();
+        self.push(Inst::La { rd, symbol });
+    }
+
+    pub fn mv(&mut self, rd: Reg, rs: Reg) {
+        self.push(Inst::Mv { rd, rs });
+    }
+
+    pub fn unary(&mut self, op: UnaryOp, rd: Reg, rs: Reg) {
+        self.push(Inst::Unary { op, rd, rs });
+    }
+
+    pub fn bin(&mut self, op: BinOp, rd: Reg, rs1: Reg, rs2: Reg) {
+        self.push(Inst::Bin { op, rd, rs1, rs2 });
+    }
+
+    pub fn bin_imm(&mut self, op: BinOp, rd: Reg, rs: Reg, imm: i32) {
+        debug_assert!(op.has_imm_form(), "{:?} has no immediate form", op);
+        self.push(Inst::BinImm { op, rd, rs, imm });
+    }
+
+    pub fn lw(&mut self, rd: Reg, base: Reg, offset: i32) {
+        self.push(Inst::Lw { rd, base, offset });
+    }
+
+    pub fn sw(&mut self, rs: Reg, base: Reg, offset: i32) {
+        self.push(Inst::Sw { rs, base, offset });
+    }
+
+    pub fn j(&mut self, target: &str) {
+        self.push(Inst::J {
+            target: target.to_string(),
+        });
+    }
+
+    pub fn branch(&mut self, cond: Cond, rs1: Reg, rs2: Reg, target: &str) {
+        self.push(Inst::Branch {
+            cond,
+            rs1,
+            rs2,
+            target: target.to_string(),
+        });
+    }
+
+    pub fn bnez(&mut self, rs: Reg, target: &str) {
+        self.branch(Cond::Ne, rs, "x0", target);
+    }
+
+    pub fn call(&mut self, callee: &str) {
+        self.push(Inst::Call {
+            callee: callee.to_string(),
+        });
+    }
+
+    pub fn tail(&mut self, callee: &str) {
+        self.push(Inst::Tail {
+            callee: callee.to_string(),
+        });
+    }
+
+    pub fn ret(&mut self) {
+        self.push(Inst::Ret);
+    }
+}
@@ -0,0 +1,145 @@
+use super::{BinOp, Inst, MachineFunc};
+use crate::asm::Reg;
+use std::collections::HashMap;
+
+// ============ Peephole ============
+/*
+    Local rewrites on the selected code:
+        li t, c; op rd, rs, t    =>  opi rd, rs, c     (t is a scratch register dead after)
+        sw r, o(b); lw d, o(b)   =>  sw r, o(b); mv d, r
+        mv r, r                  =>  (removed)
+        b<c> L; j M; L:          =>  b<!c> M; L:
+        j L; L:                  =>  L:
+    Scratch registers (t4 ~ t6) never live across blocks or calls, so a jump,
+    call or return ends their value. Other registers get no such assumption.
+*/
+
+// in instructions, leaving room for legalize to expand them
+const MAX_BRANCH_DISTANCE: usize = 256;
+
+const SCRATCH: [Reg; 3] = ["t4", "t5", "t6"];
+
+pub fn run(func: &mut MachineFunc) {
+    for block in func.blocks.iter_mut() {
+        fold_imm(&mut block.insts);
+        forward_stores(&mut block.insts);
+        block
+            .insts
+            .retain(|inst| !matches!(inst, Inst::Mv { rd, rs } if rd == rs));
+    }
+    fall_through(func);
+}
+
+fn fits(imm: i32) -> bool {
+    (-2048..=2047).contains(&imm)
+}
+
+// whether the value of the scratch register `reg` is unused by insts
+fn dead_in(insts: &[Inst], reg: Reg) -> bool {
+    if !SCRATCH.contains(&reg) {
+        return false;
+    }
+    for inst in insts {
+        // the other side of a branch is not looked at
+        if inst.uses().contains(&reg) || matches!(inst, Inst::Branch { .. }) {
+            return false;
+        }
+        if inst.def() == Some(reg) || inst.is_terminator() || matches!(inst, Inst::Call { .. }) {
+            return true;
+        }
+    }
+    // falls through to the next block
+    false
+}
+
+// `op rd, rs, imm` in one instruction, if there is such a form
+fn imm_form(op: BinOp, rd: Reg, rs: Reg, imm: i32) -> Option<Inst> {
+    match op {
+        BinOp::Sll | BinOp::Srl | BinOp::Sra if !(0..32).contains(&imm) => None,
+        _ if op.has_imm_form() && fits(imm) => Some(Inst::BinImm { op, rd, rs, imm }),
+        BinOp::Sub if imm != i32::MIN && fits(-imm) => Some(Inst::BinImm {
+            op: BinOp::Add,
+            rd,
+            rs,
+            imm: -imm,
+        }),
+        _ => None,
+    }
+}
+
+fn fold_imm(insts: &mut Vec<Inst>) {
+    let mut i = 0;
+    while i + 1 < insts.len() {
+        let folded = match (&insts[i], &insts[i + 1]) {
+            (&Inst::Li { rd: temp, imm }, &Inst::Bin { op, rd, rs1, rs2 }) => {
+                let inst = if rs2 == temp && rs1 != temp {
+                    imm_form(op, rd, rs1, imm)
+                } else if rs1 == temp && rs2 != temp && op.is_commutative() {
+                    imm_form(op, rd, rs2, imm)
+                } else {
+                    None
+                };
+                inst.filter(|_| rd == temp || dead_in(&insts[i + 2..], temp))
+            }
+            _ => None,
+        };
+        match folded {
+            Some(inst) => {
+                insts[i] = inst;
+                insts.remove(i + 1);
+            }
+            None => i += 1,
+        }
+    }
+}
+
+fn forward_stores(insts: &mut [Inst]) {
+    for i in 1..insts.len() {
+        if let (&Inst::Sw { rs, base, offset }, &Inst::Lw { rd, .. }) = (&insts[i - 1], &insts[i]) {
+            if insts[i] == (Inst::Lw { rd, base, offset }) {
+                insts[i] = Inst::Mv { rd, rs };
+            }
+        }
+    }
+}
+
+// let a block fall through to the next one instead of jumping there
+fn fall_through(func: &mut MachineFunc) {
+    // a conditional branch only reaches +-4KiB, keep `j` for far targets
+    let mut starts: HashMap<String, usize> = HashMap::new();
+    let mut pos = 0;
+    for block in func.blocks.iter() {
+        starts.insert(block.label.clone(), pos);
+        pos += block.insts.len();
+    }
+    for i in 0..func.blocks.len().saturating_sub(1) {
+        let next = func.blocks[i + 1].label.clone();
+        let here = starts[&func.blocks[i].label] + func.blocks[i].insts.len();
+        let insts = &mut func.blocks[i].insts;
+        let n = insts.len();
+        if n >= 2 {
+            if let (Inst::Branch { cond, target, .. }, Inst::J { target: other }) =
+                (&insts[n - 2], &insts[n - 1])
+            {
+                let near = starts
+                    .get(other)
+                    .is_some_and(|&there| here.abs_diff(there) <= MAX_BRANCH_DISTANCE);
+                if *target == next && near {
+                    let (cond, other) = (cond.negate(), other.clone());
+                    insts.pop();
+                    if let Some(Inst::Branch {
+                        cond: c, target: t, ..
+                    }) = insts.last_mut()
+                    {
+                        *c = cond;
+                        *t = other;
+                    }
+                    continue;
+                }
+            }
+        }
+        if matches!(insts.last(), Some(Inst::J { target }) if *target == next) {
+            insts.pop();
+        }
+    }
+}
@@ -0,0 +1,98 @@
+use super::{BinOp, Cond, Inst, MachineFunc, UnaryOp, ZERO};
+use std::fmt;
+
+// ============ Printing ============
+
+impl BinOp {
+    fn name(self) -> &'static str {
+        match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Mulh => "mulh",
+            BinOp::Div => "div",
+            BinOp::Rem => "rem",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+            BinOp::Xor => "xor",
+            BinOp::Sll => "sll",
+            BinOp::Srl => "srl",
+            BinOp::Sra => "sra",
+            BinOp::Slt => "slt",
+        }
+    }
+}
+
+impl UnaryOp {
+    fn name(self) -> &'static str {
+        match self {
+            UnaryOp::Neg => "neg",
+            UnaryOp::Seqz => "seqz",
+            UnaryOp::Snez => "snez",
+        }
+    }
+}
+
+impl Cond {
+    fn name(self) -> &'static str {
+        match self {
+            Cond::Eq => "eq",
+            Cond::Ne => "ne",
+            Cond::Lt => "lt",
+            Cond::Ge => "ge",
+            Cond::Ltu => "ltu",
+            Cond::Geu => "geu",
+        }
+    }
+}
+
+impl fmt::Display for Inst {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Inst::Li { rd, imm } => write!(f, "li {}, {}", rd, imm),
+            Inst::La { rd, symbol } => write!(f, "la {}, {}", rd, symbol),
+            Inst::Mv { rd, rs } => write!(f, "mv {}, {}", rd, rs),
+            Inst::Unary { op, rd, rs } => write!(f, "{} {}, {}", op.name(), rd, rs),
+            Inst::Bin { op, rd, rs1, rs2 } => {
+                write!(f, "{} {}, {}, {}", op.name(), rd, rs1, rs2)
+            }
+            Inst::BinImm { op, rd, rs, imm } => {
+                write!(f, "{}i {}, {}, {}", op.name(), rd, rs, imm)
+            }
+            Inst::Lw { rd, base, offset } => write!(f, "lw {}, {}({})", rd, offset, base),
+            Inst::Sw { rs, base, offset } => write!(f, "sw {}, {}({})", rs, offset, base),
+            Inst::J { target } => write!(f, "j {}", target),
+            // bnez / beqz against x0
+            Inst::Branch {
+                cond: cond @ (Cond::Eq | Cond::Ne),
+                rs1,
+                rs2,
+                target,
+            } if *rs2 == ZERO => write!(f, "b{}z {}, {}", cond.name(), rs1, target),
+            Inst::Branch {
+                cond,
+                rs1,
+                rs2,
+                target,
+            } => write!(f, "b{} {}, {}, {}", cond.name(), rs1, rs2, target),
+            Inst::Call { callee } => write!(f, "call {}", callee),
+            Inst::Tail { callee } => write!(f, "tail {}", callee),
+            Inst::Ret => write!(f, "ret"),
+        }
+    }
+}
+
+impl fmt::Display for MachineFunc {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, block) in self.blocks.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(f, "{}:", block.label)?;
+            for inst in block.insts.iter() {
+                writeln!(f, "  {}", inst)?;
+            }
+        }
+        Ok(())
+    }
+}
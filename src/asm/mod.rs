@@ -1,12 +1,17 @@
 mod build_func;
 mod build_prog;
 mod build_value;
-mod gen_riscv;
+mod config;
+mod machine;
+mod regalloc;
+mod runtime;
 mod util;
+pub use config::{AsmConfig, RegAllocKind};
 use koopa::ir::Program;
+pub use runtime::TRAP_EXIT_CODE;
 
 /*
-    t0 ~ t6, a0 ~ a7 available
+    t0 ~ t6, a0 ~ a7 available to the greedy ValueTable
     t6 is used for large imm value
 */
 static REG_LIST: [&str; 14] = [
@@ -14,7 +19,7 @@ static REG_LIST: [&str; 14] = [
 ];
 
 pub trait GenerateAsm {
-    fn to_asm(&self, prog: &Program) -> String;
+    fn to_asm(&self, prog: &Program, config: &AsmConfig) -> String;
 }
 
 pub type Asm = String;
@@ -23,6 +28,11 @@ pub type Reg = &'static str;
 
 type Addr = i32;
 
-pub fn koopa_to_asm(koopa_program: &Program) -> String {
-    build_prog::prog_to_asm(koopa_program)
+pub fn koopa_to_asm(koopa_program: &Program, config: &AsmConfig) -> String {
+    build_prog::prog_to_asm(koopa_program, config)
+}
+
+// frame size of each defined function, in layout order
+pub fn frame_sizes(koopa_program: &Program, config: &AsmConfig) -> Vec<(String, usize)> {
+    build_func::frame_sizes(koopa_program, config)
 }
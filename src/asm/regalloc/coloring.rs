@@ -0,0 +1,546 @@
+use super::liveness::{insts_of, needs_location, operands, BlockLiveness};
+use super::{block_weights, is_callee_saved, prefer_callee_saved, Allocation, ARG_REGS};
+use crate::asm::Reg;
+use koopa::ir::{FunctionData, Value, ValueKind};
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+// ============ Graph Coloring ============
+/*
+    Iterated register coalescing (George & Appel). The interference graph has
+    a node per value needing a location and a precolored node per register;
+    moves are the jump arguments passed to block parameters, and the values
+    going to or coming from a fixed register: parameters, call arguments and
+    results, the returned value.
+        simplify: remove a node of low degree that is not move related
+        coalesce: merge the ends of a move, Briggs' test for two values and
+                  George's test against a register
+        freeze:   give up the moves of a low degree node
+        spill:    pick the node of least cost / degree as a potential spill
+    and finally pop the nodes, picking a color of a move partner if possible.
+    Nodes crossing calls often are not coalesced with a caller-saved register
+    and take a callee-saved color first, avoiding a reload after every call.
+    Spill code goes through the scratch registers, so a node that cannot be
+    colored simply lives in a stack slot, without building the graph again.
+    Spill costs count the definitions and uses, weighted by loop depth.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum NodeState {
+    Precolored,
+    Initial,
+    Simplify,
+    Freeze,
+    Spill,
+    Coalesced,
+    OnStack,
+    Colored,
+    Spilled,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MoveState {
+    Worklist,
+    Active,
+    Coalesced,
+    Constrained,
+    Frozen,
+}
+
+struct Graph {
+    k: usize,
+    // node k + i is values[i], the nodes below k are the registers
+    values: Vec<Value>,
+    state: Vec<NodeState>,
+    adj_set: HashSet<(usize, usize)>,
+    adj_list: Vec<Vec<usize>>,
+    degree: Vec<usize>,
+    move_list: Vec<Vec<usize>>,
+    moves: Vec<(usize, usize)>,
+    move_state: Vec<MoveState>,
+    alias: Vec<usize>,
+    color: Vec<Option<usize>>,
+    // whether the node prefers a callee-saved color, and whether a color is callee-saved
+    prefers_callee_saved: Vec<bool>,
+    callee_saved: Vec<bool>,
+    cost: Vec<usize>,
+    simplify: BTreeSet<usize>,
+    freeze: BTreeSet<usize>,
+    spill: BTreeSet<usize>,
+    worklist_moves: BTreeSet<usize>,
+    select_stack: Vec<usize>,
+}
+
+pub fn graph_coloring(data: &FunctionData, regs: &[Reg]) -> Allocation {
+    let blocks = BlockLiveness::new(data);
+    let across = blocks.live_across_calls(data);
+    let mut graph = Graph::build(data, &blocks, regs, &across);
+    graph.make_worklist();
+    loop {
+        if let Some(&node) = graph.simplify.iter().next() {
+            graph.simplify(node);
+        } else if let Some(&m) = graph.worklist_moves.iter().next() {
+            graph.coalesce(m);
+        } else if let Some(&node) = graph.freeze.iter().next() {
+            graph.freeze_node(node);
+        } else if !graph.spill.is_empty() {
+            graph.select_spill();
+        } else {
+            break;
+        }
+    }
+    graph.assign_colors();
+
+    let mut allocation = Allocation::new();
+    let mut groups: HashMap<usize, Vec<Value>> = HashMap::new();
+    let mut spilled_roots = Vec::new();
+    for (i, &value) in graph.values.iter().enumerate() {
+        let node = graph.k + i;
+        match graph.color[node] {
+            Some(color) => {
+                allocation.regs.insert(value, regs[color]);
+            }
+            None => {
+                let root = graph.get_alias(node);
+                if !groups.contains_key(&root) {
+                    spilled_roots.push(root);
+                }
+                groups.entry(root).or_default().push(value);
+            }
+        }
+    }
+    for root in spilled_roots {
+        allocation.spilled.push(groups.remove(&root).unwrap());
+    }
+    allocation.find_saved(&blocks, across);
+    allocation
+}
+
+impl Graph {
+    fn build(
+        data: &FunctionData,
+        blocks: &BlockLiveness,
+        regs: &[Reg],
+        across: &HashMap<Value, Vec<Value>>,
+    ) -> Self {
+        let k = regs.len();
+        let located = blocks
+            .index
+            .keys()
+            .filter(|&&value| needs_location(data, value));
+        let values = blocks.sorted(located);
+        let n = k + values.len();
+        let weights = block_weights(data);
+        let callee_saved = prefer_callee_saved(data, across, &weights);
+        let mut graph = Graph {
+            k,
+            values: Vec::new(),
+            state: (0..n)
+                .map(|i| {
+                    if i < k {
+                        NodeState::Precolored
+                    } else {
+                        NodeState::Initial
+                    }
+                })
+                .collect(),
+            adj_set: HashSet::new(),
+            adj_list: vec![Vec::new(); n],
+            // precolored nodes have infinite degree
+            degree: (0..n)
+                .map(|i| if i < k { usize::MAX / 2 } else { 0 })
+                .collect(),
+            move_list: vec![Vec::new(); n],
+            moves: Vec::new(),
+            move_state: Vec::new(),
+            alias: (0..n).collect(),
+            color: (0..n).map(|i| if i < k { Some(i) } else { None }).collect(),
+            prefers_callee_saved: (0..n)
+                .map(|i| i >= k && callee_saved.contains(&values[i - k]))
+                .collect(),
+            callee_saved: regs.iter().map(|&reg| is_callee_saved(reg)).collect(),
+            cost: vec![0; n],
+            simplify: BTreeSet::new(),
+            freeze: BTreeSet::new(),
+            spill: BTreeSet::new(),
+            worklist_moves: BTreeSet::new(),
+            select_stack: Vec::new(),
+        };
+        let node_of: HashMap<Value, usize> = values
+            .iter()
+            .enumerate()
+            .map(|(i, &value)| (value, k + i))
+            .collect();
+        let reg_node = |reg: Reg| regs.iter().position(|&r| r == reg).unwrap();
+
+        // (node, node, weight)
+        let mut moves: Vec<(usize, usize, usize)> = Vec::new();
+        let entry = data.layout().entry_bb().unwrap();
+        for &bb in blocks.bbs.iter() {
+            let weight = weights[&bb];
+            let mut live = blocks.live_out[&bb].clone();
+            for inst in insts_of(data, bb).into_iter().rev() {
+                let located = |value: &Value| node_of.get(value).copied();
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Jump(jump) => {
+                        let params = data.dfg().bb(jump.target()).params();
+                        for (arg, param) in jump.args().iter().zip(params) {
+                            if let (Some(a), Some(p)) = (located(arg), located(param)) {
+                                moves.push((a, p, weight));
+                            }
+                        }
+                    }
+                    ValueKind::Branch(branch) => {
+                        let edges = [
+                            (branch.true_bb(), branch.true_args()),
+                            (branch.false_bb(), branch.false_args()),
+                        ];
+                        for (target, args) in edges {
+                            let params = data.dfg().bb(target).params();
+                            for (arg, param) in args.iter().zip(params) {
+                                if let (Some(a), Some(p)) = (located(arg), located(param)) {
+                                    moves.push((a, p, weight));
+                                }
+                            }
+                        }
+                    }
+                    ValueKind::Call(call) => {
+                        for (i, arg) in call.args().iter().take(8).enumerate() {
+                            if let Some(a) = located(arg) {
+                                moves.push((a, reg_node(ARG_REGS[i]), weight));
+                            }
+                        }
+                        if let Some(result) = located(&inst) {
+                            moves.push((result, reg_node("a0"), weight));
+                        }
+                    }
+                    ValueKind::Return(ret) => {
+                        if let Some(value) = ret.value().and_then(|value| located(&value)) {
+                            moves.push((value, reg_node("a0"), weight));
+                        }
+                    }
+                    _ => {}
+                }
+                let inst_operands = operands(data, inst);
+                if let Some(def) = located(&inst) {
+                    // nor may the result share a register with an operand
+                    for other in live.iter().chain(inst_operands.iter()) {
+                        graph.add_edge(def, node_of[other]);
+                    }
+                    graph.cost[def] += weight;
+                    live.remove(&inst);
+                }
+                for operand in inst_operands {
+                    graph.cost[node_of[&operand]] += weight;
+                    live.insert(operand);
+                }
+            }
+            // parameters are defined together at the block start
+            let mut params: Vec<Value> = data.dfg().bb(bb).params().to_vec();
+            if bb == entry {
+                params.extend(data.params().iter().copied());
+                for (i, param) in data.params().iter().enumerate().take(8) {
+                    if let Some(p) = node_of.get(param) {
+                        moves.push((*p, reg_node(ARG_REGS[i]), weight));
+                    }
+                }
+            }
+            params.retain(|param| node_of.contains_key(param));
+            live.extend(params.iter().copied());
+            for param in params.iter() {
+                for other in live.iter() {
+                    graph.add_edge(node_of[param], node_of[other]);
+                }
+                graph.cost[node_of[param]] += weight;
+            }
+        }
+        for list in graph.adj_list.iter_mut() {
+            list.sort();
+        }
+
+        // moves of larger weight are coalesced first
+        moves.sort_by_key(|&(_, _, weight)| std::cmp::Reverse(weight));
+        for (i, (a, b, _)) in moves.into_iter().enumerate() {
+            graph.moves.push((a, b));
+            graph.move_state.push(MoveState::Worklist);
+            graph.move_list[a].push(i);
+            graph.move_list[b].push(i);
+            graph.worklist_moves.insert(i);
+        }
+        graph.values = values;
+        graph
+    }
+
+    fn is_precolored(&self, node: usize) -> bool {
+        node < self.k
+    }
+
+    fn add_edge(&mut self, u: usize, v: usize) {
+        if u == v || self.adj_set.contains(&(u, v)) {
+            return;
+        }
+        self.adj_set.insert((u, v));
+        self.adj_set.insert((v, u));
+        if !self.is_precolored(u) {
+            self.adj_list[u].push(v);
+            self.degree[u] += 1;
+        }
+        if !self.is_precolored(v) {
+            self.adj_list[v].push(u);
+            self.degree[v] += 1;
+        }
+    }
+
+    fn make_worklist(&mut self) {
+        for node in self.k..self.state.len() {
+            if self.degree[node] >= self.k {
+                self.state[node] = NodeState::Spill;
+                self.spill.insert(node);
+            } else if self.move_related(node) {
+                self.state[node] = NodeState::Freeze;
+                self.freeze.insert(node);
+            } else {
+                self.state[node] = NodeState::Simplify;
+                self.simplify.insert(node);
+            }
+        }
+    }
+
+    fn adjacent(&self, node: usize) -> Vec<usize> {
+        self.adj_list[node]
+            .iter()
+            .copied()
+            .filter(|&n| !matches!(self.state[n], NodeState::OnStack | NodeState::Coalesced))
+            .collect()
+    }
+
+    fn node_moves(&self, node: usize) -> Vec<usize> {
+        self.move_list[node]
+            .iter()
+            .copied()
+            .filter(|&m| matches!(self.move_state[m], MoveState::Active | MoveState::Worklist))
+            .collect()
+    }
+
+    fn move_related(&self, node: usize) -> bool {
+        !self.node_moves(node).is_empty()
+    }
+
+    fn simplify(&mut self, node: usize) {
+        self.simplify.remove(&node);
+        self.state[node] = NodeState::OnStack;
+        self.select_stack.push(node);
+        for neighbor in self.adjacent(node) {
+            self.decrement_degree(neighbor);
+        }
+    }
+
+    fn decrement_degree(&mut self, node: usize) {
+        if self.is_precolored(node) {
+            return;
+        }
+        let degree = self.degree[node];
+        self.degree[node] = degree - 1;
+        if degree == self.k {
+            let mut nodes = self.adjacent(node);
+            nodes.push(node);
+            self.enable_moves(&nodes);
+            self.spill.remove(&node);
+            if self.move_related(node) {
+                self.state[node] = NodeState::Freeze;
+                self.freeze.insert(node);
+            } else {
+                self.state[node] = NodeState::Simplify;
+                self.simplify.insert(node);
+            }
+        }
+    }
+
+    fn enable_moves(&mut self, nodes: &[usize]) {
+        for &node in nodes {
+            for m in self.node_moves(node) {
+                if self.move_state[m] == MoveState::Active {
+                    self.move_state[m] = MoveState::Worklist;
+                    self.worklist_moves.insert(m);
+                }
+            }
+        }
+    }
+
+    fn get_alias(&self, node: usize) -> usize {
+        let mut node = node;
+        while self.state[node] == NodeState::Coalesced {
+            node = self.alias[node];
+        }
+        node
+    }
+
+    fn add_worklist(&mut self, node: usize) {
+        if !self.is_precolored(node) && !self.move_related(node) && self.degree[node] < self.k {
+            self.freeze.remove(&node);
+            self.state[node] = NodeState::Simplify;
+            self.simplify.insert(node);
+        }
+    }
+
+    // George: every neighbor of `v` is of low degree or already interferes with `u`
+    fn george(&self, u: usize, v: usize) -> bool {
+        self.adjacent(v).into_iter().all(|t| {
+            self.degree[t] < self.k || self.is_precolored(t) || self.adj_set.contains(&(t, u))
+        })
+    }
+
+    // Briggs: the merged node has less than k neighbors of high degree
+    fn briggs(&self, u: usize, v: usize) -> bool {
+        let mut nodes = self.adjacent(u);
+        for t in self.adjacent(v) {
+            if !nodes.contains(&t) {
+                nodes.push(t);
+            }
+        }
+        nodes.iter().filter(|&&n| self.degree[n] >= self.k).count() < self.k
+    }
+
+    fn coalesce(&mut self, m: usize) {
+        self.worklist_moves.remove(&m);
+        let (x, y) = self.moves[m];
+        let (x, y) = (self.get_alias(x), self.get_alias(y));
+        let (u, v) = if self.is_precolored(y) {
+            (y, x)
+        } else {
+            (x, y)
+        };
+        if u == v {
+            self.move_state[m] = MoveState::Coalesced;
+            self.add_worklist(u);
+        } else if self.is_precolored(v)
+            || self.adj_set.contains(&(u, v))
+            || (self.is_precolored(u) && self.prefers_callee_saved[v] && !self.callee_saved[u])
+        {
+            self.move_state[m] = MoveState::Constrained;
+            self.add_worklist(u);
+            self.add_worklist(v);
+        } else if (self.is_precolored(u) && self.george(u, v))
+            || (!self.is_precolored(u) && self.briggs(u, v))
+        {
+            self.move_state[m] = MoveState::Coalesced;
+            self.combine(u, v);
+            self.add_worklist(u);
+        } else {
+            self.move_state[m] = MoveState::Active;
+        }
+    }
+
+    fn combine(&mut self, u: usize, v: usize) {
+        if self.state[v] == NodeState::Freeze {
+            self.freeze.remove(&v);
+        } else {
+            self.spill.remove(&v);
+        }
+        self.state[v] = NodeState::Coalesced;
+        self.alias[v] = u;
+        let v_moves = self.move_list[v].clone();
+        self.move_list[u].extend(v_moves);
+        self.cost[u] += self.cost[v];
+        self.prefers_callee_saved[u] |= self.prefers_callee_saved[v];
+        self.enable_moves(&[v]);
+        for t in self.adjacent(v) {
+            self.add_edge(t, u);
+            self.decrement_degree(t);
+        }
+        if self.degree[u] >= self.k && self.state[u] == NodeState::Freeze {
+            self.freeze.remove(&u);
+            self.state[u] = NodeState::Spill;
+            self.spill.insert(u);
+        }
+    }
+
+    fn freeze_node(&mut self, node: usize) {
+        self.freeze.remove(&node);
+        self.state[node] = NodeState::Simplify;
+        self.simplify.insert(node);
+        self.freeze_moves(node);
+    }
+
+    fn freeze_moves(&mut self, u: usize) {
+        for m in self.node_moves(u) {
+            let (x, y) = self.moves[m];
+            let v = if self.get_alias(y) == self.get_alias(u) {
+                self.get_alias(x)
+            } else {
+                self.get_alias(y)
+            };
+            self.move_state[m] = MoveState::Frozen;
+            if !self.is_precolored(v)
+                && self.state[v] == NodeState::Freeze
+                && !self.move_related(v)
+                && self.degree[v] < self.k
+            {
+                self.freeze.remove(&v);
+                self.state[v] = NodeState::Simplify;
+                self.simplify.insert(v);
+            }
+        }
+    }
+
+    fn select_spill(&mut self) {
+        // least cost per interference, the lower index on ties
+        let node = *self
+            .spill
+            .iter()
+            .min_by(|&&a, &&b| {
+                let lhs = self.cost[a] as u128 * self.degree[b] as u128;
+                let rhs = self.cost[b] as u128 * self.degree[a] as u128;
+                lhs.cmp(&rhs)
+            })
+            .unwrap();
+        self.spill.remove(&node);
+        self.state[node] = NodeState::Simplify;
+        self.simplify.insert(node);
+        self.freeze_moves(node);
+    }
+
+    fn assign_colors(&mut self) {
+        while let Some(node) = self.select_stack.pop() {
+            let mut ok = vec![true; self.k];
+            for &w in self.adj_list[node].iter() {
+                let w = self.get_alias(w);
+                if matches!(self.state[w], NodeState::Colored | NodeState::Precolored) {
+                    ok[self.color[w].unwrap()] = false;
+                }
+            }
+            // prefer the color of the other end of a move, so the move disappears
+            let preferred = self.move_list[node].iter().find_map(|&m| {
+                let (x, y) = self.moves[m];
+                let other = if self.get_alias(x) == node {
+                    self.get_alias(y)
+                } else {
+                    self.get_alias(x)
+                };
+                self.color[other].filter(|&color| ok[color])
+            });
+            let any = (0..self.k).find(|&color| ok[color]);
+            let color = if self.prefers_callee_saved[node] {
+                let callee_saved = |color: &usize| self.callee_saved[*color];
+                preferred
+                    .filter(callee_saved)
+                    .or_else(|| (0..self.k).filter(callee_saved).find(|&color| ok[color]))
+                    .or(preferred)
+                    .or(any)
+            } else {
+                preferred.or(any)
+            };
+            match color {
+                Some(color) => {
+                    self.state[node] = NodeState::Colored;
+                    self.color[node] = Some(color);
+                }
+                None => self.state[node] = NodeState::Spilled,
+            }
+        }
+        for node in self.k..self.state.len() {
+            if self.state[node] == NodeState::Coalesced {
+                self.color[node] = self.color[self.get_alias(node)];
+            }
+        }
+    }
+}
@@ -0,0 +1,58 @@
+use super::liveness::{live_intervals, BlockLiveness, Interval};
+use super::{block_weights, hint, is_callee_saved, prefer_callee_saved, Allocation};
+use crate::asm::Reg;
+use koopa::ir::FunctionData;
+
+// ============ Linear Scan ============
+/*
+    Poletto & Sarkar: intervals are visited by increasing start, and those
+    ending before the current start give back their registers. When no
+    register is free, the interval ending last among the active ones and the
+    current one is spilled, as it holds its register the longest. Values crossing calls
+    often prefer callee-saved registers; otherwise parameters prefer the
+    register they arrive in, call results a0.
+*/
+
+pub fn linear_scan(data: &FunctionData, regs: &[Reg]) -> Allocation {
+    let blocks = BlockLiveness::new(data);
+    let across = blocks.live_across_calls(data);
+    let callee_saved = prefer_callee_saved(data, &across, &block_weights(data));
+    let mut allocation = Allocation::new();
+    // free registers in order of preference
+    let mut free: Vec<Reg> = regs.to_vec();
+    let mut active: Vec<(Interval, Reg)> = Vec::new();
+    for interval in live_intervals(data, &blocks) {
+        active.retain(|&(other, reg)| {
+            let live = other.end >= interval.start;
+            if !live {
+                free.push(reg);
+            }
+            live
+        });
+        if !free.is_empty() {
+            free.sort_by_key(|reg| regs.iter().position(|r| r == reg));
+            let index = if callee_saved.contains(&interval.value) {
+                free.iter().position(|&reg| is_callee_saved(reg))
+            } else {
+                hint(data, interval.value).and_then(|hint| free.iter().position(|&reg| reg == hint))
+            }
+            .unwrap_or(0);
+            let reg = free.remove(index);
+            allocation.regs.insert(interval.value, reg);
+            active.push((interval, reg));
+            continue;
+        }
+        let victim = (0..active.len()).max_by_key(|&i| active[i].0.end).unwrap();
+        if active[victim].0.end > interval.end {
+            let (spilled, reg) = active.remove(victim);
+            allocation.regs.remove(&spilled.value);
+            allocation.spilled.push(vec![spilled.value]);
+            allocation.regs.insert(interval.value, reg);
+            active.push((interval, reg));
+        } else {
+            allocation.spilled.push(vec![interval.value]);
+        }
+    }
+    allocation.find_saved(&blocks, across);
+    allocation
+}
@@ -0,0 +1,224 @@
+use crate::opt::util::successors;
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+// ============ Liveness ============
+/*
+    Live-in/out sets of the blocks come from the usual backward dataflow over
+    the values needing a location; jump arguments are read at the terminator
+    and block parameters defined at the block start. Within a block the live
+    values are recomputed backwards from the live-out set.
+*/
+
+pub struct BlockLiveness {
+    // blocks in layout order
+    pub bbs: Vec<BasicBlock>,
+    pub live_in: HashMap<BasicBlock, HashSet<Value>>,
+    pub live_out: HashMap<BasicBlock, HashSet<Value>>,
+    // parameters, block parameters and instructions numbered in layout order,
+    // to sort sets of values deterministically
+    pub index: HashMap<Value, usize>,
+}
+
+// whether `value` lives in a register or a spill slot: parameters and used
+// results, allocs are addressed through sp
+pub fn needs_location(data: &FunctionData, value: Value) -> bool {
+    if value.is_global() {
+        return false;
+    }
+    let value_data = data.dfg().value(value);
+    matches!(
+        value_data.kind(),
+        ValueKind::FuncArgRef(_)
+            | ValueKind::BlockArgRef(_)
+            | ValueKind::Load(_)
+            | ValueKind::Binary(_)
+            | ValueKind::Call(_)
+            | ValueKind::GetPtr(_)
+            | ValueKind::GetElemPtr(_)
+    ) && !value_data.ty().is_unit()
+        && !value_data.used_by().is_empty()
+}
+
+// operands of `inst` needing a location
+pub fn operands(data: &FunctionData, inst: Value) -> Vec<Value> {
+    data.dfg()
+        .value(inst)
+        .kind()
+        .value_uses()
+        .filter(|&operand| needs_location(data, operand))
+        .collect()
+}
+
+pub fn insts_of(data: &FunctionData, bb: BasicBlock) -> Vec<Value> {
+    data.layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .keys()
+        .copied()
+        .collect()
+}
+
+impl BlockLiveness {
+    pub fn new(data: &FunctionData) -> Self {
+        let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().collect();
+
+        // ---- upward exposed uses and definitions of each block ----
+        let mut uses: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+        let mut defs: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+        for &bb in bbs.iter() {
+            let mut bb_uses = HashSet::new();
+            let mut bb_defs: HashSet<Value> = data.dfg().bb(bb).params().iter().copied().collect();
+            for inst in insts_of(data, bb) {
+                for operand in operands(data, inst) {
+                    if !bb_defs.contains(&operand) {
+                        bb_uses.insert(operand);
+                    }
+                }
+                bb_defs.insert(inst);
+            }
+            uses.insert(bb, bb_uses);
+            defs.insert(bb, bb_defs);
+        }
+
+        // ---- live-in / live-out ----
+        let succs: HashMap<BasicBlock, Vec<BasicBlock>> =
+            bbs.iter().map(|&bb| (bb, successors(data, bb))).collect();
+        let mut live_in: HashMap<BasicBlock, HashSet<Value>> =
+            bbs.iter().map(|&bb| (bb, HashSet::new())).collect();
+        let mut live_out = live_in.clone();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &bb in bbs.iter().rev() {
+                let out: HashSet<Value> = succs[&bb]
+                    .iter()
+                    .flat_map(|succ| live_in[succ].iter().copied())
+                    .collect();
+                let mut new_in: HashSet<Value> = out.difference(&defs[&bb]).copied().collect();
+                new_in.extend(uses[&bb].iter().copied());
+                if new_in.len() != live_in[&bb].len() {
+                    live_in.insert(bb, new_in);
+                    changed = true;
+                }
+                live_out.insert(bb, out);
+            }
+        }
+
+        let index: HashMap<Value, usize> = data
+            .params()
+            .iter()
+            .copied()
+            .chain(bbs.iter().flat_map(|&bb| {
+                let params = data.dfg().bb(bb).params().iter().copied();
+                params.chain(insts_of(data, bb))
+            }))
+            .enumerate()
+            .map(|(i, value)| (value, i))
+            .collect();
+        BlockLiveness {
+            bbs,
+            live_in,
+            live_out,
+            index,
+        }
+    }
+
+    // the values of `set` in layout order
+    pub fn sorted<'a>(&self, set: impl IntoIterator<Item = &'a Value>) -> Vec<Value> {
+        let mut values: Vec<Value> = set.into_iter().copied().collect();
+        values.sort_by_key(|value| self.index[value]);
+        values
+    }
+
+    // walk each block backwards, calling `f` with every instruction and the values live right after it
+    pub fn walk_insts(&self, data: &FunctionData, mut f: impl FnMut(Value, &HashSet<Value>)) {
+        for &bb in self.bbs.iter() {
+            let mut live = self.live_out[&bb].clone();
+            for inst in insts_of(data, bb).into_iter().rev() {
+                f(inst, &live);
+                live.remove(&inst);
+                live.extend(operands(data, inst));
+            }
+        }
+    }
+
+    // the values live across each call, which clobbers every caller-saved register
+    pub fn live_across_calls(&self, data: &FunctionData) -> HashMap<Value, Vec<Value>> {
+        let mut across = HashMap::new();
+        self.walk_insts(data, |inst, live| {
+            if let ValueKind::Call(_) = data.dfg().value(inst).kind() {
+                let values = self.sorted(live.iter().filter(|&&value| value != inst));
+                across.insert(inst, values);
+            }
+        });
+        across
+    }
+}
+
+// ============ Live Intervals ============
+/*
+    For the linear scan, blocks are numbered in layout order: a block takes
+    one position for its parameters, then one per instruction. The interval of
+    a value is the smallest one covering every position it is live at, without
+    lifetime holes. Operands are read at the position of the instruction,
+    where its result is also defined, so a result never shares a register
+    with an operand.
+*/
+
+#[derive(Debug, Clone, Copy)]
+pub struct Interval {
+    pub value: Value,
+    pub start: usize,
+    pub end: usize,
+}
+
+// sorted by start, in layout order for equal starts
+pub fn live_intervals(data: &FunctionData, blocks: &BlockLiveness) -> Vec<Interval> {
+    let mut ranges: HashMap<Value, (usize, usize)> = HashMap::new();
+    let mut touch = |value: Value, pos: usize| {
+        let range = ranges.entry(value).or_insert((pos, pos));
+        range.0 = range.0.min(pos);
+        range.1 = range.1.max(pos);
+    };
+    for &param in data.params() {
+        if needs_location(data, param) {
+            touch(param, 0);
+        }
+    }
+    let mut pos = 0;
+    for &bb in blocks.bbs.iter() {
+        let start = pos;
+        for &param in data.dfg().bb(bb).params() {
+            if needs_location(data, param) {
+                touch(param, start);
+            }
+        }
+        for inst in insts_of(data, bb) {
+            pos += 1;
+            for operand in operands(data, inst) {
+                touch(operand, pos);
+            }
+            if needs_location(data, inst) {
+                touch(inst, pos);
+            }
+        }
+        // values live before their definition in the block start at its top
+        for &value in blocks.live_in[&bb].iter() {
+            touch(value, start);
+        }
+        for &value in blocks.live_out[&bb].iter() {
+            touch(value, pos);
+        }
+        pos += 1;
+    }
+
+    let mut intervals: Vec<Interval> = ranges
+        .into_iter()
+        .map(|(value, (start, end))| Interval { value, start, end })
+        .collect();
+    intervals.sort_by_key(|interval| (interval.start, blocks.index[&interval.value]));
+    intervals
+}
@@ -0,0 +1,140 @@
+mod coloring;
+mod linear_scan;
+mod liveness;
+mod moves;
+pub use coloring::graph_coloring;
+pub use linear_scan::linear_scan;
+pub use moves::{move_to, parallel_moves, Location};
+
+use super::Reg;
+use crate::opt::analysis::{Cfg, DomTree, LoopInfo};
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use liveness::BlockLiveness;
+use std::collections::{HashMap, HashSet};
+
+/*
+    Registers of the linear scan and graph coloring allocators: the
+    caller-saved a0 ~ a7, t0 ~ t3 first, then the callee-saved s1 ~ s11.
+    t4, t5 hold spilled operands and break cycles of parallel moves,
+    t6 is used for large imm value as before, s0 is kept for the frame pointer
+*/
+pub static ALLOC_REGS: [Reg; 23] = [
+    "a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7", "t0", "t1", "t2", "t3", "s1", "s2", "s3", "s4",
+    "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+];
+
+pub static CALLEE_SAVED_REGS: [Reg; 11] = [
+    "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11",
+];
+
+pub static SCRATCH_REGS: [Reg; 2] = ["t4", "t5"];
+
+pub static ARG_REGS: [Reg; 8] = ["a0", "a1", "a2", "a3", "a4", "a5", "a6", "a7"];
+
+/*
+    A caller-saved register value living across a call gets a home slot: it is
+    stored there when defined and reloaded after each call it crosses. Values
+    crossing calls often enough (weighted by loop depth) go to callee-saved
+    registers instead, saved by the prologue and restored by the epilogue,
+    which costs a save and a restore per invocation of the function.
+*/
+pub struct Allocation {
+    pub regs: HashMap<Value, Reg>,
+    // spilled values, the values of a group share one stack slot
+    pub spilled: Vec<Vec<Value>>,
+    // register values living across some call, which need a home slot
+    pub saved: Vec<Value>,
+    // the register values to reload after each call
+    pub live_across: HashMap<Value, Vec<Value>>,
+}
+
+impl Allocation {
+    fn new() -> Self {
+        Allocation {
+            regs: HashMap::new(),
+            spilled: Vec::new(),
+            saved: Vec::new(),
+            live_across: HashMap::new(),
+        }
+    }
+
+    fn find_saved(&mut self, blocks: &BlockLiveness, across: HashMap<Value, Vec<Value>>) {
+        let mut calls: Vec<(Value, Vec<Value>)> = across.into_iter().collect();
+        calls.sort_by_key(|(call, _)| blocks.index[call]);
+        for (call, mut values) in calls {
+            values.retain(|value| {
+                self.regs
+                    .get(value)
+                    .is_some_and(|&reg| !is_callee_saved(reg))
+            });
+            for &value in values.iter() {
+                if !self.saved.contains(&value) {
+                    self.saved.push(value);
+                }
+            }
+            self.live_across.insert(call, values);
+        }
+    }
+
+    // the callee-saved registers used, in the order of CALLEE_SAVED_REGS
+    pub fn callee_saved(&self) -> Vec<Reg> {
+        CALLEE_SAVED_REGS
+            .iter()
+            .copied()
+            .filter(|reg| self.regs.values().any(|r| r == reg))
+            .collect()
+    }
+}
+
+pub fn is_callee_saved(reg: Reg) -> bool {
+    CALLEE_SAVED_REGS.contains(&reg)
+}
+
+const LOOP_WEIGHT: usize = 10;
+const MAX_LOOP_DEPTH: usize = 4;
+// weighted number of calls crossed above which a callee-saved register pays off
+const CALLEE_SAVED_THRESHOLD: usize = 2;
+
+// the weight of each block, growing with the loop depth
+fn block_weights(data: &FunctionData) -> HashMap<BasicBlock, usize> {
+    let cfg = Cfg::new(data).unwrap();
+    let dom = DomTree::new(&cfg);
+    let loops = LoopInfo::new(&cfg, &dom);
+    data.layout()
+        .bbs()
+        .keys()
+        .map(|&bb| {
+            let depth = loops.depth(bb).min(MAX_LOOP_DEPTH) as u32;
+            (bb, LOOP_WEIGHT.pow(depth))
+        })
+        .collect()
+}
+
+// the values that had better live in a callee-saved register
+fn prefer_callee_saved(
+    data: &FunctionData,
+    across: &HashMap<Value, Vec<Value>>,
+    weights: &HashMap<BasicBlock, usize>,
+) -> HashSet<Value> {
+    let mut crossed: HashMap<Value, usize> = HashMap::new();
+    for (&call, values) in across.iter() {
+        let weight = weights[&data.layout().parent_bb(call).unwrap()];
+        for &value in values.iter() {
+            *crossed.entry(value).or_insert(0) += weight;
+        }
+    }
+    crossed
+        .into_iter()
+        .filter(|&(_, weight)| weight > CALLEE_SAVED_THRESHOLD)
+        .map(|(value, _)| value)
+        .collect()
+}
+
+// the register a value would like to be in
+fn hint(data: &FunctionData, value: Value) -> Option<Reg> {
+    match data.dfg().value(value).kind() {
+        ValueKind::FuncArgRef(arg) if arg.index() < 8 => Some(ARG_REGS[arg.index()]),
+        ValueKind::Call(_) => Some("a0"),
+        _ => None,
+    }
+}
@@ -0,0 +1,58 @@
+use super::SCRATCH_REGS;
+use crate::asm::machine::MachineFunc;
+use crate::asm::Reg;
+
+// ============ Parallel Moves ============
+/*
+    Block arguments and call arguments are copied all at once: a move is
+    emitted when no pending move still reads its destination. Only cycles
+    remain otherwise, one is broken by saving a destination in t5 and reading
+    it from there instead. Stack to stack moves go through t4.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    Reg(Reg),
+    // offset from sp
+    Stack(i32),
+}
+
+pub fn parallel_moves(moves: &[(Location, Location)], asm: &mut MachineFunc) {
+    let mut pending: Vec<(Location, Location)> = moves
+        .iter()
+        .copied()
+        .filter(|(dest, src)| dest != src)
+        .collect();
+    let cycle_temp = Location::Reg(SCRATCH_REGS[1]);
+    while !pending.is_empty() {
+        let ready =
+            (0..pending.len()).find(|&i| pending.iter().all(|&(_, src)| src != pending[i].0));
+        match ready {
+            Some(i) => {
+                let (dest, src) = pending.remove(i);
+                move_to(dest, src, asm);
+            }
+            None => {
+                let dest = pending[0].0;
+                move_to(cycle_temp, dest, asm);
+                for (_, src) in pending.iter_mut() {
+                    if *src == dest {
+                        *src = cycle_temp;
+                    }
+                }
+            }
+        }
+    }
+}
+
+pub fn move_to(dest: Location, src: Location, asm: &mut MachineFunc) {
+    match (dest, src) {
+        (Location::Reg(dest), Location::Reg(src)) => asm.mv(dest, src),
+        (Location::Reg(dest), Location::Stack(offset)) => asm.lw(dest, "sp", offset),
+        (Location::Stack(offset), Location::Reg(src)) => asm.sw(src, "sp", offset),
+        (Location::Stack(dest), Location::Stack(src)) => {
+            asm.lw(SCRATCH_REGS[0], "sp", src);
+            asm.sw(SCRATCH_REGS[0], "sp", dest);
+        }
+    }
+}
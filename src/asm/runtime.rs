@@ -0,0 +1,229 @@
+use super::config::AsmConfig;
+use super::machine::{BinOp, Cond, MachineFunc};
+use super::Asm;
+use crate::ir::{TrapKind, MEMCPY_FUNC, MEMSET_FUNC, TRAP_FUNC};
+use koopa::ir::Program;
+
+/*
+    Runtime support emitted together with the program.
+    __sysy_trap(kind, line, column) writes
+        "runtime error: <message> at line <line>, column <column>\n"
+    to stderr and exits with TRAP_EXIT_CODE. It never returns, so it
+    clobbers registers freely and keeps its buffer below sp.
+    __sysy_stack_overflow is jumped to from prologues under -fstack-check,
+    it must not touch the (already exhausted) stack.
+    __sysy_memset / __sysy_memcpy initialize local arrays, they only use
+    caller-saved registers and no stack.
+*/
+
+pub const TRAP_EXIT_CODE: i32 = 134;
+
+pub const STACK_LIMIT: &str = "__sysy_stack_limit";
+pub const STACK_SIZE: &str = "__sysy_stack_size";
+pub const STACK_OVERFLOW: &str = "__sysy_stack_overflow";
+const STACK_OVERFLOW_MSG: &str = "runtime error: stack overflow";
+
+// whether the program declares the runtime function `name`
+pub fn needs_func(prog: &Program, name: &str) -> bool {
+    let name = format!("@{}", name);
+    prog.func_layout()
+        .iter()
+        .any(|&func| prog.func(func).name() == name)
+}
+
+// whether the program declares the trap function (i.e. was built with -fsanitize)
+pub fn needs_trap(prog: &Program) -> bool {
+    needs_func(prog, TRAP_FUNC)
+}
+
+pub fn trap_to_asm() -> Asm {
+    let mut asm = Asm::new();
+    // messages
+    asm += "  .data\n";
+    asm += &format!("{}_prefix:\n  .asciz \"runtime error: \"\n", TRAP_FUNC);
+    for (i, kind) in TrapKind::ALL.iter().enumerate() {
+        asm += &format!("{}_msg{}:\n  .asciz \"{}\"\n", TRAP_FUNC, i, kind.message());
+    }
+    asm += &format!("{}_line:\n  .asciz \" at line \"\n", TRAP_FUNC);
+    asm += &format!("{}_col:\n  .asciz \", column \"\n", TRAP_FUNC);
+    asm += "\n  .text\n";
+
+    // a3 = line, a4 = column, t5 = write cursor into the buffer at sp
+    asm += &format!("{}:\n", TRAP_FUNC);
+    asm += "  addi sp, sp, -128\n";
+    asm += "  mv t5, sp\n";
+    asm += "  mv a5, a0\n";
+    asm += "  mv a3, a1\n";
+    asm += "  mv a4, a2\n";
+    asm += &format!("  la a0, {}_prefix\n", TRAP_FUNC);
+    asm += &format!("  call {}_puts\n", TRAP_FUNC);
+    for i in 0..TrapKind::ALL.len() {
+        asm += &format!("  la a0, {}_msg{}\n", TRAP_FUNC, i);
+        asm += &format!("  li t0, {}\n", i);
+        asm += &format!("  beq a5, t0, {}_kind\n", TRAP_FUNC);
+    }
+    asm += &format!("{}_kind:\n", TRAP_FUNC);
+    asm += &format!("  call {}_puts\n", TRAP_FUNC);
+    asm += &format!("  la a0, {}_line\n", TRAP_FUNC);
+    asm += &format!("  call {}_puts\n", TRAP_FUNC);
+    asm += "  mv a0, a3\n";
+    asm += &format!("  call {}_putu\n", TRAP_FUNC);
+    asm += &format!("  la a0, {}_col\n", TRAP_FUNC);
+    asm += &format!("  call {}_puts\n", TRAP_FUNC);
+    asm += "  mv a0, a4\n";
+    asm += &format!("  call {}_putu\n", TRAP_FUNC);
+    asm += "  li t0, 10\n";
+    asm += "  sb t0, 0(t5)\n";
+    asm += "  addi t5, t5, 1\n";
+    // write(2, buf, len); exit(TRAP_EXIT_CODE)
+    asm += "  li a0, 2\n";
+    asm += "  mv a1, sp\n";
+    asm += "  sub a2, t5, sp\n";
+    asm += "  li a7, 64\n";
+    asm += "  ecall\n";
+    asm += &format!("  li a0, {}\n", TRAP_EXIT_CODE);
+    asm += "  li a7, 93\n";
+    asm += "  ecall\n";
+
+    // append the NUL-terminated string at a0
+    asm += &format!("{}_puts:\n", TRAP_FUNC);
+    asm += "  lbu t0, 0(a0)\n";
+    asm += &format!("  beqz t0, {}_puts_end\n", TRAP_FUNC);
+    asm += "  sb t0, 0(t5)\n";
+    asm += "  addi t5, t5, 1\n";
+    asm += "  addi a0, a0, 1\n";
+    asm += &format!("  j {}_puts\n", TRAP_FUNC);
+    asm += &format!("{}_puts_end:\n", TRAP_FUNC);
+    asm += "  ret\n";
+
+    // append the unsigned decimal a0, digits are built backwards at the end of the buffer
+    asm += &format!("{}_putu:\n", TRAP_FUNC);
+    asm += "  addi t1, sp, 127\n";
+    asm += "  mv t2, t1\n";
+    asm += "  li t0, 10\n";
+    asm += &format!("{}_putu_digit:\n", TRAP_FUNC);
+    asm += "  remu t3, a0, t0\n";
+    asm += "  addi t3, t3, 48\n";
+    asm += "  sb t3, 0(t2)\n";
+    asm += "  addi t2, t2, -1\n";
+    asm += "  divu a0, a0, t0\n";
+    asm += &format!("  bnez a0, {}_putu_digit\n", TRAP_FUNC);
+    asm += &format!("{}_putu_copy:\n", TRAP_FUNC);
+    asm += "  addi t2, t2, 1\n";
+    asm += "  lbu t3, 0(t2)\n";
+    asm += "  sb t3, 0(t5)\n";
+    asm += "  addi t5, t5, 1\n";
+    asm += &format!("  bne t2, t1, {}_putu_copy\n", TRAP_FUNC);
+    asm += "  ret\n";
+    asm
+}
+
+// ============ Array Initialization ============
+
+// a0 = dest, a1 = value, a2 = words; 4 words per iteration, then one at a time
+pub fn memset_to_asm() -> Asm {
+    let mut asm = Asm::new();
+    asm += &format!("{}:\n", MEMSET_FUNC);
+    // a2 = end, t0 = end - 12, a whole group fits while a0 < t0
+    asm += "  slli a2, a2, 2\n";
+    asm += "  add a2, a0, a2\n";
+    asm += "  addi t0, a2, -12\n";
+    asm += &format!("  bgeu a0, t0, {}_tail\n", MEMSET_FUNC);
+    asm += &format!("{}_group:\n", MEMSET_FUNC);
+    for i in 0..4 {
+        asm += &format!("  sw a1, {}(a0)\n", i * 4);
+    }
+    asm += "  addi a0, a0, 16\n";
+    asm += &format!("  bltu a0, t0, {}_group\n", MEMSET_FUNC);
+    asm += &format!("{}_tail:\n", MEMSET_FUNC);
+    asm += &format!("  bgeu a0, a2, {}_end\n", MEMSET_FUNC);
+    asm += "  sw a1, 0(a0)\n";
+    asm += "  addi a0, a0, 4\n";
+    asm += &format!("  j {}_tail\n", MEMSET_FUNC);
+    asm += &format!("{}_end:\n", MEMSET_FUNC);
+    asm += "  ret\n";
+    asm
+}
+
+// a0 = dest, a1 = src, a2 = words
+pub fn memcpy_to_asm() -> Asm {
+    let mut asm = Asm::new();
+    asm += &format!("{}:\n", MEMCPY_FUNC);
+    asm += "  slli a2, a2, 2\n";
+    asm += "  add a2, a0, a2\n";
+    asm += "  addi t0, a2, -12\n";
+    asm += &format!("  bgeu a0, t0, {}_tail\n", MEMCPY_FUNC);
+    asm += &format!("{}_group:\n", MEMCPY_FUNC);
+    for i in 0..4 {
+        asm += &format!("  lw t{}, {}(a1)\n", i + 1, i * 4);
+    }
+    for i in 0..4 {
+        asm += &format!("  sw t{}, {}(a0)\n", i + 1, i * 4);
+    }
+    asm += "  addi a0, a0, 16\n";
+    asm += "  addi a1, a1, 16\n";
+    asm += &format!("  bltu a0, t0, {}_group\n", MEMCPY_FUNC);
+    asm += &format!("{}_tail:\n", MEMCPY_FUNC);
+    asm += &format!("  bgeu a0, a2, {}_end\n", MEMCPY_FUNC);
+    asm += "  lw t1, 0(a1)\n";
+    asm += "  sw t1, 0(a0)\n";
+    asm += "  addi a0, a0, 4\n";
+    asm += "  addi a1, a1, 4\n";
+    asm += &format!("  j {}_tail\n", MEMCPY_FUNC);
+    asm += &format!("{}_end:\n", MEMCPY_FUNC);
+    asm += "  ret\n";
+    asm
+}
+
+// ============ Stack Check ============
+
+// __sysy_stack_size is weak so that another object can define the size at link time
+pub fn stack_data_to_asm(config: &AsmConfig) -> Asm {
+    let mut asm = Asm::new();
+    asm += &format!("  .weak {}\n{}:\n", STACK_SIZE, STACK_SIZE);
+    asm += &format!("  .word {}\n\n", config.stack_size);
+    asm += &format!("  .global {}\n{}:\n", STACK_LIMIT, STACK_LIMIT);
+    asm += "  .word 0\n\n";
+    asm
+}
+
+// main records the lowest allowed sp before allocating its own frame
+pub fn stack_limit_init_to_asm(asm: &mut MachineFunc) {
+    asm.la("t0", STACK_SIZE);
+    asm.lw("t0", "t0", 0);
+    asm.bin(BinOp::Sub, "t0", "sp", "t0");
+    asm.la("t6", STACK_LIMIT);
+    asm.sw("t0", "t6", 0);
+}
+
+// after sp is updated; only t6 is used so the arguments in a0-a7 survive.
+// the handler may be out of branch range, so it is reached through `j`
+pub fn stack_check_to_asm(func_name: &str, asm: &mut MachineFunc) {
+    asm.la("t6", STACK_LIMIT);
+    asm.lw("t6", "t6", 0);
+    let stack_ok = format!("{}_stack_ok", func_name);
+    asm.branch(Cond::Geu, "sp", "t6", &stack_ok);
+    asm.j(STACK_OVERFLOW);
+    asm.label(&stack_ok);
+}
+
+pub fn stack_overflow_to_asm() -> Asm {
+    let mut asm = Asm::new();
+    asm += "  .data\n";
+    asm += &format!(
+        "{}_msg:\n  .asciz \"{}\\n\"\n",
+        STACK_OVERFLOW, STACK_OVERFLOW_MSG
+    );
+    asm += "\n  .text\n";
+    asm += &format!("{}:\n", STACK_OVERFLOW);
+    // write(2, msg, len); exit(TRAP_EXIT_CODE)
+    asm += "  li a0, 2\n";
+    asm += &format!("  la a1, {}_msg\n", STACK_OVERFLOW);
+    asm += &format!("  li a2, {}\n", STACK_OVERFLOW_MSG.len() + 1);
+    asm += "  li a7, 64\n";
+    asm += "  ecall\n";
+    asm += &format!("  li a0, {}\n", TRAP_EXIT_CODE);
+    asm += "  li a7, 93\n";
+    asm += "  ecall\n";
+    asm
+}
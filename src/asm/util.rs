@@ -1,4 +1,4 @@
-use super::gen_riscv::*;
+use super::machine::MachineFunc;
 use super::{Addr, Asm, Reg, REG_LIST};
 use koopa::ir::entities::{BasicBlockData, ValueData};
 use koopa::ir::types::TypeKind;
@@ -6,7 +6,7 @@ use koopa::ir::values::Aggregate;
 use koopa::ir::{BasicBlock, FunctionData, Program, Value, ValueKind};
 use std::collections::HashMap;
 
-pub fn get_value_data<'a>(func_data: &'a FunctionData, value: Value) -> &'a ValueData {
+pub fn get_value_data(func_data: &FunctionData, value: Value) -> &ValueData {
     func_data.dfg().value(value)
 }
 
@@ -54,7 +54,7 @@ impl ValueTable {
     }
 
     pub fn get_value_addr(&self, value: &Value) -> Option<Addr> {
-        self.value_addr.get(value).map(|v| *v)
+        self.value_addr.get(value).copied()
     }
 
     pub fn get_reg_status(&self, reg: &Reg) -> RegStatus {
@@ -62,7 +62,7 @@ impl ValueTable {
     }
 
     pub fn get_value_reg(&self, value: &Value) -> Option<Reg> {
-        self.value_reg.get(&value).unwrap().clone()
+        *self.value_reg.get(value).unwrap()
     }
 
     // lock reg, so that it cannot be used by other values
@@ -92,24 +92,25 @@ impl ValueTable {
         }
     }
 
-    pub fn get_free_reg(&mut self, asm: &mut Asm) -> Reg {
-        for (reg, v) in self.reg_status.iter() {
-            if *v == RegStatus::Free && !self.reg_is_locked(reg) {
+    // registers are tried in REG_LIST order, not in HashMap iteration order
+    pub fn get_free_reg(&mut self, asm: &mut MachineFunc) -> Reg {
+        for reg in REG_LIST.iter() {
+            if self.reg_status[reg] == RegStatus::Free && !self.reg_is_locked(reg) {
                 return reg;
             }
         }
         let mut reg_to_free = None;
-        for (reg, v) in self.reg_status.iter() {
+        for reg in REG_LIST.iter() {
             if !self.reg_is_locked(reg) {
-                if let RegStatus::Temp = v {
+                if let RegStatus::Temp = self.reg_status[reg] {
                     reg_to_free = Some(*reg);
                     break;
                 }
             }
         }
 
-        if let None = reg_to_free {
-            for (reg, _) in self.reg_status.iter() {
+        if reg_to_free.is_none() {
+            for reg in REG_LIST.iter() {
                 if !self.reg_is_locked(reg) {
                     reg_to_free = Some(*reg);
                     break;
@@ -118,13 +119,13 @@ impl ValueTable {
         }
         if let Some(reg) = reg_to_free {
             self.free_reg(&reg, asm);
-            return reg;
+            reg
         } else {
             panic!("all regs are locked");
         }
     }
 
-    pub fn allocate_value_to_reg(&mut self, value: &Value, asm: &mut Asm) -> Reg {
+    pub fn allocate_value_to_reg(&mut self, value: &Value, asm: &mut MachineFunc) -> Reg {
         // if value is already in reg, return reg
         if let Some(reg) = self.get_value_reg(value) {
             self.lock_reg(&reg);
@@ -139,7 +140,7 @@ impl ValueTable {
             self.reg_status.insert(reg, RegStatus::Used(*value));
             self.lock_reg(&reg);
 
-            return reg;
+            reg
         } else {
             panic!("value is not in stack");
         }
@@ -149,7 +150,7 @@ impl ValueTable {
         &mut self,
         value: &Value,
         value_data: &ValueData,
-        asm: &mut Asm,
+        asm: &mut MachineFunc,
     ) -> Reg {
         match value_data.kind() {
             ValueKind::Integer(num) => self.assign_temp_to_reg(num.value(), asm),
@@ -168,8 +169,8 @@ impl ValueTable {
                     self.reg_status.insert(reg, RegStatus::Used(*value));
                     self.lock_reg(&reg);
 
-                    riscv_lw(reg, "sp", offset, asm);
-                    return reg;
+                    asm.lw(reg, "sp", offset);
+                    reg
                 } else {
                     panic!("value is not in stack");
                 }
@@ -188,38 +189,38 @@ impl ValueTable {
         value: &Value,
         value_data: &ValueData,
         reg: &Reg,
-        asm: &mut Asm,
+        asm: &mut MachineFunc,
     ) -> Reg {
         match value_data.kind() {
             ValueKind::Integer(num) => {
                 self.assign_temp_to_specified_reg(num.value(), reg, asm);
-                return *reg;
+                reg
             }
             _ => {
                 if let Some(value_reg) = self.get_value_reg(value) {
                     if value_reg == *reg {
                         self.lock_reg(reg);
-                        return *reg;
+                        return reg;
                     }
                 }
                 // free specified reg
                 self.free_reg(reg, asm);
                 // load value to specified reg
                 if let Some(value_reg) = self.get_value_reg(value) {
-                    riscv_mv(reg, value_reg, asm);
+                    asm.mv(reg, value_reg);
                     self.value_reg.insert(*value, Some(reg));
                     self.reg_status.insert(reg, RegStatus::Used(*value));
                     self.reg_status.insert(value_reg, RegStatus::Free);
                     self.lock_reg(reg);
-                    return reg;
+                    reg
                 } else {
                     let addr = self.get_value_addr(value);
                     if let Some(offset) = addr {
                         self.value_reg.insert(*value, Some(reg));
                         self.reg_status.insert(reg, RegStatus::Used(*value));
-                        self.lock_reg(&reg);
-                        riscv_lw(reg, "sp", offset, asm);
-                        return reg;
+                        self.lock_reg(reg);
+                        asm.lw(reg, "sp", offset);
+                        reg
                     } else {
                         panic!("value is not in stack");
                     }
@@ -228,7 +229,7 @@ impl ValueTable {
         }
     }
 
-    pub fn assign_temp_to_reg(&mut self, temp: i32, asm: &mut Asm) -> Reg {
+    pub fn assign_temp_to_reg(&mut self, temp: i32, asm: &mut MachineFunc) -> Reg {
         if temp == 0 {
             return "x0";
         }
@@ -236,17 +237,37 @@ impl ValueTable {
         let reg = self.get_free_reg(asm);
         self.reg_status.insert(reg, RegStatus::Temp);
         self.lock_reg(&reg);
-        asm.push_str(&format!("  li {}, {}\n", reg, temp));
+        asm.li(reg, temp);
         reg
     }
 
-    pub fn assign_temp_to_specified_reg(&mut self, temp: i32, reg: &Reg, asm: &mut Asm) {
+    pub fn assign_temp_to_specified_reg(&mut self, temp: i32, reg: &Reg, asm: &mut MachineFunc) {
         // free specified reg
         self.free_reg(reg, asm);
         // assign temp to specified reg
         self.reg_status.insert(*reg, RegStatus::Temp);
         self.lock_reg(reg);
-        asm.push_str(&format!("  li {}, {}\n", reg, temp));
+        asm.li(reg, temp);
+    }
+
+    // load into `reg` without binding it to the value; all registers must be spilled
+    pub fn load_value_to_unbound_reg(
+        &mut self,
+        value: &Value,
+        value_data: &ValueData,
+        reg: &Reg,
+        asm: &mut MachineFunc,
+    ) {
+        match value_data.kind() {
+            ValueKind::Integer(num) => {
+                asm.li(reg, num.value());
+            }
+            _ => {
+                assert!(self.get_value_reg(value).is_none(), "value is still in a reg");
+                let offset = self.get_value_addr(value).expect("value is not in stack");
+                asm.lw(reg, "sp", offset);
+            }
+        }
     }
 
     pub fn alloc_value(&mut self, value: Value, offset: i32) {
@@ -254,7 +275,7 @@ impl ValueTable {
         self.value_reg.insert(value, None);
     }
 
-    pub fn free_reg(&mut self, reg: &Reg, asm: &mut Asm) {
+    pub fn free_reg(&mut self, reg: &Reg, asm: &mut MachineFunc) {
         if *reg == "x0" {
             return;
         }
@@ -269,7 +290,7 @@ impl ValueTable {
                     self.value_reg.insert(value, None);
                     let addr = self.get_value_addr(&value).unwrap();
                     if addr != PARAM_ADDR && addr != GLOBL_ADDR {
-                        riscv_sw(reg, "sp", addr, asm);
+                        asm.sw(reg, "sp", addr);
                     }
                 }
 
@@ -281,9 +302,19 @@ impl ValueTable {
         }
         self.unlock_reg(reg);
     }
-    pub fn free_regs(&mut self, regs: &Vec<Reg>, asm: &mut Asm) {
+    // forget all bindings at a block start, every terminator has spilled the registers
+    pub fn reset_regs(&mut self) {
+        for status in self.reg_status.values_mut() {
+            *status = RegStatus::Free;
+        }
+        for reg in self.value_reg.values_mut() {
+            *reg = None;
+        }
+    }
+
+    pub fn free_regs(&mut self, regs: &Vec<Reg>, asm: &mut MachineFunc) {
         for reg in regs {
-            self.free_reg(&reg, asm);
+            self.free_reg(reg, asm);
         }
     }
 }
@@ -1,5 +1,6 @@
 use super::exp::*;
 use super::stmt::*;
+use super::Pos;
 use koopa::ir::Type;
 use koopa::ir::TypeKind;
 
@@ -9,27 +10,40 @@ use koopa::ir::TypeKind;
 pub struct FuncDef {
     pub return_type: BType,
     pub ident: String,
+    pub pos: Pos,
     pub func_f_params: Vec<FuncFParam>,
     pub block: Block,
 }
 
 #[derive(Debug)]
 pub enum FuncFParam {
-    Var(BType, String),
-    Array(BType, String, Vec<ConstExp>),
+    Var(BType, String, Pos),
+    Array(BType, String, Vec<ConstExp>, Pos),
 }
 
 impl FuncFParam {
+    pub fn get_btype(&self) -> &BType {
+        match self {
+            FuncFParam::Var(btype, _, _) => btype,
+            FuncFParam::Array(btype, _, _, _) => btype,
+        }
+    }
     pub fn get_ident(&self) -> &String {
         match self {
-            FuncFParam::Var(_, ident) => ident,
-            FuncFParam::Array(_, ident, _) => ident,
+            FuncFParam::Var(_, ident, _) => ident,
+            FuncFParam::Array(_, ident, _, _) => ident,
+        }
+    }
+    pub fn get_pos(&self) -> Pos {
+        match self {
+            FuncFParam::Var(_, _, pos) => *pos,
+            FuncFParam::Array(_, _, _, pos) => *pos,
         }
     }
     pub fn get_size(&self) -> Option<&Vec<ConstExp>> {
         match self {
-            FuncFParam::Var(_, _) => None,
-            FuncFParam::Array(_, _, size) => Some(size),
+            FuncFParam::Var(_, _, _) => None,
+            FuncFParam::Array(_, _, size, _) => Some(size),
         }
     }
 }
@@ -43,8 +57,8 @@ pub struct Block {
 
 #[derive(Debug)]
 pub enum BlockItem {
-    Decl(Box<Decl>),
-    Stmt(Box<Stmt>),
+    Decl(Box<Decl>, Pos),
+    Stmt(Box<Stmt>, Pos),
 }
 
 // ============= Declaration =============
@@ -84,6 +98,7 @@ impl BType {
 #[derive(Debug)]
 pub struct ConstDef {
     pub ident: String,
+    pub pos: Pos,
     pub index: Vec<ConstExp>,
     pub const_init_val: Box<ConstInitVal>,
 }
@@ -106,10 +121,12 @@ pub struct VarDecl {
 pub enum VarDef {
     VarDef {
         ident: String,
+        pos: Pos,
         index: Vec<ConstExp>,
     },
     VarDefInit {
         ident: String,
+        pos: Pos,
         index: Vec<ConstExp>,
         init_val: Box<InitVal>,
     },
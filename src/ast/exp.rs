@@ -1,11 +1,23 @@
+use super::Pos;
+use std::fmt;
+
 #[derive(Debug)]
 pub enum Exp {
-    LOrExp(Box<LOrExp>),
+    LOrExp(Box<LOrExp>, Pos),
+}
+
+impl Exp {
+    pub fn get_pos(&self) -> Pos {
+        match self {
+            Exp::LOrExp(_, pos) => *pos,
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct LVal {
     pub ident: String,
+    pub pos: Pos,
     pub index: Vec<Exp>,
 }
 
@@ -19,7 +31,7 @@ pub enum PrimaryExp {
 #[derive(Debug)]
 pub enum UnaryExp {
     UnaryExp(UnaryOp, Box<UnaryExp>),
-    FuncCallExp(String, Vec<Exp>),
+    FuncCallExp(String, Vec<Exp>, Pos),
     PrimaryExp(Box<PrimaryExp>),
 }
 
@@ -33,7 +45,7 @@ pub enum UnaryOp {
 #[derive(Debug)]
 pub enum MulExp {
     UnaryExp(Box<UnaryExp>),
-    MulExp(Box<MulExp>, MulOp, Box<UnaryExp>),
+    MulExp(Box<MulExp>, MulOp, Box<UnaryExp>, Pos),
 }
 
 #[derive(Debug)]
@@ -43,13 +55,14 @@ pub enum MulOp {
     Mod,
 }
 
-impl ToString for MulOp {
-    fn to_string(&self) -> String {
-        match self {
-            MulOp::Mul => "*".to_string(),
-            MulOp::Div => "/".to_string(),
-            MulOp::Mod => "%".to_string(),
-        }
+impl fmt::Display for MulOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            MulOp::Mul => "*",
+            MulOp::Div => "/",
+            MulOp::Mod => "%",
+        };
+        write!(f, "{}", op)
     }
 }
 
@@ -65,12 +78,13 @@ pub enum AddOp {
     Sub,
 }
 
-impl ToString for AddOp {
-    fn to_string(&self) -> String {
-        match self {
-            AddOp::Add => "+".to_string(),
-            AddOp::Sub => "-".to_string(),
-        }
+impl fmt::Display for AddOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            AddOp::Add => "+",
+            AddOp::Sub => "-",
+        };
+        write!(f, "{}", op)
     }
 }
 
@@ -88,14 +102,15 @@ pub enum RelOp {
     Ge,
 }
 
-impl ToString for RelOp {
-    fn to_string(&self) -> String {
-        match self {
-            RelOp::Lt => "<".to_string(),
-            RelOp::Le => "<=".to_string(),
-            RelOp::Gt => ">".to_string(),
-            RelOp::Ge => ">=".to_string(),
-        }
+impl fmt::Display for RelOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            RelOp::Lt => "<",
+            RelOp::Le => "<=",
+            RelOp::Gt => ">",
+            RelOp::Ge => ">=",
+        };
+        write!(f, "{}", op)
     }
 }
 
@@ -111,12 +126,13 @@ pub enum EqOp {
     Ne,
 }
 
-impl ToString for EqOp {
-    fn to_string(&self) -> String {
-        match self {
-            EqOp::Eq => "==".to_string(),
-            EqOp::Ne => "!=".to_string(),
-        }
+impl fmt::Display for EqOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            EqOp::Eq => "==",
+            EqOp::Ne => "!=",
+        };
+        write!(f, "{}", op)
     }
 }
 
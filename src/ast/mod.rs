@@ -12,3 +12,33 @@ pub enum CompItem {
     Decl(Box<Decl>),
     FuncDef(Box<FuncDef>),
 }
+
+// ============= Source Position =============
+
+// byte offset into the source text
+pub type Pos = usize;
+
+#[derive(Debug, Clone)]
+pub struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    pub fn new(source: &str) -> Self {
+        let mut line_starts = vec![0];
+        for (i, c) in source.char_indices() {
+            if c == '\n' {
+                line_starts.push(i + 1);
+            }
+        }
+        LineIndex { line_starts }
+    }
+    // 1-based (line, column)
+    pub fn line_col(&self, pos: Pos) -> (usize, usize) {
+        let line = match self.line_starts.binary_search(&pos) {
+            Ok(line) => line,
+            Err(line) => line - 1,
+        };
+        (line + 1, pos - self.line_starts[line] + 1)
+    }
+}
@@ -0,0 +1,54 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+// assemblers tried in order when --assembler is not given
+const DEFAULT_ASSEMBLERS: [&str; 3] = ["riscv64-unknown-elf-as", "riscv64-linux-gnu-as", "llvm-mc"];
+
+// ============ Object Output ============
+// run an external assembler, feeding it the assembly on stdin
+
+pub fn assemble(asm: &str, output: &str, assembler: Option<&str>) -> Result<(), String> {
+    let program = match assembler {
+        Some(program) => program.to_string(),
+        None => DEFAULT_ASSEMBLERS
+            .iter()
+            .find(|program| in_path(program))
+            .map(|program| program.to_string())
+            .ok_or("no RISC-V assembler found, use --assembler=<program>")?,
+    };
+    let mut command = Command::new(&program);
+    if program.ends_with("llvm-mc") {
+        command.args([
+            "-triple=riscv32",
+            "-mattr=+m",
+            "-filetype=obj",
+            "-o",
+            output,
+        ]);
+    } else {
+        command.args(["-march=rv32im", "-mabi=ilp32", "-o", output, "-"]);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("cannot run {}: {}", program, err))?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(asm.as_bytes())
+        .map_err(|err| format!("cannot write to {}: {}", program, err))?;
+    let status = child
+        .wait()
+        .map_err(|err| format!("cannot run {}: {}", program, err))?;
+    if !status.success() {
+        return Err(format!("{} failed with {}", program, status));
+    }
+    Ok(())
+}
+
+fn in_path(program: &str) -> bool {
+    std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
@@ -0,0 +1,265 @@
+mod assemble;
+pub use assemble::assemble;
+
+use std::path::Path;
+use sysy_compiler::Options;
+
+pub const USAGE: &str = "\
+usage: SysY-Compiler [options] [<input>]
+       SysY-Compiler -koopa|-riscv|-perf <input> -o <output>
+
+Reads <input>, or stdin if it is `-` or missing.
+
+options:
+  -o <file>                 write output to <file>, `-` for stdout
+  --emit=<kind>[=<file>],...
+                            artifacts to write: ast, koopa, riscv, obj (default riscv)
+  -O0, -O1, -O2             optimization level (default -O0)
+  -koopa, -riscv, -perf     same as --emit=koopa, --emit=riscv, --emit=riscv -O2
+  --passes=<pass>,...       run these passes instead of the -O pipeline
+  --print-after=<pass>,...  dump Koopa IR to stderr after these passes
+  --print-after-all         dump Koopa IR after every pass
+  --verify                  verify the IR after generation and after the passes
+  --verify-each             verify the IR after generation and after every pass
+  --time-passes             report the time spent in each pass
+  --inline-threshold=<n>    inline callees of at most <n> instructions (default 40)
+  --unroll-factor=<n>       partially unroll inner loops <n> times, 1 disables (default 4)
+  --memoize                 cache the results of recursive pure functions (-O1 and up)
+  --list-passes             list the available passes
+  -W<lint>, -Wno-<lint>, -Wall, -Werror, -w
+                            warning control
+  -fsanitize=<checks>, -fno-sanitize=<checks>
+                            runtime checks: bounds, division, all
+  -fstack-check, -fstack-size=<bytes>[K|M]
+                            check the stack limit in every prologue
+  -fstack-usage             print the frame size of each function
+  -foptimize-sibling-calls, -fno-optimize-sibling-calls
+                            reuse the frame for calls in tail position (default from -O1)
+  -fregalloc=<kind>         register allocator: graph (default at -O2), linear-scan,
+                            greedy (default below -O2)
+  -fno-omit-frame-pointer, -fomit-frame-pointer
+                            keep s0 as the frame pointer (default: omitted)
+  --assembler=<program>     assembler used for --emit=obj
+  -h, --help                print this message
+  -V, --version             print the version
+";
+
+// ============ Emit Kinds ============
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EmitKind {
+    Ast,
+    Koopa,
+    Riscv,
+    Obj,
+}
+
+impl EmitKind {
+    fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "ast" => Some(EmitKind::Ast),
+            "koopa" => Some(EmitKind::Koopa),
+            "riscv" | "asm" => Some(EmitKind::Riscv),
+            "obj" => Some(EmitKind::Obj),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            EmitKind::Ast => "ast",
+            EmitKind::Koopa => "koopa",
+            EmitKind::Riscv => "S",
+            EmitKind::Obj => "o",
+        }
+    }
+}
+
+// where an artifact goes
+#[derive(Debug, Clone, PartialEq)]
+pub enum Output {
+    Stdout,
+    File(String),
+}
+
+impl Output {
+    fn from_arg(arg: &str) -> Self {
+        if arg == "-" {
+            Output::Stdout
+        } else {
+            Output::File(arg.to_string())
+        }
+    }
+}
+
+// ============ Arguments ============
+
+pub struct Args {
+    pub options: Options,
+    // None: read stdin
+    pub input: Option<String>,
+    pub emits: Vec<(EmitKind, Output)>,
+    pub stack_usage: bool,
+    pub assembler: Option<String>,
+}
+
+pub enum Action {
+    Compile(Args),
+    Help,
+    Version,
+    ListPasses,
+}
+
+pub fn parse_args(args: impl IntoIterator<Item = String>) -> Result<Action, String> {
+    let mut options = Options::default();
+    let mut input = None;
+    let mut output = None;
+    let mut emits: Vec<(EmitKind, Option<Output>)> = Vec::new();
+    let mut stack_usage = false;
+    let mut assembler = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "-h" | "--help" => return Ok(Action::Help),
+            "-V" | "--version" => return Ok(Action::Version),
+            "-o" => {
+                let file = args.next().ok_or("missing file name after -o")?;
+                output = Some(Output::from_arg(&file));
+            }
+            // the old mode arguments
+            "-koopa" => add_emit(&mut emits, EmitKind::Koopa, None),
+            "-riscv" => add_emit(&mut emits, EmitKind::Riscv, None),
+            "-perf" => {
+                add_emit(&mut emits, EmitKind::Riscv, None);
+                options.opt_level = 2;
+            }
+            "-fstack-usage" => stack_usage = true,
+            "-" => set_input(&mut input, arg)?,
+            _ => {
+                if let Some(kinds) = arg.strip_prefix("--emit=") {
+                    for spec in kinds.split(',') {
+                        let (name, file) = match spec.split_once('=') {
+                            Some((name, file)) => (name, Some(Output::from_arg(file))),
+                            None => (spec, None),
+                        };
+                        let kind = EmitKind::from_name(name)
+                            .ok_or_else(|| format!("unknown emit kind: {}", name))?;
+                        add_emit(&mut emits, kind, file);
+                    }
+                } else if let Some(level) = arg.strip_prefix("-O") {
+                    options.opt_level = match level {
+                        "0" => 0,
+                        "" | "1" => 1,
+                        "2" | "3" => 2,
+                        _ => return Err(format!("invalid optimization level: {}", arg)),
+                    };
+                } else if let Some(file) = arg.strip_prefix("-o") {
+                    output = Some(Output::from_arg(file));
+                } else if let Some(program) = arg.strip_prefix("--assembler=") {
+                    assembler = Some(program.to_string());
+                } else if arg.starts_with("--pass")
+                    || arg.starts_with("--print-after")
+                    || arg == "--verify"
+                    || arg == "--verify-each"
+                    || arg == "--time-passes"
+                    || arg == "--memoize"
+                    || arg.starts_with("--inline-threshold=")
+                    || arg.starts_with("--unroll-factor=")
+                {
+                    options.passes.parse_flag(&arg)?;
+                } else if arg == "--list-passes" {
+                    return Ok(Action::ListPasses);
+                } else if arg == "-w" || arg.starts_with("-W") {
+                    options.warnings.parse_flag(&arg)?;
+                } else if arg.starts_with("-fsanitize=") || arg.starts_with("-fno-sanitize=") {
+                    options.sanitize.parse_flag(&arg)?;
+                } else if arg.starts_with("-fstack-")
+                    || arg == "-fno-stack-check"
+                    || arg.ends_with("optimize-sibling-calls")
+                    || arg.starts_with("-fregalloc=")
+                    || arg.ends_with("omit-frame-pointer")
+                {
+                    options.asm.parse_flag(&arg)?;
+                } else if arg.starts_with('-') {
+                    return Err(format!("unknown option: {}", arg));
+                } else {
+                    set_input(&mut input, arg)?;
+                }
+            }
+        }
+    }
+    if emits.is_empty() {
+        emits.push((EmitKind::Riscv, None));
+    }
+    let input = input.filter(|file| file != "-");
+    let emits = resolve_outputs(emits, output, input.as_deref())?;
+    Ok(Action::Compile(Args {
+        options,
+        input,
+        emits,
+        stack_usage,
+        assembler,
+    }))
+}
+
+fn set_input(input: &mut Option<String>, arg: String) -> Result<(), String> {
+    if let Some(first) = input {
+        return Err(format!("multiple input files: {} and {}", first, arg));
+    }
+    *input = Some(arg);
+    Ok(())
+}
+
+// a later --emit of the same kind replaces the earlier one
+fn add_emit(emits: &mut Vec<(EmitKind, Option<Output>)>, kind: EmitKind, file: Option<Output>) {
+    emits.retain(|(k, _)| *k != kind);
+    emits.push((kind, file));
+}
+
+/*
+    Output of each artifact without an explicit `kind=file`:
+        one artifact:   -o <file>, otherwise stdout for stdin input, <stem>.<ext> for a file
+        several:        <-o stem or input stem>.<ext>
+*/
+fn resolve_outputs(
+    emits: Vec<(EmitKind, Option<Output>)>,
+    output: Option<Output>,
+    input: Option<&str>,
+) -> Result<Vec<(EmitKind, Output)>, String> {
+    let single = emits.len() == 1;
+    let stem = match (&output, input) {
+        (Some(Output::File(file)), _) => Some(file.clone()),
+        (_, Some(input)) => Some(input.to_string()),
+        _ => None,
+    };
+    let mut resolved = Vec::new();
+    for (kind, file) in emits {
+        let file = match file {
+            Some(file) => file,
+            None if single && output.is_some() => output.clone().unwrap(),
+            None if single && input.is_none() => Output::Stdout,
+            None if !single && output == Some(Output::Stdout) => {
+                return Err("-o - needs a single --emit kind".to_string());
+            }
+            None => match &stem {
+                Some(stem) => Output::File(
+                    Path::new(stem)
+                        .with_extension(kind.extension())
+                        .to_string_lossy()
+                        .into_owned(),
+                ),
+                None => return Err("several --emit kinds from stdin need -o".to_string()),
+            },
+        };
+        resolved.push((kind, file));
+    }
+    let to_stdout = resolved
+        .iter()
+        .filter(|(_, file)| *file == Output::Stdout)
+        .count();
+    if to_stdout > 1 {
+        return Err("only one artifact can be written to stdout".to_string());
+    }
+    Ok(resolved)
+}
@@ -1,6 +1,7 @@
 use super::const_eval::*;
 use super::*;
 use crate::ast::decl::*;
+use crate::warning::Lint;
 use koopa::ir::builder::GlobalInstBuilder;
 use koopa::ir::builder::LocalInstBuilder;
 use koopa::ir::builder::ValueBuilder;
@@ -38,11 +39,12 @@ impl IrGenerator for ConstDecl {
 impl IrGenerator for ConstDef {
     type Output = Result<(), String>;
     fn build_ir(&self, program: &mut Program, context: &mut IrContext) -> Self::Output {
+        context.check_shadow(&self.ident, self.pos);
         if self.index.is_empty() {
             let const_init_val = self.const_init_val.get_const_i32(context).unwrap();
             context
                 .symbol_tables
-                .add_const(&self.ident, Type::get_i32(), const_init_val);
+                .add_const(&self.ident, Type::get_i32(), const_init_val, self.pos);
         } else {
             let size = Array::const_exp2size(&self.index, context);
             let array_type = Array::size2type(&size, Type::get_i32());
@@ -54,7 +56,7 @@ impl IrGenerator for ConstDef {
                 add_value(program, context, alloc).unwrap();
                 context
                     .symbol_tables
-                    .add_array(&self.ident, array_type, alloc, size);
+                    .add_array(&self.ident, array_type, alloc, size, self.pos);
                 // assign
                 const_init_array.init_assign_to_array(program, context, alloc);
             } else {
@@ -69,7 +71,7 @@ impl IrGenerator for ConstDef {
                 program.set_value_name(alloc, Some(format!("@{}", self.ident)));
                 context
                     .symbol_tables
-                    .add_array(&self.ident, array_type, alloc, size);
+                    .add_array(&self.ident, array_type, alloc, size, self.pos);
             }
         }
         Ok(())
@@ -94,14 +96,15 @@ impl IrGenerator for VarDef {
         if !context.is_global {
             // Local Variable
             match self {
-                VarDef::VarDef { ident, index } => {
+                VarDef::VarDef { ident, pos, index } => {
+                    context.check_shadow(ident, *pos);
                     if index.is_empty() {
                         // Single Variable
                         let alloc = new_value_builder(program, context).alloc(Type::get_i32());
                         add_value(program, context, alloc).unwrap();
                         context
                             .symbol_tables
-                            .add_var(&ident, Type::get_i32(), alloc);
+                            .add_var(ident, Type::get_i32(), alloc, *pos);
                     } else {
                         // Array Variable
                         let size = Array::const_exp2size(index, context);
@@ -110,27 +113,30 @@ impl IrGenerator for VarDef {
                         add_value(program, context, alloc).unwrap();
                         context
                             .symbol_tables
-                            .add_array(&ident, array_kind, alloc, size);
+                            .add_array(ident, array_kind, alloc, size, *pos);
                     }
-                    return Ok(());
+                    Ok(())
                 }
                 VarDef::VarDefInit {
                     ident,
+                    pos,
                     index,
                     init_val,
                 } => {
+                    context.check_shadow(ident, *pos);
                     if index.is_empty() {
                         let alloc = new_value_builder(program, context).alloc(Type::get_i32());
                         add_value(program, context, alloc).unwrap();
                         context
                             .symbol_tables
-                            .add_var(&ident, Type::get_i32(), alloc);
+                            .add_var(ident, Type::get_i32(), alloc, *pos);
                         match init_val.as_ref() {
                             InitVal::Exp(exp) => {
                                 let exp_val = exp.build_ir(program, context).unwrap();
                                 let store =
                                     new_value_builder(program, context).store(exp_val, alloc);
                                 add_value(program, context, store).unwrap();
+                                context.note_write(ident);
                                 Ok(())
                             }
                             InitVal::Array(_) => unreachable!(),
@@ -142,11 +148,11 @@ impl IrGenerator for VarDef {
                         let alloc = new_value_builder(program, context).alloc(array_kind.clone());
                         add_value(program, context, alloc).unwrap();
                         let (init_array, _is_zero) =
-                            Array::get_init_array(program, context, &init_val, &size);
+                            Array::get_init_array(program, context, init_val, &size);
                         init_array.init_assign_to_array(program, context, alloc);
                         context
                             .symbol_tables
-                            .add_array(&ident, array_kind, alloc, size);
+                            .add_array(ident, array_kind, alloc, size, *pos);
 
                         Ok(())
                     }
@@ -155,14 +161,14 @@ impl IrGenerator for VarDef {
         } else {
             // Global Variable
             match self {
-                VarDef::VarDef { ident, index } => {
+                VarDef::VarDef { ident, pos, index } => {
                     if index.is_empty() {
                         // Single Variable
                         let val_0 = const_int_value(program, context, 0);
                         let alloc = program.new_value().global_alloc(val_0);
                         context
                             .symbol_tables
-                            .add_var(&ident, Type::get_i32(), alloc);
+                            .add_var(ident, Type::get_i32(), alloc, *pos);
                         program.set_value_name(alloc, Some(format!("@{}", ident)));
                     } else {
                         // Array Variable
@@ -172,13 +178,14 @@ impl IrGenerator for VarDef {
                         let alloc = program.new_value().global_alloc(zeroinit_value);
                         context
                             .symbol_tables
-                            .add_array(&ident, array_type, alloc, size);
+                            .add_array(ident, array_type, alloc, size, *pos);
                         program.set_value_name(alloc, Some(format!("@{}", ident)));
                     }
                     Ok(())
                 }
                 VarDef::VarDefInit {
                     ident,
+                    pos,
                     index,
                     init_val,
                 } => {
@@ -189,14 +196,14 @@ impl IrGenerator for VarDef {
                         let alloc = program.new_value().global_alloc(val);
                         context
                             .symbol_tables
-                            .add_var(&ident, Type::get_i32(), alloc);
+                            .add_var(ident, Type::get_i32(), alloc, *pos);
                         program.set_value_name(alloc, Some(format!("@{}", ident)));
                     } else {
                         // Array Variable
                         let size = Array::const_exp2size(index, context);
                         let array_type = Array::size2type(&size, Type::get_i32());
                         let (init_array, is_zero) =
-                            Array::get_init_array(program, context, &init_val, &size);
+                            Array::get_init_array(program, context, init_val, &size);
 
                         let alloc = if is_zero {
                             let zeroinit_value = program.new_value().zero_init(array_type.clone());
@@ -208,7 +215,7 @@ impl IrGenerator for VarDef {
 
                         context
                             .symbol_tables
-                            .add_array(&ident, array_type, alloc, size);
+                            .add_array(ident, array_type, alloc, size, *pos);
                         program.set_value_name(alloc, Some(format!("@{}", ident)));
                     }
                     Ok(())
@@ -235,7 +242,8 @@ impl IrGenerator for FuncDef {
             self.return_type.to_type(),
         );
         let func = program.new_func(func_data);
-        context.func_table.insert(self.ident.clone(), func.clone());
+        context.func_table.insert(self.ident.clone(), func);
+        context.func_defs.push((self.ident.clone(), self.pos));
         context.change_current_func(func);
         // create entry block
         let entry_bb = create_bb(program, context, "%entry");
@@ -252,24 +260,27 @@ impl IrGenerator for FuncDef {
             })
             .collect();
 
-        for i in 0..params.len() {
-            let (param, param_ty) = params[i].clone();
+        for (i, (param, param_ty)) in params.into_iter().enumerate() {
             let alloc_value = new_value_builder(program, context).alloc(param_ty.clone());
             add_value(program, context, alloc_value).unwrap();
-            if let FuncFParam::Var(_, _) = &self.func_f_params[i] {
-                context.symbol_tables.add_var(
-                    &self.func_f_params[i].get_ident(),
+            let param_pos = self.func_f_params[i].get_pos();
+            context.check_shadow(self.func_f_params[i].get_ident(), param_pos);
+            if let FuncFParam::Var(_, _, _) = &self.func_f_params[i] {
+                context.symbol_tables.add_var_param(
+                    self.func_f_params[i].get_ident(),
                     param_ty,
                     alloc_value,
+                    param_pos,
                 );
             } else {
                 let size =
-                    Array::const_exp2size(&self.func_f_params[i].get_size().unwrap(), context);
+                    Array::const_exp2size(self.func_f_params[i].get_size().unwrap(), context);
                 context.symbol_tables.add_array_param(
-                    &self.func_f_params[i].get_ident(),
+                    self.func_f_params[i].get_ident(),
                     param_ty,
                     alloc_value,
                     size,
+                    param_pos,
                 );
             }
             let store_value = new_value_builder(program, context).store(param, alloc_value);
@@ -292,6 +303,16 @@ impl IrGenerator for FuncDef {
             need_ret = true;
         }
         if need_ret {
+            let end_bb = context.current_bb.unwrap();
+            if self.return_type.to_typekind() != TypeKind::Unit
+                && bb_reachable(program, context, end_bb)
+            {
+                context.warnings.warn(
+                    Lint::ReturnType,
+                    self.pos,
+                    format!("control reaches end of non-void function '{}'", self.ident),
+                );
+            }
             match self.return_type.to_typekind() {
                 TypeKind::Unit => {
                     let ret = new_value_builder(program, context).ret(None);
@@ -305,7 +326,7 @@ impl IrGenerator for FuncDef {
                 _ => unreachable!(),
             }
         }
-        context.symbol_tables.pop_table();
+        context.pop_scope();
         Ok(())
     }
 }
@@ -316,12 +337,26 @@ impl IrGenerator for Block {
     type Output = Result<(), String>;
     // 确保调用前使用了push_table，调用后使用了pop_table
     fn build_ir(&self, program: &mut Program, context: &mut IrContext) -> Self::Output {
+        // code following return/break/continue is reported once per block;
+        // if the block itself is unreachable, the enclosing block already reported it
+        let mut unreachable_reported = bb_closed(program, context, context.current_bb.unwrap());
         for item in self.block_items.iter() {
+            let pos = match item {
+                BlockItem::Decl(_, pos) | BlockItem::Stmt(_, pos) => *pos,
+            };
+            if !unreachable_reported && bb_closed(program, context, context.current_bb.unwrap()) {
+                context.warnings.warn(
+                    Lint::UnreachableCode,
+                    pos,
+                    "code will never be executed".to_string(),
+                );
+                unreachable_reported = true;
+            }
             match item {
-                BlockItem::Decl(decl) => {
+                BlockItem::Decl(decl, _) => {
                     decl.build_ir(program, context)?;
                 }
-                BlockItem::Stmt(stmt) => {
+                BlockItem::Stmt(stmt, _) => {
                     stmt.build_ir(program, context)?;
                 }
             }
@@ -1,6 +1,9 @@
+use super::const_eval::ConstI32Eval;
+use super::sanitize::{check_division, check_index};
 use super::util::*;
 use super::*;
 use crate::ast::exp::*;
+use crate::warning::Lint;
 use koopa::ir::builder::LocalInstBuilder;
 use koopa::ir::{BinaryOp, Type, Value};
 
@@ -8,7 +11,7 @@ impl IrGenerator for Exp {
     type Output = Result<Value, String>;
     fn build_ir(&self, program: &mut Program, context: &mut IrContext) -> Self::Output {
         match self {
-            Exp::LOrExp(exp) => exp.build_ir(program, context),
+            Exp::LOrExp(exp, _) => exp.build_ir(program, context),
         }
     }
 }
@@ -20,11 +23,51 @@ pub enum LValValue {
     ArrayParamElem(Value, Vec<usize>, Vec<Value>),
 }
 
+impl LVal {
+    // warn about constant subscripts outside of `bounds`; `None` means unknown length
+    fn check_const_index(&self, context: &mut IrContext, bounds: &[Option<usize>]) {
+        for (exp, bound) in self.index.iter().zip(bounds.iter()) {
+            let idx = match exp.get_const_i32(context) {
+                Ok(idx) => idx,
+                Err(_) => continue,
+            };
+            let out_of_bounds = match bound {
+                Some(len) => idx < 0 || idx as usize >= *len,
+                None => idx < 0,
+            };
+            if out_of_bounds {
+                context.warnings.warn(
+                    Lint::ArrayBounds,
+                    exp.get_pos(),
+                    format!("array index {} is out of bounds for '{}'", idx, self.ident),
+                );
+            }
+        }
+    }
+}
+
 impl IrGenerator for LVal {
     type Output = Result<LValValue, String>;
     fn build_ir(&self, program: &mut Program, context: &mut IrContext) -> Self::Output {
         let (entry, _) = context.symbol_tables.get_symbol(&self.ident);
-        let entry = entry.unwrap();
+        let entry = match entry {
+            Some(entry) => entry,
+            None => return Err(format!("{} is not defined", self.ident)),
+        };
+        match &entry {
+            SymbolTableEntry::Array(_, _, size) => {
+                let bounds: Vec<_> = size.iter().map(|len| Some(*len)).collect();
+                self.check_const_index(context, &bounds);
+            }
+            SymbolTableEntry::ArrayParam(_, _, size) => {
+                // the first dimension of an array parameter has no known length
+                let bounds: Vec<_> = std::iter::once(None)
+                    .chain(size.iter().map(|len| Some(*len)))
+                    .collect();
+                self.check_const_index(context, &bounds);
+            }
+            _ => {}
+        }
         match entry {
             SymbolTableEntry::Var(_, value) => Ok(LValValue::Var(value)),
             SymbolTableEntry::Const(_, value) => {
@@ -35,6 +78,8 @@ impl IrGenerator for LVal {
                 let mut index: Vec<Value> = Vec::new();
                 for i in 0..self.index.len() {
                     let index_val = self.index[i].build_ir(program, context)?;
+                    let len = size.get(i).copied();
+                    check_index(program, context, index_val, len, self.index[i].get_pos())?;
                     index.push(index_val);
                 }
                 Ok(LValValue::ArrayElem(value, size, index))
@@ -43,6 +88,12 @@ impl IrGenerator for LVal {
                 let mut index: Vec<Value> = Vec::new();
                 for i in 0..self.index.len() {
                     let index_val = self.index[i].build_ir(program, context)?;
+                    let len = if i == 0 {
+                        None
+                    } else {
+                        size.get(i - 1).copied()
+                    };
+                    check_index(program, context, index_val, len, self.index[i].get_pos())?;
                     index.push(index_val);
                 }
                 let array_value = new_value_builder(program, context).load(value);
@@ -59,6 +110,7 @@ impl IrGenerator for PrimaryExp {
         match self {
             PrimaryExp::BracketExp(exp) => exp.build_ir(program, context),
             PrimaryExp::LVal(lval) => {
+                context.note_read(&lval.ident, lval.pos);
                 let lval_val = lval.build_ir(program, context)?;
                 match lval_val {
                     LValValue::Var(value) => {
@@ -114,12 +166,15 @@ impl IrGenerator for UnaryExp {
                 };
                 Ok(value)
             }
-            UnaryExp::FuncCallExp(func_name, func_r_params) => {
+            UnaryExp::FuncCallExp(func_name, func_r_params, _) => {
                 let params_val: Vec<Value> = func_r_params
                     .iter()
                     .map(|exp| exp.build_ir(program, context).unwrap())
                     .collect();
-                let callee = get_func(context, &func_name);
+                if context.current_func != context.func_table.get(func_name).copied() {
+                    context.called_funcs.insert(func_name.clone());
+                }
+                let callee = get_func(context, func_name);
                 let call_val = new_value_builder(program, context).call(callee, params_val);
                 add_value(program, context, call_val)?;
                 Ok(call_val)
@@ -134,9 +189,12 @@ impl IrGenerator for MulExp {
     fn build_ir(&self, program: &mut Program, context: &mut IrContext) -> Self::Output {
         match self {
             MulExp::UnaryExp(exp) => exp.build_ir(program, context),
-            MulExp::MulExp(exp1, op, exp2) => {
+            MulExp::MulExp(exp1, op, exp2, pos) => {
                 let exp1_val = exp1.build_ir(program, context)?;
                 let exp2_val = exp2.build_ir(program, context)?;
+                if let MulOp::Div | MulOp::Mod = op {
+                    check_division(program, context, exp1_val, exp2_val, *pos)?;
+                }
                 let value = match op {
                     MulOp::Mul => new_value_builder(program, context).binary(
                         BinaryOp::Mul,
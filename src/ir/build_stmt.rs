@@ -1,7 +1,9 @@
+use super::const_eval::ConstI32Eval;
 use super::util::*;
 use super::*;
 use crate::ast::stmt::*;
 use crate::ir::build_expr::LValValue;
+use crate::warning::Lint;
 use koopa::ir::builder::LocalInstBuilder;
 
 impl IrGenerator for Stmt {
@@ -15,6 +17,7 @@ impl IrGenerator for Stmt {
                         let exp_val = exp.build_ir(program, context)?;
                         let store = new_value_builder(program, context).store(exp_val, value);
                         add_value(program, context, store)?;
+                        context.note_assign(&lval.ident);
                         Ok(())
                     }
                     LValValue::Const(_) => Err("Assign to constant".to_string()),
@@ -44,10 +47,17 @@ impl IrGenerator for Stmt {
             Stmt::BlockStmt(block) => {
                 context.symbol_tables.push_table();
                 block.build_ir(program, context)?;
-                context.symbol_tables.pop_table();
+                context.pop_scope();
                 Ok(())
             }
             Stmt::IfStmt(exp, then_stmt, else_stmt) => {
+                if let Ok(cond) = exp.get_const_i32(context) {
+                    context.warnings.warn(
+                        Lint::ConstantCondition,
+                        exp.get_pos(),
+                        format!("condition is always {}", cond != 0),
+                    );
+                }
                 let if_bb = new_bb(program, context, "%if");
                 let if_bb = insert_bb(program, context, if_bb);
                 change_current_bb(program, context, if_bb);
@@ -98,6 +108,14 @@ impl IrGenerator for Stmt {
                 Ok(())
             }
             Stmt::WhileStmt(exp, stmt) => {
+                // `while (1)` is a common idiom, only a loop that never runs is suspicious
+                if let Ok(0) = exp.get_const_i32(context) {
+                    context.warnings.warn(
+                        Lint::ConstantCondition,
+                        exp.get_pos(),
+                        "loop condition is always false".to_string(),
+                    );
+                }
                 let while_bb = new_bb(program, context, "%while");
                 let while_bb = insert_bb(program, context, while_bb);
                 change_current_bb(program, context, while_bb);
@@ -1,12 +1,17 @@
 use super::{IrContext, SymbolTableEntry};
 use crate::ast::{decl::ConstInitVal, decl::InitVal, exp::*};
 
+// where the values of named constants come from
+pub trait ConstEnv {
+    fn const_value(&self, ident: &str) -> Result<i32, String>;
+}
+
 pub trait ConstI32Eval {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String>;
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String>;
 }
 
 impl ConstI32Eval for ConstInitVal {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             ConstInitVal::ConstExp(e) => e.get_const_i32(context),
             ConstInitVal::ConstArray(_) => unreachable!(),
@@ -15,7 +20,7 @@ impl ConstI32Eval for ConstInitVal {
 }
 
 impl ConstI32Eval for InitVal {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             InitVal::Exp(e) => e.get_const_i32(context),
             InitVal::Array(_) => unreachable!(),
@@ -24,15 +29,15 @@ impl ConstI32Eval for InitVal {
 }
 
 impl ConstI32Eval for Exp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
-            Exp::LOrExp(e) => e.get_const_i32(context),
+            Exp::LOrExp(e, _) => e.get_const_i32(context),
         }
     }
 }
 
 impl ConstI32Eval for PrimaryExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             PrimaryExp::Number(n) => Ok(*n),
             PrimaryExp::BracketExp(e) => e.get_const_i32(context),
@@ -42,33 +47,39 @@ impl ConstI32Eval for PrimaryExp {
 }
 
 impl ConstI32Eval for UnaryExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             UnaryExp::PrimaryExp(p) => p.get_const_i32(context),
             UnaryExp::UnaryExp(op, e) => {
-                let val = e.get_const_i32(context).unwrap();
+                let val = e.get_const_i32(context)?;
                 match op {
                     UnaryOp::Plus => Ok(val),
-                    UnaryOp::Minus => Ok(-val),
+                    UnaryOp::Minus => Ok(val.wrapping_neg()),
                     UnaryOp::Not => Ok(if val != 0 { 0 } else { 1 }),
                 }
             }
-            _ => unreachable!(),
+            UnaryExp::FuncCallExp(ident, _, _) => Err(format!(
+                "call to {} cannot be evaluated during compilation",
+                ident
+            )),
         }
     }
 }
 
 impl ConstI32Eval for MulExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             MulExp::UnaryExp(e) => e.get_const_i32(context),
-            MulExp::MulExp(e, op, u) => {
-                let val = e.get_const_i32(context).unwrap();
-                let uval = u.get_const_i32(context).unwrap();
+            MulExp::MulExp(e, op, u, _) => {
+                let val = e.get_const_i32(context)?;
+                let uval = u.get_const_i32(context)?;
                 match op {
-                    MulOp::Mul => Ok(val * uval),
-                    MulOp::Div => Ok(val / uval),
-                    MulOp::Mod => Ok(val % uval),
+                    MulOp::Mul => Ok(val.wrapping_mul(uval)),
+                    MulOp::Div | MulOp::Mod if uval == 0 => {
+                        Err("division by zero in constant expression".to_string())
+                    }
+                    MulOp::Div => Ok(val.wrapping_div(uval)),
+                    MulOp::Mod => Ok(val.wrapping_rem(uval)),
                 }
             }
         }
@@ -76,15 +87,15 @@ impl ConstI32Eval for MulExp {
 }
 
 impl ConstI32Eval for AddExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             AddExp::MulExp(e) => e.get_const_i32(context),
             AddExp::AddExp(e, op, m) => {
-                let val = e.get_const_i32(context).unwrap();
-                let mval = m.get_const_i32(context).unwrap();
+                let val = e.get_const_i32(context)?;
+                let mval = m.get_const_i32(context)?;
                 match op {
-                    AddOp::Add => Ok(val + mval),
-                    AddOp::Sub => Ok(val - mval),
+                    AddOp::Add => Ok(val.wrapping_add(mval)),
+                    AddOp::Sub => Ok(val.wrapping_sub(mval)),
                 }
             }
         }
@@ -92,12 +103,12 @@ impl ConstI32Eval for AddExp {
 }
 
 impl ConstI32Eval for RelExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             RelExp::AddExp(e) => e.get_const_i32(context),
             RelExp::RelExp(e, op, a) => {
-                let val = e.get_const_i32(context).unwrap();
-                let aval = a.get_const_i32(context).unwrap();
+                let val = e.get_const_i32(context)?;
+                let aval = a.get_const_i32(context)?;
                 match op {
                     RelOp::Lt => Ok(if val < aval { 1 } else { 0 }),
                     RelOp::Le => Ok(if val <= aval { 1 } else { 0 }),
@@ -110,12 +121,12 @@ impl ConstI32Eval for RelExp {
 }
 
 impl ConstI32Eval for EqExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             EqExp::RelExp(e) => e.get_const_i32(context),
             EqExp::EqExp(e, op, r) => {
-                let val = e.get_const_i32(context).unwrap();
-                let rval = r.get_const_i32(context).unwrap();
+                let val = e.get_const_i32(context)?;
+                let rval = r.get_const_i32(context)?;
                 match op {
                     EqOp::Eq => Ok(if val == rval { 1 } else { 0 }),
                     EqOp::Ne => Ok(if val != rval { 1 } else { 0 }),
@@ -126,12 +137,12 @@ impl ConstI32Eval for EqExp {
 }
 
 impl ConstI32Eval for LAndExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             LAndExp::EqExp(e) => e.get_const_i32(context),
             LAndExp::LAndExp(e, eq) => {
-                let val = e.get_const_i32(context).unwrap();
-                let eqval = eq.get_const_i32(context).unwrap();
+                let val = e.get_const_i32(context)?;
+                let eqval = eq.get_const_i32(context)?;
                 if val != 0 && eqval != 0 {
                     Ok(1)
                 } else {
@@ -143,12 +154,12 @@ impl ConstI32Eval for LAndExp {
 }
 
 impl ConstI32Eval for LOrExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         match self {
             LOrExp::LAndExp(e) => e.get_const_i32(context),
             LOrExp::LOrExp(e, land) => {
-                let val = e.get_const_i32(context).unwrap();
-                let landval = land.get_const_i32(context).unwrap();
+                let val = e.get_const_i32(context)?;
+                let landval = land.get_const_i32(context)?;
                 if val != 0 || landval != 0 {
                     Ok(1)
                 } else {
@@ -160,24 +171,33 @@ impl ConstI32Eval for LOrExp {
 }
 
 impl ConstI32Eval for LVal {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
-        let (symbol, _) = context.symbol_tables.get_symbol(&self.ident);
-        if let Some(symbol) = symbol {
-            match symbol {
-                SymbolTableEntry::Const(_, value) => Ok(value),
-                _ => Err(format!(
-                    "{} cannot be evaluated during compilation",
-                    self.ident
-                )),
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
+        if !self.index.is_empty() {
+            return Err(format!(
+                "{} cannot be evaluated during compilation",
+                self.ident
+            ));
+        }
+        context.const_value(&self.ident)
+    }
+}
+
+impl ConstEnv for IrContext {
+    fn const_value(&self, ident: &str) -> Result<i32, String> {
+        let (symbol, _) = self.symbol_tables.get_symbol(ident);
+        match symbol {
+            Some(SymbolTableEntry::Const(_, value)) => {
+                self.symbol_tables.get_usage(ident).unwrap().read.set(true);
+                Ok(value)
             }
-        } else {
-            Err(format!("{} is not defined", self.ident))
+            Some(_) => Err(format!("{} cannot be evaluated during compilation", ident)),
+            None => Err(format!("{} is not defined", ident)),
         }
     }
 }
 
 impl ConstI32Eval for ConstExp {
-    fn get_const_i32(&self, context: &IrContext) -> Result<i32, String> {
+    fn get_const_i32(&self, context: &dyn ConstEnv) -> Result<i32, String> {
         self.exp.get_const_i32(context)
     }
 }
@@ -2,10 +2,15 @@ mod build_decl;
 mod build_expr;
 mod build_stmt;
 mod const_eval;
+mod sanitize;
 mod util;
 use crate::ast::*;
+use crate::warning::{WarningConfig, WarningEmitter};
+pub use const_eval::{ConstEnv, ConstI32Eval};
 use koopa::ir::{Function, Program, TypeKind};
+pub use sanitize::{SanitizeConfig, TrapKind, TRAP_FUNC};
 use util::*;
+pub use util::{INIT_TEMPLATE_PREFIX, MEMCPY_FUNC, MEMSET_FUNC};
 
 pub trait IrGenerator {
     type Output;
@@ -16,9 +21,13 @@ impl IrGenerator for CompUnit {
     type Output = Result<(), String>;
     fn build_ir(&self, program: &mut Program, context: &mut IrContext) -> Self::Output {
         init_lib_decl(program, context);
+        if context.sanitize.enabled() {
+            sanitize::init_trap_decl(program, context);
+        }
         for item in &self.items {
-            item.build_ir(program, context).unwrap();
+            item.build_ir(program, context)?;
         }
+        context.report_unused_funcs();
         Ok(())
     }
 }
@@ -38,9 +47,17 @@ impl IrGenerator for CompItem {
         }
     }
 }
-pub fn compile(ast: &CompUnit) -> koopa::ir::Program {
+pub fn compile(
+    ast: &CompUnit,
+    line_index: LineIndex,
+    warning_config: WarningConfig,
+    sanitize_config: SanitizeConfig,
+) -> Result<(Program, WarningEmitter), String> {
     let mut program = Program::new();
     let mut context = IrContext::new();
-    ast.build_ir(&mut program, &mut context).unwrap();
-    program
+    context.warnings = WarningEmitter::new(warning_config);
+    context.sanitize = sanitize_config;
+    context.line_index = line_index;
+    ast.build_ir(&mut program, &mut context)?;
+    Ok((program, context.warnings))
 }
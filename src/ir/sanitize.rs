@@ -0,0 +1,194 @@
+use super::util::*;
+use super::IrContext;
+use crate::ast::Pos;
+use koopa::ir::builder::LocalInstBuilder;
+use koopa::ir::{BinaryOp, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+
+// ============ Sanitizer Config ============
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SanitizeConfig {
+    pub bounds: bool,
+    pub division: bool,
+}
+
+impl SanitizeConfig {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.bounds || self.division
+    }
+
+    // parse -fsanitize=<checks> / -fno-sanitize=<checks>, checks: bounds, division, all
+    pub fn parse_flag(&mut self, flag: &str) -> Result<(), String> {
+        let (checks, enabled) = if let Some(checks) = flag.strip_prefix("-fsanitize=") {
+            (checks, true)
+        } else if let Some(checks) = flag.strip_prefix("-fno-sanitize=") {
+            (checks, false)
+        } else {
+            return Err(format!("not a sanitizer flag: {}", flag));
+        };
+        for check in checks.split(',') {
+            match check {
+                "bounds" => self.bounds = enabled,
+                "division" => self.division = enabled,
+                "all" => {
+                    self.bounds = enabled;
+                    self.division = enabled;
+                }
+                _ => return Err(format!("unknown sanitizer check: {}", check)),
+            }
+        }
+        Ok(())
+    }
+}
+
+// ============ Trap ============
+// A failing check calls `@__sysy_trap(kind, line, column)`, which never returns.
+// The function body is provided by the backend (see asm/runtime.rs).
+
+pub const TRAP_FUNC: &str = "__sysy_trap";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrapKind {
+    IndexOutOfBounds,
+    DivisionByZero,
+    DivisionOverflow,
+}
+
+impl TrapKind {
+    pub const ALL: [TrapKind; 3] = [
+        TrapKind::IndexOutOfBounds,
+        TrapKind::DivisionByZero,
+        TrapKind::DivisionOverflow,
+    ];
+
+    pub fn message(&self) -> &'static str {
+        match self {
+            TrapKind::IndexOutOfBounds => "array index out of bounds",
+            TrapKind::DivisionByZero => "division by zero",
+            TrapKind::DivisionOverflow => "signed integer overflow in division",
+        }
+    }
+}
+
+pub fn init_trap_decl(program: &mut Program, context: &mut IrContext) {
+    // decl @__sysy_trap(i32, i32, i32)
+    let func_data = FunctionData::new_decl(
+        format!("@{}", TRAP_FUNC),
+        vec![Type::get_i32(), Type::get_i32(), Type::get_i32()],
+        Type::get(TypeKind::Unit),
+    );
+    let func = program.new_func(func_data);
+    context.func_table.insert(TRAP_FUNC.to_string(), func);
+}
+
+// branch to a trap block if `fail` is non-zero, otherwise continue in a new block
+fn trap_if(
+    program: &mut Program,
+    context: &mut IrContext,
+    fail: Value,
+    kind: TrapKind,
+    pos: Pos,
+) -> Result<(), String> {
+    let trap_bb = new_bb(program, context, "%san_trap");
+    let ok_bb = new_bb(program, context, "%san_ok");
+    let branch = new_value_builder(program, context).branch(fail, trap_bb, ok_bb);
+    add_value(program, context, branch)?;
+    // build trap bb
+    let trap_bb = insert_bb(program, context, trap_bb);
+    change_current_bb(program, context, trap_bb);
+    let (line, col) = context.line_index.line_col(pos);
+    let args = vec![
+        const_int_value(program, context, kind as i32),
+        const_int_value(program, context, line as i32),
+        const_int_value(program, context, col as i32),
+    ];
+    let trap = get_func(context, TRAP_FUNC);
+    let call = new_value_builder(program, context).call(trap, args);
+    add_value(program, context, call)?;
+    // the trap never returns, the jump only keeps the block well-formed
+    let jump = new_value_builder(program, context).jump(ok_bb);
+    add_value(program, context, jump)?;
+    // continue in ok bb
+    let ok_bb = insert_bb(program, context, ok_bb);
+    change_current_bb(program, context, ok_bb);
+    Ok(())
+}
+
+fn const_of(program: &Program, context: &IrContext, value: Value) -> Option<i32> {
+    match get_valuekind(program, context, value) {
+        ValueKind::Integer(num) => Some(num.value()),
+        _ => None,
+    }
+}
+
+fn binary(
+    program: &mut Program,
+    context: &mut IrContext,
+    op: BinaryOp,
+    lhs: Value,
+    rhs: Value,
+) -> Result<Value, String> {
+    let value = new_value_builder(program, context).binary(op, lhs, rhs);
+    add_value(program, context, value)?;
+    Ok(value)
+}
+
+// ============ Checks ============
+
+// check 0 <= index < len, `len` is None for the first dimension of an array parameter
+pub fn check_index(
+    program: &mut Program,
+    context: &mut IrContext,
+    index: Value,
+    len: Option<usize>,
+    pos: Pos,
+) -> Result<(), String> {
+    if !context.sanitize.bounds {
+        return Ok(());
+    }
+    if let Some(idx) = const_of(program, context, index) {
+        if idx >= 0 && len.is_none_or(|len| (idx as usize) < len) {
+            return Ok(());
+        }
+    }
+    let val_0 = const_int_value(program, context, 0);
+    let mut fail = binary(program, context, BinaryOp::Lt, index, val_0)?;
+    if let Some(len) = len {
+        let len = const_int_value(program, context, len as i32);
+        let too_large = binary(program, context, BinaryOp::Ge, index, len)?;
+        fail = binary(program, context, BinaryOp::Or, fail, too_large)?;
+    }
+    trap_if(program, context, fail, TrapKind::IndexOutOfBounds, pos)
+}
+
+// check the divisor of `/` and `%`: rhs != 0 and !(lhs == INT_MIN && rhs == -1)
+pub fn check_division(
+    program: &mut Program,
+    context: &mut IrContext,
+    lhs: Value,
+    rhs: Value,
+    pos: Pos,
+) -> Result<(), String> {
+    if !context.sanitize.division {
+        return Ok(());
+    }
+    let rhs_const = const_of(program, context, rhs);
+    if rhs_const.is_none_or(|rhs| rhs == 0) {
+        let val_0 = const_int_value(program, context, 0);
+        let fail = binary(program, context, BinaryOp::Eq, rhs, val_0)?;
+        trap_if(program, context, fail, TrapKind::DivisionByZero, pos)?;
+    }
+    if rhs_const.is_none_or(|rhs| rhs == -1) {
+        let int_min = const_int_value(program, context, i32::MIN);
+        let neg_1 = const_int_value(program, context, -1);
+        let lhs_min = binary(program, context, BinaryOp::Eq, lhs, int_min)?;
+        let rhs_neg_1 = binary(program, context, BinaryOp::Eq, rhs, neg_1)?;
+        let fail = binary(program, context, BinaryOp::And, lhs_min, rhs_neg_1)?;
+        trap_if(program, context, fail, TrapKind::DivisionOverflow, pos)?;
+    }
+    Ok(())
+}
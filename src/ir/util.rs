@@ -1,13 +1,18 @@
 use super::const_eval::ConstI32Eval;
+use super::sanitize::SanitizeConfig;
 use super::*;
 use crate::ast::decl::*;
 use crate::ast::exp::*;
+use crate::ast::{LineIndex, Pos};
+use crate::warning::{Lint, WarningConfig, WarningEmitter};
 use koopa::ir::builder::{
-    BasicBlockBuilder, BlockBuilder, LocalBuilder, LocalInstBuilder, ValueBuilder,
+    BasicBlockBuilder, BlockBuilder, GlobalInstBuilder, LocalBuilder, LocalInstBuilder,
+    ValueBuilder,
 };
 use koopa::ir::entities::ValueData;
 use koopa::ir::{BasicBlock, FunctionData, Program, Type, Value, ValueKind};
-use std::collections::HashMap;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 
 // ============ Library Functions ============
 
@@ -31,15 +36,13 @@ pub fn init_lib_decl(program: &mut Program, context: &mut IrContext) {
         Type::get(TypeKind::Int32),
     );
     let func = program.new_func(func_data);
-    context
-        .func_table
-        .insert("getint".to_string(), func.clone());
+    context.func_table.insert("getint".to_string(), func);
 
     // getch
     let func_data =
         FunctionData::new_decl("@getch".to_string(), Vec::new(), Type::get(TypeKind::Int32));
     let func = program.new_func(func_data);
-    context.func_table.insert("getch".to_string(), func.clone());
+    context.func_table.insert("getch".to_string(), func);
 
     // getarray
     let func_data = FunctionData::new_decl(
@@ -48,9 +51,7 @@ pub fn init_lib_decl(program: &mut Program, context: &mut IrContext) {
         Type::get(TypeKind::Int32),
     );
     let func = program.new_func(func_data);
-    context
-        .func_table
-        .insert("getarray".to_string(), func.clone());
+    context.func_table.insert("getarray".to_string(), func);
 
     // putint
     let func_data = FunctionData::new_decl(
@@ -59,9 +60,7 @@ pub fn init_lib_decl(program: &mut Program, context: &mut IrContext) {
         Type::get(TypeKind::Unit),
     );
     let func = program.new_func(func_data);
-    context
-        .func_table
-        .insert("putint".to_string(), func.clone());
+    context.func_table.insert("putint".to_string(), func);
 
     // putch
     let func_data = FunctionData::new_decl(
@@ -70,7 +69,7 @@ pub fn init_lib_decl(program: &mut Program, context: &mut IrContext) {
         Type::get(TypeKind::Unit),
     );
     let func = program.new_func(func_data);
-    context.func_table.insert("putch".to_string(), func.clone());
+    context.func_table.insert("putch".to_string(), func);
 
     // putarray
     let func_data = FunctionData::new_decl(
@@ -82,9 +81,7 @@ pub fn init_lib_decl(program: &mut Program, context: &mut IrContext) {
         Type::get(TypeKind::Unit),
     );
     let func = program.new_func(func_data);
-    context
-        .func_table
-        .insert("putarray".to_string(), func.clone());
+    context.func_table.insert("putarray".to_string(), func);
 
     // starttime
     let func_data = FunctionData::new_decl(
@@ -93,9 +90,7 @@ pub fn init_lib_decl(program: &mut Program, context: &mut IrContext) {
         Type::get(TypeKind::Unit),
     );
     let func = program.new_func(func_data);
-    context
-        .func_table
-        .insert("starttime".to_string(), func.clone());
+    context.func_table.insert("starttime".to_string(), func);
 
     // stoptime
     let func_data = FunctionData::new_decl(
@@ -104,9 +99,37 @@ pub fn init_lib_decl(program: &mut Program, context: &mut IrContext) {
         Type::get(TypeKind::Unit),
     );
     let func = program.new_func(func_data);
-    context
-        .func_table
-        .insert("stoptime".to_string(), func.clone());
+    context.func_table.insert("stoptime".to_string(), func);
+}
+
+// ============ Runtime Helpers ============
+/*
+    Helpers whose bodies are provided by the backend (see asm/runtime.rs),
+    declared on first use:
+       decl @__sysy_memset(*i32, i32, i32)    // dest, value, words
+       decl @__sysy_memcpy(*i32, *i32, i32)   // dest, src, words
+    Constant templates of local arrays are globals named __sysy_init_<n>,
+    the backend puts them in .rodata.
+*/
+
+pub const MEMSET_FUNC: &str = "__sysy_memset";
+pub const MEMCPY_FUNC: &str = "__sysy_memcpy";
+pub const INIT_TEMPLATE_PREFIX: &str = "__sysy_init_";
+
+fn get_runtime_func(program: &mut Program, context: &mut IrContext, name: &str) -> Function {
+    if let Some(&func) = context.func_table.get(name) {
+        return func;
+    }
+    let ptr = Type::get_pointer(Type::get_i32());
+    let params = if name == MEMSET_FUNC {
+        vec![ptr, Type::get_i32(), Type::get_i32()]
+    } else {
+        vec![ptr.clone(), ptr, Type::get_i32()]
+    };
+    let func_data = FunctionData::new_decl(format!("@{}", name), params, Type::get(TypeKind::Unit));
+    let func = program.new_func(func_data);
+    context.func_table.insert(name.to_string(), func);
+    func
 }
 
 // ============ Basic Block utils ============
@@ -164,16 +187,12 @@ pub fn get_bb_last_value<'a>(
         .insts_mut()
         .keys()
         .last()
-        .map(|v| v.clone())
+        .copied()
 }
 
 pub fn bb_closed(program: &Program, context: &IrContext, bb: BasicBlock) -> bool {
     let func_data = program.func(context.current_func.unwrap());
-    let last_value = func_data.layout().bbs()[&bb]
-        .insts()
-        .keys()
-        .last()
-        .map(|v| v.clone());
+    let last_value = func_data.layout().bbs()[&bb].insts().keys().last().copied();
     if let Some(last_value) = last_value {
         if let ValueKind::Return(_) = get_valuekind(program, context, last_value) {
             return true;
@@ -188,6 +207,40 @@ pub fn bb_closed(program: &Program, context: &IrContext, bb: BasicBlock) -> bool
     false
 }
 
+// whether bb can be reached from the entry block, branches on constants are followed
+// only on the taken side
+pub fn bb_reachable(program: &Program, context: &IrContext, bb: BasicBlock) -> bool {
+    let func_data = program.func(context.current_func.unwrap());
+    let entry_bb = func_data.layout().entry_bb().unwrap();
+    let mut visited = HashSet::new();
+    let mut stack = vec![entry_bb];
+    while let Some(cur) = stack.pop() {
+        if !visited.insert(cur) {
+            continue;
+        }
+        if cur == bb {
+            return true;
+        }
+        let last_value = match func_data.layout().bbs()[&cur].insts().back_key() {
+            Some(&last_value) => last_value,
+            None => continue,
+        };
+        match get_valuekind(program, context, last_value) {
+            ValueKind::Jump(jump) => stack.push(jump.target()),
+            ValueKind::Branch(branch) => match get_valuekind(program, context, branch.cond()) {
+                ValueKind::Integer(cond) if cond.value() != 0 => stack.push(branch.true_bb()),
+                ValueKind::Integer(_) => stack.push(branch.false_bb()),
+                _ => {
+                    stack.push(branch.true_bb());
+                    stack.push(branch.false_bb());
+                }
+            },
+            _ => {}
+        }
+    }
+    false
+}
+
 // ============ Value utils ============
 
 pub fn new_value_builder<'a>(
@@ -262,10 +315,10 @@ pub fn get_type(program: &Program, context: &IrContext, value: Value) -> Type {
 // ============ Function utils ============
 
 pub fn get_func(context: &IrContext, ident: &str) -> Function {
-    context.func_table.get(ident).unwrap().clone()
+    *context.func_table.get(ident).unwrap()
 }
 
-pub fn get_func_data<'a>(program: &'a Program, func: Function) -> &'a FunctionData {
+pub fn get_func_data(program: &Program, func: Function) -> &FunctionData {
     program.func(func)
 }
 
@@ -276,8 +329,8 @@ pub fn get_array_elem(
     program: &mut Program,
     context: &mut IrContext,
     array: Value,
-    size: &Vec<usize>,
-    index: &Vec<Value>,
+    size: &[usize],
+    index: &[Value],
 ) -> Value {
     let mut elem = array;
     for i in index.iter() {
@@ -299,8 +352,8 @@ pub fn get_array_param_elem(
     program: &mut Program,
     context: &mut IrContext,
     array: Value,
-    size: &Vec<usize>,
-    index: &Vec<Value>,
+    size: &[usize],
+    index: &[Value],
 ) -> Value {
     let mut elem = array;
     if !index.is_empty() {
@@ -315,7 +368,7 @@ pub fn get_array_param_elem(
         elem = new_value_builder(program, context).load(elem);
         add_value(program, context, elem).unwrap();
     } else {
-        if index.len() != 0 {
+        if !index.is_empty() {
             // elem point to an array
             let val_0 = const_int_value(program, context, 0);
             elem = new_value_builder(program, context).get_elem_ptr(elem, val_0);
@@ -330,7 +383,7 @@ pub fn get_array_elem_addr(
     program: &mut Program,
     context: &mut IrContext,
     array: Value,
-    index: &Vec<Value>,
+    index: &[Value],
 ) -> Value {
     let mut elem = array;
     for i in index.iter() {
@@ -344,7 +397,7 @@ pub fn get_array_param_elem_addr(
     program: &mut Program,
     context: &mut IrContext,
     array: Value,
-    index: &Vec<Value>,
+    index: &[Value],
 ) -> Value {
     let mut elem = array;
     if !index.is_empty() {
@@ -358,6 +411,11 @@ pub fn get_array_param_elem_addr(
     elem
 }
 
+// arrays with fewer elements are initialized element by element
+const INIT_FILL_MIN_LEN: usize = 16;
+// non-zero constants worth a template
+const INIT_TEMPLATE_MIN_CONSTS: usize = 8;
+
 #[derive(Clone)]
 pub struct Array {
     data: Vec<Value>,
@@ -365,13 +423,13 @@ pub struct Array {
 }
 
 impl Array {
-    pub fn new(program: &mut Program, context: &mut IrContext, size: &Vec<usize>) -> Self {
+    pub fn new(program: &mut Program, context: &mut IrContext, size: &[usize]) -> Self {
         let len = Self::size2len(size);
         let val_0 = const_int_value(program, context, 0);
         let data = vec![val_0; len];
         Self {
             data,
-            size: size.clone(),
+            size: size.to_vec(),
         }
     }
 
@@ -394,27 +452,27 @@ impl Array {
         let mut result = 0;
         let mut factor = 1;
         for (i, &p) in idx.iter().enumerate().rev() {
-            result += p as usize * factor;
-            factor *= self.size[i] as usize;
+            result += p * factor;
+            factor *= self.size[i];
         }
         result
     }
 
-    pub fn const_exp2size(index: &Vec<ConstExp>, context: &IrContext) -> Vec<usize> {
+    pub fn const_exp2size(index: &[ConstExp], context: &IrContext) -> Vec<usize> {
         index
             .iter()
             .map(|i| i.get_const_i32(context).unwrap() as usize)
             .collect()
     }
 
-    pub fn size2len(size: &Vec<usize>) -> usize {
+    pub fn size2len(size: &[usize]) -> usize {
         size.iter().product::<usize>()
     }
 
-    pub fn size2type(size: &Vec<usize>, btype: Type) -> Type {
+    pub fn size2type(size: &[usize], btype: Type) -> Type {
         let mut ty = btype;
         for i in size.iter().rev() {
-            ty = Type::get_array(ty, *i as usize);
+            ty = Type::get_array(ty, *i);
         }
         ty
     }
@@ -424,14 +482,14 @@ impl Array {
         program: &mut Program,
         context: &mut IrContext,
         init_val: &ConstInitVal,
-        size: &Vec<usize>,
+        size: &[usize],
         start_pos: &mut usize,
     ) -> bool {
         let mut is_zero = true;
         match init_val {
             ConstInitVal::ConstExp(_) => unreachable!(),
             ConstInitVal::ConstArray(a) => {
-                let init_start_pos = start_pos.clone();
+                let init_start_pos = *start_pos;
                 for v in a.iter() {
                     match v {
                         ConstInitVal::ConstExp(e) => {
@@ -440,17 +498,17 @@ impl Array {
                                 is_zero = false;
                             }
                             let val = const_int_value(program, context, val);
-                            *self.data.get_mut(*start_pos as usize).unwrap() = val;
-                            *start_pos = *start_pos + 1;
+                            *self.data.get_mut(*start_pos).unwrap() = val;
+                            *start_pos += 1;
                         }
                         ConstInitVal::ConstArray(_) => {
                             // check current len
                             let mut len = *start_pos - init_start_pos;
                             let mut new_size: Vec<usize> = Vec::new();
                             for dim in size.iter().skip(1).rev() {
-                                if len % *dim == 0 {
+                                if len.is_multiple_of(*dim) {
                                     new_size.insert(0, *dim);
-                                    len = len / *dim;
+                                    len /= *dim;
                                 } else {
                                     break;
                                 }
@@ -464,8 +522,8 @@ impl Array {
                 // fill the rest with 0
                 let val_0 = const_int_value(program, context, 0);
                 while *start_pos < init_start_pos + size.iter().product::<usize>() {
-                    *self.data.get_mut(*start_pos as usize).unwrap() = val_0;
-                    *start_pos = *start_pos + 1;
+                    *self.data.get_mut(*start_pos).unwrap() = val_0;
+                    *start_pos += 1;
                 }
                 is_zero
             }
@@ -476,14 +534,14 @@ impl Array {
         program: &mut Program,
         context: &mut IrContext,
         init_val: &InitVal,
-        size: &Vec<usize>,
+        size: &[usize],
         start_pos: &mut usize,
     ) -> bool {
         let mut is_zero = true;
         match init_val {
             InitVal::Exp(_) => unreachable!(),
             InitVal::Array(a) => {
-                let init_start_pos = start_pos.clone();
+                let init_start_pos = *start_pos;
                 for v in a.iter() {
                     match v {
                         InitVal::Exp(e) => {
@@ -497,17 +555,17 @@ impl Array {
                                 is_zero = false;
                                 e.build_ir(program, context).unwrap()
                             };
-                            *self.data.get_mut(*start_pos as usize).unwrap() = val;
-                            *start_pos = *start_pos + 1;
+                            *self.data.get_mut(*start_pos).unwrap() = val;
+                            *start_pos += 1;
                         }
                         InitVal::Array(_) => {
                             // check current len
                             let mut len = *start_pos - init_start_pos;
                             let mut new_size: Vec<usize> = Vec::new();
                             for dim in size.iter().skip(1).rev() {
-                                if len % *dim == 0 {
+                                if len.is_multiple_of(*dim) {
                                     new_size.insert(0, *dim);
-                                    len = len / *dim;
+                                    len /= *dim;
                                 } else {
                                     break;
                                 }
@@ -520,8 +578,8 @@ impl Array {
                 // fill the rest with 0
                 let val_0 = const_int_value(program, context, 0);
                 while *start_pos < init_start_pos + size.iter().product::<usize>() {
-                    *self.data.get_mut(*start_pos as usize).unwrap() = val_0;
-                    *start_pos = *start_pos + 1;
+                    *self.data.get_mut(*start_pos).unwrap() = val_0;
+                    *start_pos += 1;
                 }
                 is_zero
             }
@@ -534,7 +592,7 @@ impl Array {
         }
         for i in (0..self.size.len()).rev() {
             let mut values_new = Vec::new();
-            let dim = self.size[i] as usize;
+            let dim = self.size[i];
             for j in (0..values.len()).step_by(dim) {
                 let val = if context.is_global {
                     program.new_value().aggregate(values[j..j + dim].to_vec())
@@ -548,14 +606,43 @@ impl Array {
         values[0]
     }
 
+    /*
+        Small arrays are initialized element by element. Larger ones are
+        first filled with a helper: copied from a constant template if
+        there are enough non-zero constants, otherwise zeroed. Only the
+        elements the fill got wrong are stored afterwards.
+    */
     pub fn init_assign_to_array(
         &self,
         program: &mut Program,
         context: &mut IrContext,
         array: Value,
     ) {
+        let consts: Vec<Option<i32>> = self
+            .data
+            .iter()
+            .map(|&v| match get_valuekind(program, context, v) {
+                ValueKind::Integer(num) => Some(num.value()),
+                _ => None,
+            })
+            .collect();
+        let non_zero = consts.iter().filter(|c| c.is_some_and(|c| c != 0)).count();
+        let filled: Vec<Option<i32>> = if self.data.len() < INIT_FILL_MIN_LEN {
+            vec![None; self.data.len()]
+        } else if non_zero >= INIT_TEMPLATE_MIN_CONSTS {
+            let template = self.to_template(program, &consts);
+            self.fill_from(program, context, array, MEMCPY_FUNC, template);
+            consts.iter().map(|c| Some(c.unwrap_or(0))).collect()
+        } else {
+            let zero = const_int_value(program, context, 0);
+            self.fill_from(program, context, array, MEMSET_FUNC, zero);
+            vec![Some(0); self.data.len()]
+        };
         for i in 0..self.data.len() {
-            let index = self
+            if filled[i].is_some() && filled[i] == consts[i] {
+                continue;
+            }
+            let index: Vec<_> = self
                 .pos2index(i)
                 .into_iter()
                 .map(|v| const_int_value(program, context, v as i32))
@@ -567,11 +654,55 @@ impl Array {
         }
     }
 
+    // a global holding the constant elements, zeros in place of the others
+    fn to_template(&self, program: &mut Program, consts: &[Option<i32>]) -> Value {
+        let mut values: Vec<Value> = consts
+            .iter()
+            .map(|c| program.new_value().integer(c.unwrap_or(0)))
+            .collect();
+        for &dim in self.size.iter().rev() {
+            values = values
+                .chunks(dim)
+                .map(|elems| program.new_value().aggregate(elems.to_vec()))
+                .collect();
+        }
+        let name = format!("@{}{}", INIT_TEMPLATE_PREFIX, program.inst_layout().len());
+        let template = program.new_value().global_alloc(values[0]);
+        program.set_value_name(template, Some(name));
+        template
+    }
+
+    // call @__sysy_memset(array, value, len) or @__sysy_memcpy(array, value, len)
+    fn fill_from(
+        &self,
+        program: &mut Program,
+        context: &mut IrContext,
+        array: Value,
+        helper: &str,
+        value: Value,
+    ) {
+        let zeros: Vec<_> = self
+            .size
+            .iter()
+            .map(|_| const_int_value(program, context, 0))
+            .collect();
+        let dest = get_array_elem_addr(program, context, array, &zeros);
+        let value = if helper == MEMCPY_FUNC {
+            get_array_elem_addr(program, context, value, &zeros)
+        } else {
+            value
+        };
+        let len = const_int_value(program, context, self.data.len() as i32);
+        let func = get_runtime_func(program, context, helper);
+        let call = new_value_builder(program, context).call(func, vec![dest, value, len]);
+        add_value(program, context, call).unwrap();
+    }
+
     pub fn get_const_init_array(
         program: &mut Program,
         context: &mut IrContext,
         init_val: &ConstInitVal,
-        size: &Vec<usize>,
+        size: &[usize],
     ) -> (Array, bool) {
         let mut start_pos = 0;
         let mut const_init_array = Array::new(program, context, size);
@@ -583,7 +714,7 @@ impl Array {
         program: &mut Program,
         context: &mut IrContext,
         init_val: &InitVal,
-        size: &Vec<usize>,
+        size: &[usize],
     ) -> (Array, bool) {
         let mut start_pos = 0;
         let mut init_array = Array::new(program, context, size);
@@ -596,9 +727,9 @@ impl Array {
 impl FuncFParam {
     pub fn to_type(&self, context: &mut IrContext) -> Type {
         match self {
-            FuncFParam::Var(btype, _) => btype.to_type(),
-            FuncFParam::Array(btype, _, size) => {
-                let size_val = size
+            FuncFParam::Var(btype, _, _) => btype.to_type(),
+            FuncFParam::Array(btype, _, size, _) => {
+                let size_val: Vec<_> = size
                     .iter()
                     .map(|exp| exp.get_const_i32(context).unwrap() as usize)
                     .collect();
@@ -610,7 +741,7 @@ impl FuncFParam {
 // ============ Symbol Table ============
 
 pub struct SymbolTableStack {
-    tables: Vec<HashMap<String, SymbolTableEntry>>,
+    tables: Vec<HashMap<String, (SymbolTableEntry, SymbolUsage)>>,
 }
 
 #[derive(Clone)]
@@ -621,6 +752,25 @@ pub enum SymbolTableEntry {
     ArrayParam(Type, Value, Vec<usize>),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SymbolKind {
+    Global,
+    Local,
+    Param,
+}
+
+// Usage information of a symbol, used for warnings
+pub struct SymbolUsage {
+    pub pos: Pos,
+    pub kind: SymbolKind,
+    // const evaluation only has `&IrContext`, so `read` is a Cell
+    pub read: Cell<bool>,
+    pub written: bool,
+    // assigned by an assignment statement, not only by its initializer
+    pub assigned: bool,
+    pub uninit_warned: bool,
+}
+
 impl SymbolTableStack {
     pub fn new() -> Self {
         SymbolTableStack { tables: Vec::new() }
@@ -628,34 +778,106 @@ impl SymbolTableStack {
     pub fn push_table(&mut self) {
         self.tables.push(HashMap::new());
     }
-    pub fn pop_table(&mut self) {
-        self.tables.pop();
+    // returns the usage of all symbols in the popped table, sorted by position
+    pub fn pop_table(&mut self) -> Vec<(String, SymbolUsage)> {
+        let mut usages: Vec<_> = self
+            .tables
+            .pop()
+            .unwrap()
+            .into_iter()
+            .map(|(name, (_, usage))| (name, usage))
+            .collect();
+        usages.sort_by_key(|(_, usage)| usage.pos);
+        usages
     }
     pub fn get_symbol(&self, name: &str) -> (Option<SymbolTableEntry>, usize) {
         for (i, table) in self.tables.iter().rev().enumerate() {
-            if let Some(entry) = table.get(name).cloned() {
-                return (Some(entry), self.tables.len() - i - 1);
+            if let Some((entry, _)) = table.get(name) {
+                return (Some(entry.clone()), self.tables.len() - i - 1);
             }
         }
         (None, 0)
     }
-    fn add_symbol(&mut self, name: &str, entry: SymbolTableEntry) {
+    pub fn get_usage(&self, name: &str) -> Option<&SymbolUsage> {
+        self.tables
+            .iter()
+            .rev()
+            .find_map(|table| table.get(name))
+            .map(|(_, usage)| usage)
+    }
+    pub fn get_usage_mut(&mut self, name: &str) -> Option<&mut SymbolUsage> {
+        self.tables
+            .iter_mut()
+            .rev()
+            .find_map(|table| table.get_mut(name))
+            .map(|(_, usage)| usage)
+    }
+    fn add_symbol(&mut self, name: &str, entry: SymbolTableEntry, kind: SymbolKind, pos: Pos) {
+        let kind = if self.tables.len() == 1 {
+            SymbolKind::Global
+        } else {
+            kind
+        };
+        let usage = SymbolUsage {
+            pos,
+            kind,
+            read: Cell::new(false),
+            // locals start uninitialized, globals are zero-initialized
+            written: kind != SymbolKind::Local || !matches!(entry, SymbolTableEntry::Var(..)),
+            assigned: false,
+            uninit_warned: false,
+        };
         self.tables
             .last_mut()
             .unwrap()
-            .insert(name.to_string(), entry);
+            .insert(name.to_string(), (entry, usage));
+    }
+    pub fn add_var(&mut self, name: &str, ty: Type, value: Value, pos: Pos) {
+        self.add_symbol(
+            name,
+            SymbolTableEntry::Var(ty, value),
+            SymbolKind::Local,
+            pos,
+        );
     }
-    pub fn add_var(&mut self, name: &str, ty: Type, value: Value) {
-        self.add_symbol(name, SymbolTableEntry::Var(ty, value));
+    pub fn add_var_param(&mut self, name: &str, ty: Type, value: Value, pos: Pos) {
+        self.add_symbol(
+            name,
+            SymbolTableEntry::Var(ty, value),
+            SymbolKind::Param,
+            pos,
+        );
     }
-    pub fn add_const(&mut self, name: &str, ty: Type, value: i32) {
-        self.add_symbol(name, SymbolTableEntry::Const(ty, value));
+    pub fn add_const(&mut self, name: &str, ty: Type, value: i32, pos: Pos) {
+        self.add_symbol(
+            name,
+            SymbolTableEntry::Const(ty, value),
+            SymbolKind::Local,
+            pos,
+        );
     }
-    pub fn add_array(&mut self, name: &str, ty: Type, value: Value, size: Vec<usize>) {
-        self.add_symbol(name, SymbolTableEntry::Array(ty, value, size));
+    pub fn add_array(&mut self, name: &str, ty: Type, value: Value, size: Vec<usize>, pos: Pos) {
+        self.add_symbol(
+            name,
+            SymbolTableEntry::Array(ty, value, size),
+            SymbolKind::Local,
+            pos,
+        );
     }
-    pub fn add_array_param(&mut self, name: &str, ty: Type, value: Value, size: Vec<usize>) {
-        self.add_symbol(name, SymbolTableEntry::ArrayParam(ty, value, size));
+    pub fn add_array_param(
+        &mut self,
+        name: &str,
+        ty: Type,
+        value: Value,
+        size: Vec<usize>,
+        pos: Pos,
+    ) {
+        self.add_symbol(
+            name,
+            SymbolTableEntry::ArrayParam(ty, value, size),
+            SymbolKind::Param,
+            pos,
+        );
     }
     pub fn get_depth(&self) -> usize {
         self.tables.len() - 1
@@ -723,6 +945,13 @@ pub struct IrContext {
     pub name_manager: NameManager,
     pub while_stack: WhileStack,
     pub is_global: bool,
+    pub warnings: WarningEmitter,
+    // user functions in definition order, and the functions called by others
+    pub func_defs: Vec<(String, Pos)>,
+    pub called_funcs: HashSet<String>,
+    pub sanitize: SanitizeConfig,
+    // maps positions to the source locations reported by runtime checks
+    pub line_index: LineIndex,
 }
 
 impl IrContext {
@@ -735,6 +964,11 @@ impl IrContext {
             name_manager: NameManager::new(),
             while_stack: WhileStack::new(),
             is_global: true,
+            warnings: WarningEmitter::new(WarningConfig::new()),
+            func_defs: Vec::new(),
+            called_funcs: HashSet::new(),
+            sanitize: SanitizeConfig::new(),
+            line_index: LineIndex::new(""),
         };
         ret.symbol_tables.push_table(); // 全局变量表
         ret
@@ -744,6 +978,86 @@ impl IrContext {
         self.current_bb = None;
         self.while_stack.clear();
     }
+
+    // ---- warnings ----
+
+    // pop the innermost scope, reporting symbols that were never read
+    pub fn pop_scope(&mut self) {
+        for (name, usage) in self.symbol_tables.pop_table() {
+            if usage.read.get() {
+                continue;
+            }
+            match usage.kind {
+                SymbolKind::Local => self.warnings.warn(
+                    Lint::UnusedVariable,
+                    usage.pos,
+                    if usage.assigned {
+                        format!("variable '{}' set but not used", name)
+                    } else {
+                        format!("unused variable '{}'", name)
+                    },
+                ),
+                SymbolKind::Param => self.warnings.warn(
+                    Lint::UnusedParameter,
+                    usage.pos,
+                    format!("unused parameter '{}'", name),
+                ),
+                SymbolKind::Global => {}
+            }
+        }
+    }
+
+    pub fn check_shadow(&mut self, name: &str, pos: Pos) {
+        let (entry, depth) = self.symbol_tables.get_symbol(name);
+        if entry.is_none() || depth == self.symbol_tables.get_depth() {
+            return;
+        }
+        let message = if depth == 0 {
+            format!("declaration of '{}' shadows a global declaration", name)
+        } else {
+            format!("declaration of '{}' shadows a previous local", name)
+        };
+        self.warnings.warn(Lint::Shadow, pos, message);
+    }
+
+    pub fn note_read(&mut self, name: &str, pos: Pos) {
+        if let Some(usage) = self.symbol_tables.get_usage_mut(name) {
+            usage.read.set(true);
+            if !usage.written && !usage.uninit_warned {
+                usage.uninit_warned = true;
+                self.warnings.warn(
+                    Lint::Uninitialized,
+                    pos,
+                    format!("'{}' is used uninitialized", name),
+                );
+            }
+        }
+    }
+
+    pub fn note_write(&mut self, name: &str) {
+        if let Some(usage) = self.symbol_tables.get_usage_mut(name) {
+            usage.written = true;
+        }
+    }
+
+    pub fn note_assign(&mut self, name: &str) {
+        if let Some(usage) = self.symbol_tables.get_usage_mut(name) {
+            usage.written = true;
+            usage.assigned = true;
+        }
+    }
+
+    pub fn report_unused_funcs(&mut self) {
+        for (name, pos) in self.func_defs.iter() {
+            if name != "main" && !self.called_funcs.contains(name) {
+                self.warnings.warn(
+                    Lint::UnusedFunction,
+                    *pos,
+                    format!("function '{}' is never called", name),
+                );
+            }
+        }
+    }
 }
 
 // ============ Debug ============
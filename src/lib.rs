@@ -0,0 +1,19 @@
+#![cfg_attr(
+    debug_assertions,
+    allow(dead_code, unused_imports, unused_variables, non_snake_case)
+)]
+
+use lalrpop_util::lalrpop_mod;
+
+pub mod asm;
+pub mod ast;
+pub mod ir;
+pub mod opt;
+pub mod semantic;
+pub mod session;
+pub mod warning;
+
+// 引用 lalrpop 生成的解析器
+lalrpop_mod!(#[allow(clippy::all)] pub sysy);
+
+pub use session::{CompileError, Compiler, Options, Session};
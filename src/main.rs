@@ -1,59 +1,120 @@
-#![cfg_attr(
-    debug_assertions,
-    allow(dead_code, unused_imports, unused_variables, non_snake_case)
-)]
-
-use crate::asm::*;
-use crate::ir::*;
-use koopa::ir::Type;
-use lalrpop_util::lalrpop_mod;
+mod cli;
+
+use cli::{Action, Args, EmitKind, Output};
 use std::env::args;
-use std::fs::read_to_string;
-use std::io::Result;
-use std::io::Write;
-
-pub mod asm;
-pub mod ast;
-pub mod ir;
-
-// 引用 lalrpop 生成的解析器
-// 因为我们刚刚创建了 sysy.lalrpop, 所以模块名是 sysy
-lalrpop_mod!(sysy);
-
-fn main() -> Result<()> {
-    // INIT
-    Type::set_ptr_size(4);
-    // 解析命令行参数
-    let mut args = args();
-    args.next();
-    let mode = args.next().unwrap();
-    let input = args.next().unwrap();
-    args.next();
-    let output = args.next().unwrap();
-
-    // 读取输入文件
-    let input = read_to_string(input)?;
-
-    // 调用 lalrpop 生成的 parser 解析输入文件
-    let ast = sysy::CompUnitParser::new().parse(&input).unwrap();
-    let koopa_program = compile(&ast);
-
-    // 输出解析得到的 AST 到输出文件
-    match mode.as_str() {
-        "-koopa" => {
-            use koopa::back::KoopaGenerator;
-            let mut generator = KoopaGenerator::new(Vec::new());
-            generator.generate_on(&koopa_program).unwrap();
-            let koopa_str = String::from_utf8(generator.writer().to_vec()).unwrap();
-            let mut output = std::fs::File::create(output)?;
-            write!(output, "{}", koopa_str)?;
+use std::fs::{read_to_string, File};
+use std::io::{self, Read, Write};
+use std::process::exit;
+use sysy_compiler::asm::frame_sizes;
+use sysy_compiler::opt::pass_list;
+use sysy_compiler::{CompileError, Compiler, Session};
+
+fn main() {
+    let args = match cli::parse_args(args().skip(1)) {
+        Ok(Action::Compile(args)) => args,
+        Ok(Action::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        }
+        Ok(Action::ListPasses) => {
+            for (name, description) in pass_list() {
+                println!("  {:<16}{}", name, description);
+            }
+            return;
+        }
+        Ok(Action::Version) => {
+            println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+            return;
+        }
+        Err(err) => fail(&format!("{} (see --help)", err)),
+    };
+
+    // 读取输入, 没有输入文件时读 stdin
+    let (file_name, input) = match &args.input {
+        Some(file) => (
+            file.as_str(),
+            read_to_string(file)
+                .unwrap_or_else(|err| fail(&format!("cannot read {}: {}", file, err))),
+        ),
+        None => {
+            let mut input = String::new();
+            io::stdin()
+                .read_to_string(&mut input)
+                .unwrap_or_else(|err| fail(&format!("cannot read stdin: {}", err)));
+            ("<stdin>", input)
         }
-        "-riscv" | "-perf" => {
-            let mut output = std::fs::File::create(output)?;
-            let asm_str = koopa_to_asm(&koopa_program);
-            write!(output, "{}", asm_str)?;
+    };
+
+    let compiler = Compiler::new(args.options.clone());
+    let mut session = compiler.session(file_name, &input);
+    let result = run(&mut session, &args);
+    // 输出警告
+    for warning in session.warnings() {
+        eprintln!("{}", session.render_warning(warning));
+    }
+    if let Err(err) = result {
+        eprintln!("{}", session.render_error(&err));
+        exit(1);
+    }
+}
+
+// compile and write every requested artifact
+fn run(session: &mut Session, args: &Args) -> Result<(), CompileError> {
+    let ast = session.parse()?;
+    for (kind, output) in args.emits.iter() {
+        if *kind == EmitKind::Ast {
+            session.emit_ast(&ast, &mut *open(output)?)?;
+        }
+    }
+    if args.emits.iter().all(|(kind, _)| *kind == EmitKind::Ast) {
+        return Ok(());
+    }
+    session.check(&ast)?;
+    let mut program = session.build_ir(&ast)?;
+    session.run_passes(&mut program)?;
+
+    if args.stack_usage {
+        for (func, size) in frame_sizes(&program, &session.options().asm) {
+            eprintln!("{}\t{}", func, size);
+        }
+    }
+    let mut asm = None;
+    for (kind, output) in args.emits.iter() {
+        match kind {
+            EmitKind::Ast => {}
+            EmitKind::Koopa => session.emit_koopa(&program, &mut *open(output)?)?,
+            EmitKind::Riscv => session.emit_asm(&program, &mut *open(output)?)?,
+            EmitKind::Obj => {
+                if asm.is_none() {
+                    let mut buf = Vec::new();
+                    session.emit_asm(&program, &mut buf)?;
+                    asm = Some(String::from_utf8(buf).unwrap());
+                }
+                let file = match output {
+                    Output::Stdout => "-",
+                    Output::File(file) => file.as_str(),
+                };
+                cli::assemble(asm.as_ref().unwrap(), file, args.assembler.as_deref())
+                    .map_err(|err| CompileError::Io(io::Error::other(err)))?;
+            }
         }
-        _ => panic!("Unknown mode: {}", mode),
     }
     Ok(())
 }
+
+fn open(output: &Output) -> Result<Box<dyn Write>, CompileError> {
+    Ok(match output {
+        Output::Stdout => Box::new(io::stdout()),
+        Output::File(file) => Box::new(File::create(file).map_err(|err| {
+            CompileError::Io(io::Error::new(
+                err.kind(),
+                format!("cannot create {}: {}", file, err),
+            ))
+        })?),
+    })
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("error: {}", message);
+    exit(1);
+}
@@ -0,0 +1,184 @@
+use koopa::ir::{FunctionData, TypeKind, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+// ============ Alias Analysis ============
+/*
+    Every pointer is traced back through getelemptr/getptr to the object it
+    points into: a local alloc, a global, or something unknown (a function
+    argument or a loaded pointer). Distinct allocs and globals never alias;
+    unknown pointers may alias any global and any alloc whose address escapes.
+    Within one object, a pointer is a byte offset from its start: a constant
+    plus the non-constant indices times their scales. Pointers with the same
+    non-constant part do not alias if the constant parts keep the memory they
+    point to apart. getelemptr and getptr add up the same way, so
+    `getptr(getelemptr(@g, 0), 1)` and `getelemptr(@g, 1)` are the same address.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Base {
+    Alloc(Value),
+    Global(Value),
+    Unknown,
+}
+
+// in bytes, constant + sum of index * scale
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Offset {
+    constant: i64,
+    scaled: HashMap<Value, i64>,
+}
+
+impl Offset {
+    fn add(&mut self, data: &FunctionData, index: Value, scale: i64) {
+        match int_value(data, index) {
+            Some(index) => self.constant += index as i64 * scale,
+            None => {
+                let total = self.scaled.entry(index).or_insert(0);
+                *total += scale;
+                if *total == 0 {
+                    self.scaled.remove(&index);
+                }
+            }
+        }
+    }
+}
+
+pub struct AliasAnalysis {
+    // pointer -> (object, offset into the object)
+    paths: HashMap<Value, (Base, Offset)>,
+    // allocs whose address is passed to a call or a block, or stored to memory
+    escaped: HashSet<Value>,
+}
+
+impl AliasAnalysis {
+    pub fn new(data: &FunctionData) -> Self {
+        let mut analysis = AliasAnalysis {
+            paths: HashMap::new(),
+            escaped: HashSet::new(),
+        };
+        for (_, node) in data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Call(call) => {
+                        for &arg in call.args() {
+                            analysis.escape(data, arg);
+                        }
+                    }
+                    ValueKind::Store(store) => analysis.escape(data, store.value()),
+                    ValueKind::Jump(jump) => {
+                        for &arg in jump.args() {
+                            analysis.escape(data, arg);
+                        }
+                    }
+                    ValueKind::Branch(branch) => {
+                        for &arg in branch.true_args().iter().chain(branch.false_args()) {
+                            analysis.escape(data, arg);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        analysis
+    }
+
+    pub fn base(&mut self, data: &FunctionData, ptr: Value) -> Base {
+        self.path(data, ptr).0
+    }
+
+    // whether the two pointers may refer to overlapping memory
+    pub fn may_alias(&mut self, data: &FunctionData, a: Value, b: Value) -> bool {
+        if a == b {
+            return true;
+        }
+        let (base_a, offset_a) = self.path(data, a);
+        let (base_b, offset_b) = self.path(data, b);
+        match (base_a, base_b) {
+            (Base::Unknown, Base::Unknown) => true,
+            (Base::Unknown, other) | (other, Base::Unknown) => self.reachable_from_unknown(other),
+            _ if base_a != base_b => false,
+            _ if offset_a.scaled != offset_b.scaled => true,
+            _ => {
+                let (start_a, start_b) = (offset_a.constant, offset_b.constant);
+                start_a < start_b + pointee_size(data, b)
+                    && start_b < start_a + pointee_size(data, a)
+            }
+        }
+    }
+
+    // whether a call may read or write memory `ptr` points into
+    pub fn call_may_access(&mut self, data: &FunctionData, ptr: Value) -> bool {
+        let base = self.base(data, ptr);
+        self.reachable_from_unknown(base)
+    }
+
+    fn reachable_from_unknown(&self, base: Base) -> bool {
+        match base {
+            Base::Alloc(alloc) => self.escaped.contains(&alloc),
+            _ => true,
+        }
+    }
+
+    fn escape(&mut self, data: &FunctionData, value: Value) {
+        let is_pointer = value.is_global()
+            || matches!(
+                data.dfg().value(value).kind(),
+                ValueKind::Alloc(_) | ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_)
+            );
+        if is_pointer {
+            if let Base::Alloc(alloc) = self.base(data, value) {
+                self.escaped.insert(alloc);
+            }
+        }
+    }
+
+    fn path(&mut self, data: &FunctionData, ptr: Value) -> (Base, Offset) {
+        if let Some(path) = self.paths.get(&ptr) {
+            return path.clone();
+        }
+        let path = if ptr.is_global() {
+            (Base::Global(ptr), Offset::default())
+        } else {
+            // both step over elements of the type the result points to
+            let scale = pointee_size(data, ptr);
+            match data.dfg().value(ptr).kind() {
+                ValueKind::Alloc(_) => (Base::Alloc(ptr), Offset::default()),
+                ValueKind::GetElemPtr(gep) => {
+                    let (base, mut offset) = self.path(data, gep.src());
+                    offset.add(data, gep.index(), scale);
+                    (base, offset)
+                }
+                ValueKind::GetPtr(gp) => {
+                    let (base, mut offset) = self.path(data, gp.src());
+                    offset.add(data, gp.index(), scale);
+                    (base, offset)
+                }
+                _ => (Base::Unknown, Offset::default()),
+            }
+        };
+        self.paths.insert(ptr, path.clone());
+        path
+    }
+}
+
+// size in bytes of what `ptr` points to
+fn pointee_size(data: &FunctionData, ptr: Value) -> i64 {
+    // a function cannot see the type of a global, take the whole object
+    if ptr.is_global() {
+        return i64::MAX / 2;
+    }
+    match data.dfg().value(ptr).ty().kind() {
+        TypeKind::Pointer(base) => base.size() as i64,
+        _ => unreachable!(),
+    }
+}
+
+fn int_value(data: &FunctionData, value: Value) -> Option<i32> {
+    if value.is_global() {
+        return None;
+    }
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(num) => Some(num.value()),
+        _ => None,
+    }
+}
@@ -0,0 +1,90 @@
+use koopa::ir::{Function, FunctionData, Program, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+// ============ Call Graph ============
+
+// the call instructions of a function and their callees, in layout order
+pub fn calls_of(data: &FunctionData) -> Vec<(Value, Function)> {
+    let mut calls = Vec::new();
+    for (_, node) in data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            if let ValueKind::Call(call) = data.dfg().value(inst).kind() {
+                calls.push((inst, call.callee()));
+            }
+        }
+    }
+    calls
+}
+
+// Tarjan's algorithm over the functions with a body; SCCs come out callees first
+pub fn call_graph_sccs(program: &Program) -> Vec<Vec<Function>> {
+    struct Tarjan<'a> {
+        program: &'a Program,
+        index: HashMap<Function, usize>,
+        low: HashMap<Function, usize>,
+        stack: Vec<Function>,
+        on_stack: HashSet<Function>,
+        sccs: Vec<Vec<Function>>,
+    }
+    impl Tarjan<'_> {
+        fn visit(&mut self, func: Function) {
+            let index = self.index.len();
+            self.index.insert(func, index);
+            self.low.insert(func, index);
+            self.stack.push(func);
+            self.on_stack.insert(func);
+            for (_, callee) in calls_of(self.program.func(func)) {
+                if self.program.func(callee).layout().entry_bb().is_none() {
+                    continue;
+                }
+                if !self.index.contains_key(&callee) {
+                    self.visit(callee);
+                    let low = self.low[&func].min(self.low[&callee]);
+                    self.low.insert(func, low);
+                } else if self.on_stack.contains(&callee) {
+                    let low = self.low[&func].min(self.index[&callee]);
+                    self.low.insert(func, low);
+                }
+            }
+            if self.low[&func] == index {
+                let mut scc = Vec::new();
+                loop {
+                    let member = self.stack.pop().unwrap();
+                    self.on_stack.remove(&member);
+                    scc.push(member);
+                    if member == func {
+                        break;
+                    }
+                }
+                self.sccs.push(scc);
+            }
+        }
+    }
+    let mut tarjan = Tarjan {
+        program,
+        index: HashMap::new(),
+        low: HashMap::new(),
+        stack: Vec::new(),
+        on_stack: HashSet::new(),
+        sccs: Vec::new(),
+    };
+    for &func in program.func_layout() {
+        if program.func(func).layout().entry_bb().is_some() && !tarjan.index.contains_key(&func) {
+            tarjan.visit(func);
+        }
+    }
+    tarjan.sccs
+}
+
+// every call to `callee` as (caller, call), in layout order
+pub fn call_sites(program: &Program, callee: Function) -> Vec<(Function, Value)> {
+    let mut sites = Vec::new();
+    for &caller in program.func_layout() {
+        for (call, target) in calls_of(program.func(caller)) {
+            if target == callee {
+                sites.push((caller, call));
+            }
+        }
+    }
+    sites
+}
@@ -0,0 +1,69 @@
+use crate::opt::util::successors;
+use koopa::ir::{BasicBlock, FunctionData};
+use std::collections::{HashMap, HashSet};
+
+// ============ Control Flow Graph ============
+
+pub struct Cfg {
+    pub entry: BasicBlock,
+    pub succs: HashMap<BasicBlock, Vec<BasicBlock>>,
+    pub preds: HashMap<BasicBlock, Vec<BasicBlock>>,
+    // reverse postorder of the blocks reachable from the entry
+    pub rpo: Vec<BasicBlock>,
+}
+
+impl Cfg {
+    // None for function declarations
+    pub fn new(data: &FunctionData) -> Option<Self> {
+        let entry = data.layout().entry_bb()?;
+        let mut succs = HashMap::new();
+        let mut preds: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        for &bb in data.layout().bbs().keys() {
+            preds.entry(bb).or_default();
+        }
+        // predecessors are in layout order, so the result is deterministic
+        for &bb in data.layout().bbs().keys() {
+            let targets = successors(data, bb);
+            for &target in targets.iter() {
+                let target_preds = preds.entry(target).or_default();
+                if !target_preds.contains(&bb) {
+                    target_preds.push(bb);
+                }
+            }
+            succs.insert(bb, targets);
+        }
+
+        let mut postorder = Vec::new();
+        let mut visited = HashSet::new();
+        // iterative DFS: (block, index of the next successor to visit)
+        let mut stack = vec![(entry, 0)];
+        visited.insert(entry);
+        while let Some((bb, next)) = stack.pop() {
+            let bb_succs: &Vec<BasicBlock> = &succs[&bb];
+            if next < bb_succs.len() {
+                stack.push((bb, next + 1));
+                let succ = bb_succs[next];
+                if succs.contains_key(&succ) && visited.insert(succ) {
+                    stack.push((succ, 0));
+                }
+            } else {
+                postorder.push(bb);
+            }
+        }
+        postorder.reverse();
+        Some(Cfg {
+            entry,
+            succs,
+            preds,
+            rpo: postorder,
+        })
+    }
+
+    pub fn succs(&self, bb: BasicBlock) -> &[BasicBlock] {
+        self.succs.get(&bb).map_or(&[], |succs| succs.as_slice())
+    }
+
+    pub fn preds(&self, bb: BasicBlock) -> &[BasicBlock] {
+        self.preds.get(&bb).map_or(&[], |preds| preds.as_slice())
+    }
+}
@@ -0,0 +1,103 @@
+use super::Cfg;
+use koopa::ir::BasicBlock;
+use std::collections::HashMap;
+
+// ============ Dominator Tree ============
+// Cooper, Harvey, Kennedy: "A Simple, Fast Dominance Algorithm"
+
+pub struct DomTree {
+    // immediate dominator, the entry maps to itself; unreachable blocks are absent
+    idom: HashMap<BasicBlock, BasicBlock>,
+    // position in reverse postorder
+    order: HashMap<BasicBlock, usize>,
+    children: HashMap<BasicBlock, Vec<BasicBlock>>,
+    entry: BasicBlock,
+}
+
+impl DomTree {
+    pub fn new(cfg: &Cfg) -> Self {
+        let order: HashMap<BasicBlock, usize> =
+            cfg.rpo.iter().enumerate().map(|(i, &bb)| (bb, i)).collect();
+        let mut idom = HashMap::new();
+        idom.insert(cfg.entry, cfg.entry);
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &bb in cfg.rpo.iter().skip(1) {
+                let mut new_idom = None;
+                for &pred in cfg.preds(bb) {
+                    if !idom.contains_key(&pred) {
+                        continue;
+                    }
+                    new_idom = Some(match new_idom {
+                        None => pred,
+                        Some(other) => intersect(&idom, &order, pred, other),
+                    });
+                }
+                let new_idom = new_idom.unwrap();
+                if idom.get(&bb) != Some(&new_idom) {
+                    idom.insert(bb, new_idom);
+                    changed = true;
+                }
+            }
+        }
+        let mut children: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+        for &bb in cfg.rpo.iter().skip(1) {
+            children.entry(idom[&bb]).or_default().push(bb);
+        }
+        DomTree {
+            idom,
+            order,
+            children,
+            entry: cfg.entry,
+        }
+    }
+
+    pub fn is_reachable(&self, bb: BasicBlock) -> bool {
+        self.idom.contains_key(&bb)
+    }
+
+    // None for the entry and unreachable blocks
+    pub fn idom(&self, bb: BasicBlock) -> Option<BasicBlock> {
+        self.idom.get(&bb).copied().filter(|_| bb != self.entry)
+    }
+
+    pub fn children(&self, bb: BasicBlock) -> &[BasicBlock] {
+        self.children.get(&bb).map_or(&[], |children| children.as_slice())
+    }
+
+    // every block dominates itself
+    pub fn dominates(&self, a: BasicBlock, b: BasicBlock) -> bool {
+        if !self.is_reachable(a) || !self.is_reachable(b) {
+            return false;
+        }
+        let mut bb = b;
+        loop {
+            if bb == a {
+                return true;
+            }
+            // an idom always comes earlier in reverse postorder, once past a it cannot be reached
+            if self.order[&bb] < self.order[&a] || bb == self.entry {
+                return false;
+            }
+            bb = self.idom[&bb];
+        }
+    }
+}
+
+fn intersect(
+    idom: &HashMap<BasicBlock, BasicBlock>,
+    order: &HashMap<BasicBlock, usize>,
+    mut a: BasicBlock,
+    mut b: BasicBlock,
+) -> BasicBlock {
+    while a != b {
+        while order[&a] > order[&b] {
+            a = idom[&a];
+        }
+        while order[&b] > order[&a] {
+            b = idom[&b];
+        }
+    }
+    a
+}
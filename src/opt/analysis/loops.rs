@@ -0,0 +1,174 @@
+use super::{Cfg, DomTree};
+use crate::opt::util::new_bb;
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, FunctionData, Value, ValueKind};
+use std::collections::{HashMap, HashSet};
+
+// ============ Natural Loops ============
+
+pub struct Loop {
+    pub header: BasicBlock,
+    // blocks of the loop, including the header and the blocks of inner loops
+    pub blocks: HashSet<BasicBlock>,
+    // sources of the back edges
+    pub latches: Vec<BasicBlock>,
+    // index of the innermost enclosing loop
+    pub parent: Option<usize>,
+    // 1 for outermost loops
+    pub depth: usize,
+}
+
+impl Loop {
+    // constants, function parameters and instructions outside the loop
+    pub fn is_invariant(&self, data: &FunctionData, value: Value) -> bool {
+        match data.dfg().value(value).kind() {
+            ValueKind::Integer(_) | ValueKind::FuncArgRef(_) => true,
+            ValueKind::BlockArgRef(_) => false,
+            _ => data
+                .layout()
+                .parent_bb(value)
+                .is_some_and(|bb| !self.blocks.contains(&bb)),
+        }
+    }
+}
+
+pub struct LoopInfo {
+    // ordered by the reverse postorder of the headers, so outer loops come first
+    pub loops: Vec<Loop>,
+    // innermost loop of every block in a loop
+    innermost: HashMap<BasicBlock, usize>,
+}
+
+impl LoopInfo {
+    pub fn new(cfg: &Cfg, dom: &DomTree) -> Self {
+        let mut loops: Vec<Loop> = Vec::new();
+        for &header in cfg.rpo.iter() {
+            // back edges: header dominates their source
+            let latches: Vec<BasicBlock> = cfg
+                .preds(header)
+                .iter()
+                .filter(|&&pred| dom.dominates(header, pred))
+                .copied()
+                .collect();
+            if latches.is_empty() {
+                continue;
+            }
+            let mut blocks = HashSet::from([header]);
+            let mut stack = latches.clone();
+            while let Some(bb) = stack.pop() {
+                if blocks.insert(bb) {
+                    stack.extend(cfg.preds(bb).iter().filter(|pred| dom.is_reachable(**pred)));
+                }
+            }
+            loops.push(Loop {
+                header,
+                blocks,
+                latches,
+                parent: None,
+                depth: 1,
+            });
+        }
+        // the header of an outer loop comes earlier in reverse postorder
+        for i in 0..loops.len() {
+            let parent = (0..i)
+                .rev()
+                .find(|&j| loops[j].blocks.contains(&loops[i].header));
+            if let Some(parent) = parent {
+                loops[i].parent = Some(parent);
+                loops[i].depth = loops[parent].depth + 1;
+            }
+        }
+        let mut innermost = HashMap::new();
+        for (i, lp) in loops.iter().enumerate() {
+            for &bb in lp.blocks.iter() {
+                innermost.insert(bb, i);
+            }
+        }
+        LoopInfo { loops, innermost }
+    }
+
+    // innermost loop containing `bb`
+    pub fn loop_of(&self, bb: BasicBlock) -> Option<usize> {
+        self.innermost.get(&bb).copied()
+    }
+
+    // number of loops containing `bb`, 0 outside loops
+    pub fn depth(&self, bb: BasicBlock) -> usize {
+        self.loop_of(bb).map_or(0, |i| self.loops[i].depth)
+    }
+}
+
+// the only block entering the loop, if it ends with a jump to the header
+pub fn preheader(cfg: &Cfg, lp: &Loop) -> Option<BasicBlock> {
+    let entering: Vec<BasicBlock> = cfg
+        .preds(lp.header)
+        .iter()
+        .filter(|pred| !lp.blocks.contains(*pred))
+        .copied()
+        .collect();
+    match entering[..] {
+        [pred] if cfg.succs(pred) == [lp.header] => Some(pred),
+        _ => None,
+    }
+}
+
+// give the loop a preheader, redirecting the edges entering the header;
+// the cfg is stale afterwards
+pub fn insert_preheader(data: &mut FunctionData, cfg: &Cfg, lp: &Loop) -> BasicBlock {
+    if let Some(pre) = preheader(cfg, lp) {
+        return pre;
+    }
+    let header = lp.header;
+    let tys = data
+        .dfg()
+        .bb(header)
+        .params()
+        .iter()
+        .map(|&param| data.dfg().value(param).ty().clone())
+        .collect();
+    let pre = new_bb(data, "preheader", tys);
+    let params = data.dfg().bb(pre).params().to_vec();
+    let jump = data.dfg_mut().new_value().jump_with_args(header, params);
+    data.layout_mut()
+        .bbs_mut()
+        .cursor_mut(header)
+        .insert_key_before(pre)
+        .unwrap();
+    data.layout_mut()
+        .bb_mut(pre)
+        .insts_mut()
+        .push_key_back(jump)
+        .unwrap();
+
+    let entering: Vec<Value> = data
+        .dfg()
+        .bb(header)
+        .used_by()
+        .iter()
+        .filter(|&&user| {
+            user != jump
+                && data
+                    .layout()
+                    .parent_bb(user)
+                    .is_some_and(|bb| !lp.blocks.contains(&bb))
+        })
+        .copied()
+        .collect();
+    for user in entering {
+        let mut user_data = data.dfg().value(user).clone();
+        match user_data.kind_mut() {
+            ValueKind::Jump(jump) => *jump.target_mut() = pre,
+            ValueKind::Branch(branch) => {
+                if branch.true_bb() == header {
+                    *branch.true_bb_mut() = pre;
+                }
+                if branch.false_bb() == header {
+                    *branch.false_bb_mut() = pre;
+                }
+            }
+            _ => unreachable!(),
+        }
+        data.dfg_mut().replace_value_with(user).raw(user_data);
+    }
+    pre
+}
@@ -0,0 +1,13 @@
+mod alias;
+mod call_graph;
+mod cfg;
+mod dom;
+mod loops;
+mod purity;
+
+pub use alias::{AliasAnalysis, Base};
+pub use call_graph::{call_graph_sccs, call_sites, calls_of};
+pub use cfg::Cfg;
+pub use dom::DomTree;
+pub use loops::{insert_preheader, preheader, Loop, LoopInfo};
+pub use purity::pure_functions;
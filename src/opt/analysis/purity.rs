@@ -0,0 +1,97 @@
+use super::call_graph::calls_of;
+use koopa::ir::{Function, FunctionData, Program, Value, ValueKind};
+use std::collections::HashSet;
+
+// ============ Function Purity ============
+/*
+    A function is pure if its result only depends on its arguments and
+    calling it has no visible effect: it only loads and stores through its
+    own allocs, besides loading globals that no function ever writes, and
+    only calls pure functions. Reading pointer parameters or written globals
+    disqualifies it, as memory may change between two calls. Library
+    declarations are not pure.
+    All functions start as pure and those breaking a rule are removed until
+    nothing changes, so recursion does not make a function impure.
+*/
+
+pub fn pure_functions(program: &Program) -> HashSet<Function> {
+    let written = written_globals(program);
+    let mut pure: HashSet<Function> = program
+        .func_layout()
+        .iter()
+        .copied()
+        .filter(|&func| {
+            let data = program.func(func);
+            data.layout().entry_bb().is_some() && accesses_only_locals(data, &written)
+        })
+        .collect();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &func in program.func_layout() {
+            if pure.contains(&func)
+                && calls_of(program.func(func))
+                    .iter()
+                    .any(|(_, callee)| !pure.contains(callee))
+            {
+                pure.remove(&func);
+                changed = true;
+            }
+        }
+    }
+    pure
+}
+
+// the global or the alloc a pointer is derived from, or the parameter or
+// block parameter it comes from
+fn root_of(data: &FunctionData, mut ptr: Value) -> Value {
+    while !ptr.is_global() {
+        ptr = match data.dfg().value(ptr).kind() {
+            ValueKind::GetElemPtr(gep) => gep.src(),
+            ValueKind::GetPtr(gp) => gp.src(),
+            _ => break,
+        };
+    }
+    ptr
+}
+
+// globals stored to, or whose address is stored or passed to a call somewhere
+fn written_globals(program: &Program) -> HashSet<Value> {
+    let mut written = HashSet::new();
+    for &func in program.func_layout() {
+        let data = program.func(func);
+        for (_, node) in data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                let ptrs: Vec<Value> = match data.dfg().value(inst).kind() {
+                    ValueKind::Store(store) => vec![store.value(), store.dest()],
+                    ValueKind::Call(call) => call.args().to_vec(),
+                    _ => continue,
+                };
+                written.extend(
+                    ptrs.into_iter()
+                        .map(|ptr| root_of(data, ptr))
+                        .filter(|root| root.is_global()),
+                );
+            }
+        }
+    }
+    written
+}
+
+fn accesses_only_locals(data: &FunctionData, written: &HashSet<Value>) -> bool {
+    let is_local = |root: Value| {
+        !root.is_global() && matches!(data.dfg().value(root).kind(), ValueKind::Alloc(_))
+    };
+    data.layout().bbs().nodes().all(|node| {
+        node.insts()
+            .keys()
+            .all(|&inst| match data.dfg().value(inst).kind() {
+                ValueKind::Load(load) => {
+                    let root = root_of(data, load.src());
+                    is_local(root) || (root.is_global() && !written.contains(&root))
+                }
+                ValueKind::Store(store) => is_local(root_of(data, store.dest())),
+                _ => true,
+            })
+    })
+}
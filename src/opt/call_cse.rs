@@ -0,0 +1,95 @@
+use super::analysis::{pure_functions, Cfg, DomTree};
+use super::util::{remove_inst, replace_uses};
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Value, ValueKind};
+use koopa::opt::ModulePass;
+use std::collections::{HashMap, HashSet};
+
+// ============ Pure Call Elimination ============
+/*
+    A call to a pure function with the same arguments as a call in a
+    dominating position returns the same value, and is replaced by it.
+    Walks the dominator tree in preorder like gvn. A pure function neither
+    reads nor writes the memory of its caller, so no store or call in
+    between invalidates the available calls.
+*/
+
+pub struct CallCse;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    Const(i32),
+    Value(Value),
+}
+
+type CallKey = (Function, Vec<Operand>);
+
+impl ModulePass for CallCse {
+    fn run_on(&mut self, program: &mut Program) {
+        let pure = pure_functions(program);
+        if pure.is_empty() {
+            return;
+        }
+        let funcs: Vec<Function> = program.func_layout().to_vec();
+        for func in funcs {
+            let data = program.func_mut(func);
+            if let Some(cfg) = Cfg::new(data) {
+                eliminate_calls(data, &cfg, &pure);
+            }
+        }
+    }
+}
+
+fn eliminate_calls(data: &mut FunctionData, cfg: &Cfg, pure: &HashSet<Function>) {
+    let dom = DomTree::new(cfg);
+    let mut stack = vec![(cfg.entry, HashMap::new())];
+    while let Some((bb, mut available)) = stack.pop() {
+        eliminate_in_block(data, bb, pure, &mut available);
+        for &child in dom.children(bb).iter().rev() {
+            stack.push((child, available.clone()));
+        }
+    }
+}
+
+fn eliminate_in_block(
+    data: &mut FunctionData,
+    bb: BasicBlock,
+    pure: &HashSet<Function>,
+    available: &mut HashMap<CallKey, Value>,
+) {
+    let insts: Vec<Value> = data
+        .layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .keys()
+        .copied()
+        .collect();
+    for inst in insts {
+        let key = match data.dfg().value(inst).kind() {
+            ValueKind::Call(call) if pure.contains(&call.callee()) => {
+                let args = call.args().iter().map(|&arg| operand(data, arg)).collect();
+                (call.callee(), args)
+            }
+            _ => continue,
+        };
+        match available.get(&key) {
+            Some(&value) => {
+                replace_uses(data, inst, value);
+                remove_inst(data, inst);
+            }
+            None => {
+                available.insert(key, inst);
+            }
+        }
+    }
+}
+
+fn operand(data: &FunctionData, value: Value) -> Operand {
+    if !value.is_global() {
+        if let ValueKind::Integer(int) = data.dfg().value(value).kind() {
+            return Operand::Const(int.value());
+        }
+    }
+    Operand::Value(value)
+}
@@ -0,0 +1,72 @@
+use super::util::{remove_inst, replace_uses};
+use koopa::ir::builder::ValueBuilder;
+use koopa::ir::{BinaryOp, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+
+// ============ Constant Folding ============
+// binary operations on two constants are evaluated at compile time
+
+pub struct ConstFold;
+
+impl FunctionPass for ConstFold {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        let insts: Vec<Value> = data
+            .layout()
+            .bbs()
+            .iter()
+            .flat_map(|(_, node)| node.insts().keys().copied())
+            .collect();
+        // layout order, so folded operands are seen before their users
+        for inst in insts {
+            let folded = match data.dfg().value(inst).kind() {
+                ValueKind::Binary(binary) => {
+                    match (int_value(data, binary.lhs()), int_value(data, binary.rhs())) {
+                        (Some(lhs), Some(rhs)) => Some(fold_binary(binary.op(), lhs, rhs)),
+                        _ => None,
+                    }
+                }
+                _ => None,
+            };
+            if let Some(result) = folded {
+                let result = data.dfg_mut().new_value().integer(result);
+                replace_uses(data, inst, result);
+                remove_inst(data, inst);
+            }
+        }
+    }
+}
+
+fn int_value(data: &FunctionData, value: Value) -> Option<i32> {
+    if value.is_global() {
+        return None;
+    }
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(num) => Some(num.value()),
+        _ => None,
+    }
+}
+
+// RV32M semantics: x / 0 = -1, x % 0 = x, INT_MIN / -1 = INT_MIN, INT_MIN % -1 = 0
+pub fn fold_binary(op: BinaryOp, lhs: i32, rhs: i32) -> i32 {
+    match op {
+        BinaryOp::NotEq => (lhs != rhs) as i32,
+        BinaryOp::Eq => (lhs == rhs) as i32,
+        BinaryOp::Gt => (lhs > rhs) as i32,
+        BinaryOp::Lt => (lhs < rhs) as i32,
+        BinaryOp::Ge => (lhs >= rhs) as i32,
+        BinaryOp::Le => (lhs <= rhs) as i32,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div if rhs == 0 => -1,
+        BinaryOp::Mod if rhs == 0 => lhs,
+        BinaryOp::Div => lhs.wrapping_div(rhs),
+        BinaryOp::Mod => lhs.wrapping_rem(rhs),
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr => ((lhs as u32).wrapping_shr(rhs as u32)) as i32,
+        BinaryOp::Sar => lhs.wrapping_shr(rhs as u32),
+    }
+}
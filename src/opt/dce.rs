@@ -0,0 +1,106 @@
+use super::util::{detach_block_param, remove_dead_values};
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::{HashMap, HashSet};
+
+// ============ Dead Code Elimination ============
+/*
+    Mark and sweep: stores, calls and terminators are live, and so is every
+    value they use. Jump/branch arguments are only live if the parameter they
+    are passed to is, so values flowing around a loop without being used are
+    removed together with their block parameters.
+*/
+
+pub struct Dce;
+
+impl FunctionPass for Dce {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        // owner block and index of every block parameter
+        let mut params: HashMap<Value, (BasicBlock, usize)> = HashMap::new();
+        for &bb in data.layout().bbs().keys() {
+            for (i, &param) in data.dfg().bb(bb).params().iter().enumerate() {
+                params.insert(param, (bb, i));
+            }
+        }
+
+        // ---- mark ----
+        let mut live = HashSet::new();
+        let mut worklist = Vec::new();
+        for (_, node) in data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                if has_side_effect(data.dfg().value(inst).kind()) {
+                    worklist.push(inst);
+                }
+            }
+        }
+        while let Some(value) = worklist.pop() {
+            if value.is_global() || !live.insert(value) {
+                continue;
+            }
+            if let Some(&(bb, index)) = params.get(&value) {
+                // the arguments passed to a live parameter
+                for &user in data.dfg().bb(bb).used_by() {
+                    match data.dfg().value(user).kind() {
+                        ValueKind::Jump(jump) => worklist.push(jump.args()[index]),
+                        ValueKind::Branch(branch) => {
+                            if branch.true_bb() == bb {
+                                worklist.push(branch.true_args()[index]);
+                            }
+                            if branch.false_bb() == bb {
+                                worklist.push(branch.false_args()[index]);
+                            }
+                        }
+                        _ => unreachable!(),
+                    }
+                }
+                continue;
+            }
+            match data.dfg().value(value).kind() {
+                ValueKind::Jump(_) => {}
+                ValueKind::Branch(branch) => worklist.push(branch.cond()),
+                kind => worklist.extend(kind.value_uses()),
+            }
+        }
+
+        // ---- sweep ----
+        let mut dead = Vec::new();
+        let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().collect();
+        for &bb in bbs.iter() {
+            let count = data.dfg().bb(bb).params().len();
+            for index in (0..count).rev() {
+                let param = data.dfg().bb(bb).params()[index];
+                if !live.contains(&param) {
+                    dead.push(detach_block_param(data, bb, index));
+                }
+            }
+        }
+        for &bb in bbs.iter() {
+            let insts: Vec<Value> = data
+                .layout()
+                .bbs()
+                .node(&bb)
+                .unwrap()
+                .insts()
+                .keys()
+                .filter(|inst| !live.contains(*inst))
+                .copied()
+                .collect();
+            for inst in insts {
+                data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+                dead.push(inst);
+            }
+        }
+        remove_dead_values(data, dead);
+    }
+}
+
+pub fn has_side_effect(kind: &ValueKind) -> bool {
+    matches!(
+        kind,
+        ValueKind::Store(_)
+            | ValueKind::Call(_)
+            | ValueKind::Jump(_)
+            | ValueKind::Branch(_)
+            | ValueKind::Return(_)
+    )
+}
@@ -0,0 +1,14 @@
+use super::util::remove_unreachable_blocks;
+use koopa::ir::{Function, FunctionData};
+use koopa::opt::FunctionPass;
+
+// ============ Dead Block Elimination ============
+// remove blocks unreachable from the entry, such as the %new_bb created after return/break
+
+pub struct DeadBlockElim;
+
+impl FunctionPass for DeadBlockElim {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        remove_unreachable_blocks(data);
+    }
+}
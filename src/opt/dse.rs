@@ -0,0 +1,151 @@
+use super::analysis::Cfg;
+use super::util::{remove_inst, successors};
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::{HashMap, HashSet};
+
+// ============ Dead Store Elimination ============
+/*
+    Local allocs whose address never escapes (only loaded from, stored to or
+    indexed) die at `ret`, so a store to one is dead if no later load may read
+    it. Liveness is computed per alloc over the CFG; a store to a scalar alloc
+    overwrites it completely, a store through getelemptr/getptr does not.
+    Allocs that are never read lose all their stores and are removed.
+*/
+
+pub struct DeadStoreElim;
+
+impl FunctionPass for DeadStoreElim {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        let cfg = match Cfg::new(data) {
+            Some(cfg) => cfg,
+            None => return,
+        };
+        let roots = local_roots(data);
+        if roots.is_empty() {
+            return;
+        }
+
+        // ---- liveness, backward ----
+        let mut live_in: HashMap<BasicBlock, HashSet<Value>> = HashMap::new();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &bb in cfg.rpo.iter().rev() {
+                let mut live = live_out(data, &live_in, bb);
+                for &inst in insts(data, bb).iter().rev() {
+                    transfer(data, &roots, inst, &mut live);
+                }
+                if live_in.get(&bb) != Some(&live) {
+                    live_in.insert(bb, live);
+                    changed = true;
+                }
+            }
+        }
+
+        // ---- remove dead stores ----
+        let mut dead = Vec::new();
+        for &bb in cfg.rpo.iter() {
+            let mut live = live_out(data, &live_in, bb);
+            for &inst in insts(data, bb).iter().rev() {
+                if let ValueKind::Store(store) = data.dfg().value(inst).kind() {
+                    if let Some(root) = roots.get(&store.dest()) {
+                        if !live.contains(root) {
+                            dead.push(inst);
+                            continue;
+                        }
+                    }
+                }
+                transfer(data, &roots, inst, &mut live);
+            }
+        }
+        for inst in dead {
+            remove_inst(data, inst);
+        }
+
+        // allocs and address computations left without users, users first
+        let mut addrs: Vec<Value> = roots.keys().copied().collect();
+        while let Some(pos) = addrs
+            .iter()
+            .position(|&addr| data.dfg().value(addr).used_by().is_empty())
+        {
+            remove_inst(data, addrs.swap_remove(pos));
+        }
+    }
+}
+
+fn insts(data: &FunctionData, bb: BasicBlock) -> Vec<Value> {
+    data.layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .keys()
+        .copied()
+        .collect()
+}
+
+fn live_out(
+    data: &FunctionData,
+    live_in: &HashMap<BasicBlock, HashSet<Value>>,
+    bb: BasicBlock,
+) -> HashSet<Value> {
+    successors(data, bb)
+        .iter()
+        .filter_map(|succ| live_in.get(succ))
+        .flatten()
+        .copied()
+        .collect()
+}
+
+fn transfer(
+    data: &FunctionData,
+    roots: &HashMap<Value, Value>,
+    inst: Value,
+    live: &mut HashSet<Value>,
+) {
+    match data.dfg().value(inst).kind() {
+        ValueKind::Load(load) => {
+            if let Some(&root) = roots.get(&load.src()) {
+                live.insert(root);
+            }
+        }
+        // a scalar store overwrites the whole alloc
+        ValueKind::Store(store) if roots.get(&store.dest()) == Some(&store.dest()) => {
+            live.remove(&store.dest());
+        }
+        _ => {}
+    }
+}
+
+// address -> alloc it points into, for every local alloc that does not escape
+fn local_roots(data: &FunctionData) -> HashMap<Value, Value> {
+    let mut roots = HashMap::new();
+    for (_, node) in data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            if !matches!(data.dfg().value(inst).kind(), ValueKind::Alloc(_)) {
+                continue;
+            }
+            let mut addrs = vec![inst];
+            let mut escapes = false;
+            let mut i = 0;
+            while i < addrs.len() && !escapes {
+                let addr = addrs[i];
+                i += 1;
+                for &user in data.dfg().value(addr).used_by() {
+                    match data.dfg().value(user).kind() {
+                        ValueKind::Load(_) => {}
+                        ValueKind::Store(store) if store.dest() == addr && store.value() != addr => {}
+                        ValueKind::GetElemPtr(gep) if gep.src() == addr => addrs.push(user),
+                        ValueKind::GetPtr(gp) if gp.src() == addr => addrs.push(user),
+                        _ => escapes = true,
+                    }
+                }
+            }
+            if !escapes {
+                roots.extend(addrs.into_iter().map(|addr| (addr, inst)));
+            }
+        }
+    }
+    roots
+}
@@ -0,0 +1,395 @@
+use super::analysis::{call_sites, calls_of, Cfg, DomTree, LoopInfo};
+use super::util::{insert_inst_before, remove_inst, replace_uses};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use koopa::ir::{Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use koopa::opt::ModulePass;
+use std::collections::{HashMap, HashSet};
+
+// ============ Global Variable Optimization ============
+/*
+    Scalar globals are only ever loaded and stored directly, SysY cannot take
+    the address of a scalar:
+    1. a global never stored to becomes its initial value;
+    2. a global only referenced by main, which nobody calls, becomes a local
+       alloc of main initialized at the entry;
+    3. otherwise, in a function accessing it more often than it calls
+       functions that may access it, the global is cached in a local alloc:
+       loaded at the entry, written back before such calls and `ret`, and
+       reloaded after calls that may store to it.
+    mem2reg then promotes these allocs to SSA values, so they stay in registers.
+    Loads with constant indices from arrays that are never written, nor
+    passed to a call, become the initial elements.
+    Globals no function refers to any more are removed.
+*/
+
+// an access in a loop counts as 8 per loop level
+const LOOP_WEIGHT: usize = 8;
+
+pub struct GlobalOpt;
+
+impl ModulePass for GlobalOpt {
+    fn run_on(&mut self, program: &mut Program) {
+        let globals: Vec<Value> = program.inst_layout().to_vec();
+        for &global in globals.iter() {
+            if is_scalar(program, global) {
+                optimize_scalar(program, global);
+            } else {
+                fold_array_loads(program, global);
+            }
+        }
+        for global in globals {
+            if program.borrow_value(global).used_by().is_empty() {
+                remove_global(program, global);
+            }
+        }
+    }
+}
+
+fn is_scalar(program: &Program, global: Value) -> bool {
+    match program.borrow_value(global).ty().kind() {
+        TypeKind::Pointer(base) => base.is_i32(),
+        _ => false,
+    }
+}
+
+fn funcs_with_body(program: &Program) -> Vec<Function> {
+    program
+        .func_layout()
+        .iter()
+        .copied()
+        .filter(|&func| program.func(func).layout().entry_bb().is_some())
+        .collect()
+}
+
+// instructions of `data` using `global` as an operand, in layout order
+fn users_in(data: &FunctionData, global: Value) -> Vec<Value> {
+    let mut users = Vec::new();
+    for (_, node) in data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            if data
+                .dfg()
+                .value(inst)
+                .kind()
+                .value_uses()
+                .any(|v| v == global)
+            {
+                users.push(inst);
+            }
+        }
+    }
+    users
+}
+
+fn int_value(program: &Program, value: Value) -> i32 {
+    match program.borrow_value(value).kind() {
+        ValueKind::Integer(int) => int.value(),
+        ValueKind::ZeroInit(_) => 0,
+        _ => unreachable!(),
+    }
+}
+
+fn init_of(program: &Program, global: Value) -> Value {
+    match program.borrow_value(global).kind() {
+        ValueKind::GlobalAlloc(alloc) => alloc.init(),
+        _ => unreachable!(),
+    }
+}
+
+fn remove_global(program: &mut Program, global: Value) {
+    let init = init_of(program, global);
+    program.remove_value(global);
+    if program.borrow_value(init).used_by().is_empty() {
+        program.remove_value(init);
+    }
+}
+
+// ---- scalars ----
+
+fn optimize_scalar(program: &mut Program, global: Value) {
+    let funcs = funcs_with_body(program);
+    let mut loads: HashMap<Function, usize> = HashMap::new();
+    let mut stores: HashSet<Function> = HashSet::new();
+    for &func in funcs.iter() {
+        let data = program.func(func);
+        for user in users_in(data, global) {
+            match data.dfg().value(user).kind() {
+                ValueKind::Load(_) => *loads.entry(func).or_default() += 1,
+                ValueKind::Store(store) if store.dest() == global => {
+                    stores.insert(func);
+                }
+                _ => return,
+            }
+        }
+    }
+
+    let init = int_value(program, init_of(program, global));
+    if stores.is_empty() {
+        for func in loads.into_keys() {
+            let data = program.func_mut(func);
+            for load in users_in(data, global) {
+                let int = data.dfg_mut().new_value().integer(init);
+                replace_uses(data, load, int);
+                remove_inst(data, load);
+            }
+        }
+        return;
+    }
+
+    let (accessors, writers) = transitive_accesses(program, &funcs, &loads, &stores);
+    let main = funcs
+        .iter()
+        .copied()
+        .find(|&func| program.func(func).name() == "@main");
+    if let Some(main) = main {
+        let only_main = accessors.iter().all(|&func| func == main);
+        if only_main && call_sites(program, main).is_empty() {
+            let data = program.func_mut(main);
+            cache_global(data, global, Some(init), &HashMap::new(), false);
+            return;
+        }
+    }
+
+    for &func in funcs.iter() {
+        let data = program.func(func);
+        let mut calls = HashMap::new();
+        for (call, callee) in calls_of(data) {
+            if accessors.contains(&callee) {
+                calls.insert(call, writers.contains(&callee));
+            }
+        }
+        let write_back = stores.contains(&func);
+        if worth_caching(data, global, &calls, write_back) {
+            cache_global(program.func_mut(func), global, None, &calls, write_back);
+        }
+    }
+}
+
+// functions that may access the global and those that may store to it,
+// directly or through their calls
+fn transitive_accesses(
+    program: &Program,
+    funcs: &[Function],
+    loads: &HashMap<Function, usize>,
+    stores: &HashSet<Function>,
+) -> (HashSet<Function>, HashSet<Function>) {
+    let mut accessors: HashSet<Function> = loads.keys().chain(stores.iter()).copied().collect();
+    let mut writers = stores.clone();
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for &func in funcs.iter() {
+            for (_, callee) in calls_of(program.func(func)) {
+                if accessors.contains(&callee) && accessors.insert(func) {
+                    changed = true;
+                }
+                if writers.contains(&callee) && writers.insert(func) {
+                    changed = true;
+                }
+            }
+        }
+    }
+    (accessors, writers)
+}
+
+// whether the accesses removed outweigh the loads and stores added at the entry,
+// around calls and before `ret`, weighted by loop depth
+fn worth_caching(
+    data: &FunctionData,
+    global: Value,
+    calls: &HashMap<Value, bool>,
+    write_back: bool,
+) -> bool {
+    let cfg = Cfg::new(data).unwrap();
+    let dom = DomTree::new(&cfg);
+    let loops = LoopInfo::new(&cfg, &dom);
+    let weight = |inst: Value| {
+        let bb = data.layout().parent_bb(inst).unwrap();
+        LOOP_WEIGHT.pow(loops.depth(bb).min(3) as u32)
+    };
+    let saved: usize = users_in(data, global).into_iter().map(weight).sum();
+    let mut added = 1;
+    for (_, node) in data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            match data.dfg().value(inst).kind() {
+                ValueKind::Call(_) => {
+                    if let Some(&stores) = calls.get(&inst) {
+                        added += weight(inst) * (write_back as usize + stores as usize);
+                    }
+                }
+                ValueKind::Return(_) => added += write_back as usize,
+                _ => {}
+            }
+        }
+    }
+    saved > added
+}
+
+// replace the accesses to `global` in `data` by a local alloc, initialized with `init`
+// or the global; `calls` are the calls that may access it, mapped to whether they may
+// store to it
+fn cache_global(
+    data: &mut FunctionData,
+    global: Value,
+    init: Option<i32>,
+    calls: &HashMap<Value, bool>,
+    write_back: bool,
+) {
+    let users = users_in(data, global);
+    let entry = data.layout().entry_bb().unwrap();
+    let first = *data
+        .layout()
+        .bbs()
+        .node(&entry)
+        .unwrap()
+        .insts()
+        .front_key()
+        .unwrap();
+    let local = data.dfg_mut().new_value().alloc(Type::get_i32());
+    insert_inst_before(data, local, first);
+    let value = match init {
+        Some(init) => data.dfg_mut().new_value().integer(init),
+        None => {
+            let load = data.dfg_mut().new_value().load(global);
+            insert_inst_before(data, load, first);
+            load
+        }
+    };
+    let store = data.dfg_mut().new_value().store(value, local);
+    insert_inst_before(data, store, first);
+
+    for user in users {
+        match data.dfg().value(user).kind() {
+            ValueKind::Load(_) => {
+                data.dfg_mut().replace_value_with(user).load(local);
+            }
+            ValueKind::Store(store) => {
+                let value = store.value();
+                data.dfg_mut().replace_value_with(user).store(value, local);
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    // written back before calls, reloaded after them
+    let mut sync_points = Vec::new();
+    for (_, node) in data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            match data.dfg().value(inst).kind() {
+                ValueKind::Call(_) => {
+                    if let Some(&stores) = calls.get(&inst) {
+                        sync_points.push((inst, write_back, stores));
+                    }
+                }
+                ValueKind::Return(_) => sync_points.push((inst, write_back, false)),
+                _ => {}
+            }
+        }
+    }
+    for (inst, before, after) in sync_points {
+        if before {
+            let value = data.dfg_mut().new_value().load(local);
+            insert_inst_before(data, value, inst);
+            let store = data.dfg_mut().new_value().store(value, global);
+            insert_inst_before(data, store, inst);
+        }
+        if after {
+            let bb = data.layout().parent_bb(inst).unwrap();
+            let next = *data
+                .layout()
+                .bbs()
+                .node(&bb)
+                .unwrap()
+                .insts()
+                .cursor(inst)
+                .next_key()
+                .unwrap();
+            let value = data.dfg_mut().new_value().load(global);
+            insert_inst_before(data, value, next);
+            let store = data.dfg_mut().new_value().store(value, local);
+            insert_inst_before(data, store, next);
+        }
+    }
+}
+
+// ---- read-only arrays ----
+
+fn fold_array_loads(program: &mut Program, global: Value) {
+    let funcs = funcs_with_body(program);
+    let mut loads = Vec::new();
+    for &func in funcs.iter() {
+        let data = program.func(func);
+        let mut work = users_in(data, global);
+        while let Some(user) = work.pop() {
+            match data.dfg().value(user).kind() {
+                ValueKind::Load(_) => loads.push((func, user)),
+                ValueKind::GetElemPtr(_) => {
+                    work.extend(data.dfg().value(user).used_by().iter().copied())
+                }
+                // stored to, stored somewhere, or passed to a call
+                _ => return,
+            }
+        }
+    }
+    let init = init_of(program, global);
+    for (func, load) in loads {
+        let data = program.func(func);
+        let mut indices = Vec::new();
+        let mut ptr = match data.dfg().value(load).kind() {
+            ValueKind::Load(load) => load.src(),
+            _ => unreachable!(),
+        };
+        while ptr != global {
+            match data.dfg().value(ptr).kind() {
+                ValueKind::GetElemPtr(gep) => {
+                    indices.push(gep.index());
+                    ptr = gep.src();
+                }
+                _ => unreachable!(),
+            }
+        }
+        let indices: Option<Vec<i32>> = indices
+            .iter()
+            .rev()
+            .map(|&index| match data.dfg().value(index).kind() {
+                ValueKind::Integer(int) => Some(int.value()),
+                _ => None,
+            })
+            .collect();
+        if let Some(value) = indices.and_then(|indices| element_of(program, init, &indices)) {
+            let data = program.func_mut(func);
+            let mut ptr = match data.dfg().value(load).kind() {
+                ValueKind::Load(load) => load.src(),
+                _ => unreachable!(),
+            };
+            let int = data.dfg_mut().new_value().integer(value);
+            replace_uses(data, load, int);
+            remove_inst(data, load);
+            // remove the getelemptrs left unused, so the global may become unused
+            while ptr != global && data.dfg().value(ptr).used_by().is_empty() {
+                let src = match data.dfg().value(ptr).kind() {
+                    ValueKind::GetElemPtr(gep) => gep.src(),
+                    _ => unreachable!(),
+                };
+                remove_inst(data, ptr);
+                ptr = src;
+            }
+        }
+    }
+}
+
+// the element of the initializer at `indices`, None if out of bounds
+fn element_of(program: &Program, mut init: Value, indices: &[i32]) -> Option<i32> {
+    for &index in indices {
+        let elem = match program.borrow_value(init).kind() {
+            ValueKind::ZeroInit(_) => return Some(0),
+            ValueKind::Aggregate(aggr) => *aggr.elems().get(usize::try_from(index).ok()?)?,
+            _ => return None,
+        };
+        init = elem;
+    }
+    match program.borrow_value(init).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        ValueKind::ZeroInit(_) => Some(0),
+        _ => None,
+    }
+}
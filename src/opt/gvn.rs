@@ -0,0 +1,173 @@
+use super::analysis::{AliasAnalysis, Cfg, DomTree};
+use super::util::{remove_inst, replace_uses};
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::HashMap;
+
+// ============ Global Value Numbering ============
+/*
+    Dominator-based value numbering: walking the dominator tree in preorder,
+    a binary/getelemptr/getptr equal to one computed in a dominating position
+    is replaced by it.
+    Loads are numbered by address: a load is redundant if the same address
+    was loaded or stored earlier with no store or call in between that may
+    alias it. Memory state only flows into a block from its only predecessor.
+*/
+
+pub struct Gvn;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operand {
+    Const(i32),
+    Value(Value),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Expr {
+    Binary(BinaryOp, Operand, Operand),
+    GetElemPtr(Operand, Operand),
+    GetPtr(Operand, Operand),
+}
+
+// available values at the end of a block
+#[derive(Clone, Default)]
+struct State {
+    exprs: HashMap<Expr, Value>,
+    // address -> value last loaded from or stored to it
+    memory: HashMap<Value, Value>,
+}
+
+impl FunctionPass for Gvn {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        let cfg = match Cfg::new(data) {
+            Some(cfg) => cfg,
+            None => return,
+        };
+        let dom = DomTree::new(&cfg);
+        let mut alias = AliasAnalysis::new(data);
+        let mut stack = vec![(cfg.entry, State::default())];
+        while let Some((bb, mut state)) = stack.pop() {
+            if cfg.preds(bb).len() > 1 {
+                state.memory.clear();
+            }
+            number_block(data, &mut alias, bb, &mut state);
+            for &child in dom.children(bb).iter().rev() {
+                let mut child_state = state.clone();
+                // also reached through other paths, the memory state is unknown
+                if cfg.preds(child) != [bb] {
+                    child_state.memory.clear();
+                }
+                stack.push((child, child_state));
+            }
+        }
+    }
+}
+
+fn number_block(
+    data: &mut FunctionData,
+    alias: &mut AliasAnalysis,
+    bb: BasicBlock,
+    state: &mut State,
+) {
+    let insts: Vec<Value> = data
+        .layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .keys()
+        .copied()
+        .collect();
+    for inst in insts {
+        let redundant = match data.dfg().value(inst).kind() {
+            ValueKind::Binary(_) | ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_) => {
+                let expr = expr_of(data, inst);
+                let existing = state
+                    .exprs
+                    .get(&expr)
+                    .or_else(|| commuted(&expr).and_then(|expr| state.exprs.get(&expr)));
+                match existing {
+                    Some(&existing) => Some(existing),
+                    None => {
+                        state.exprs.insert(expr, inst);
+                        None
+                    }
+                }
+            }
+            ValueKind::Load(load) => {
+                let src = load.src();
+                match state.memory.get(&src) {
+                    Some(&existing) => Some(existing),
+                    None => {
+                        state.memory.insert(src, inst);
+                        None
+                    }
+                }
+            }
+            ValueKind::Store(store) => {
+                let (dest, value) = (store.dest(), store.value());
+                state
+                    .memory
+                    .retain(|&addr, _| !alias.may_alias(data, addr, dest));
+                state.memory.insert(dest, value);
+                None
+            }
+            ValueKind::Call(_) => {
+                state
+                    .memory
+                    .retain(|&addr, _| !alias.call_may_access(data, addr));
+                None
+            }
+            _ => None,
+        };
+        if let Some(existing) = redundant {
+            replace_uses(data, inst, existing);
+            remove_inst(data, inst);
+        }
+    }
+}
+
+fn expr_of(data: &FunctionData, inst: Value) -> Expr {
+    match data.dfg().value(inst).kind() {
+        ValueKind::Binary(binary) => Expr::Binary(
+            binary.op(),
+            operand(data, binary.lhs()),
+            operand(data, binary.rhs()),
+        ),
+        ValueKind::GetElemPtr(gep) => {
+            Expr::GetElemPtr(operand(data, gep.src()), operand(data, gep.index()))
+        }
+        ValueKind::GetPtr(gp) => Expr::GetPtr(operand(data, gp.src()), operand(data, gp.index())),
+        _ => unreachable!(),
+    }
+}
+
+fn operand(data: &FunctionData, value: Value) -> Operand {
+    if !value.is_global() {
+        if let ValueKind::Integer(num) = data.dfg().value(value).kind() {
+            return Operand::Const(num.value());
+        }
+    }
+    Operand::Value(value)
+}
+
+// `b op a` for a commutative `a op b`
+fn commuted(expr: &Expr) -> Option<Expr> {
+    match *expr {
+        Expr::Binary(op, lhs, rhs)
+            if matches!(
+                op,
+                BinaryOp::Add
+                    | BinaryOp::Mul
+                    | BinaryOp::And
+                    | BinaryOp::Or
+                    | BinaryOp::Xor
+                    | BinaryOp::Eq
+                    | BinaryOp::NotEq
+            ) =>
+        {
+            Some(Expr::Binary(op, rhs, lhs))
+        }
+        _ => None,
+    }
+}
@@ -0,0 +1,385 @@
+use super::analysis::{call_graph_sccs, calls_of, Cfg};
+use super::util::{new_bb, remove_inst, replace_uses};
+use koopa::ir::builder::{BasicBlockBuilder, LocalInstBuilder, ValueBuilder};
+use koopa::ir::entities::ValueData;
+use koopa::ir::{BasicBlock, Function, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use koopa::opt::ModulePass;
+use std::collections::HashMap;
+
+// ============ Function Inlining ============
+/*
+    Functions are visited bottom-up in the call graph, so a callee is
+    already inlined into when its own calls are considered. A call is
+    inlined if the callee has at most `threshold` instructions (allocs are
+    free) and is not in the same strongly connected component as the caller,
+    which rules out inlining recursion. The callee blocks are cloned between
+    the two halves of the split call block, `ret` becomes a jump to the
+    second half, which receives the return value as its parameter.
+*/
+
+pub const DEFAULT_INLINE_THRESHOLD: usize = 40;
+
+// callers stop growing past this size
+const MAX_CALLER_SIZE: usize = 4000;
+
+pub struct Inliner {
+    threshold: usize,
+}
+
+impl Inliner {
+    pub fn new(threshold: usize) -> Self {
+        Inliner { threshold }
+    }
+}
+
+impl ModulePass for Inliner {
+    fn run_on(&mut self, program: &mut Program) {
+        let sccs = call_graph_sccs(program);
+        let scc_of: HashMap<Function, usize> = sccs
+            .iter()
+            .enumerate()
+            .flat_map(|(i, scc)| scc.iter().map(move |&func| (func, i)))
+            .collect();
+        for &caller in sccs.iter().flatten() {
+            let calls: Vec<(Value, Function)> = calls_of(program.func(caller));
+            for (call, callee) in calls {
+                if scc_of.get(&callee) == scc_of.get(&caller)
+                    || !scc_of.contains_key(&callee)
+                    || size(program.func(callee)) > self.threshold
+                    || size(program.func(caller)) > MAX_CALLER_SIZE
+                {
+                    continue;
+                }
+                let body = Body::new(program.func(callee));
+                inline_call(program.func_mut(caller), call, &body);
+            }
+        }
+    }
+}
+
+// instructions that cost code size
+fn size(data: &FunctionData) -> usize {
+    data.layout()
+        .bbs()
+        .nodes()
+        .flat_map(|node| node.insts().keys())
+        .filter(|&&inst| !matches!(data.dfg().value(inst).kind(), ValueKind::Alloc(_)))
+        .count()
+}
+
+// ---- cloning ----
+
+// a copy of the callee, as the caller and the callee cannot be borrowed at once
+pub(super) struct Body {
+    name: String,
+    ret_ty: Type,
+    // reachable blocks in reverse postorder, the entry first
+    blocks: Vec<(BasicBlock, String, Vec<Value>)>,
+    insts: HashMap<BasicBlock, Vec<Value>>,
+    values: HashMap<Value, ValueData>,
+}
+
+impl Body {
+    pub(super) fn new(data: &FunctionData) -> Self {
+        let cfg = Cfg::new(data).unwrap();
+        let mut body = Body {
+            name: data.name()[1..].to_string(),
+            ret_ty: match data.ty().kind() {
+                TypeKind::Function(_, ret) => ret.clone(),
+                _ => unreachable!(),
+            },
+            blocks: Vec::new(),
+            insts: HashMap::new(),
+            values: HashMap::new(),
+        };
+        for &bb in cfg.rpo.iter() {
+            let bb_data = data.dfg().bb(bb);
+            let name = bb_data.name().as_ref().map_or("bb", |name| &name[1..]);
+            body.blocks
+                .push((bb, name.to_string(), bb_data.params().to_vec()));
+            let insts: Vec<Value> = data
+                .layout()
+                .bbs()
+                .node(&bb)
+                .unwrap()
+                .insts()
+                .keys()
+                .copied()
+                .collect();
+            for &value in bb_data.params().iter().chain(insts.iter()) {
+                body.copy_value(data, value);
+            }
+            body.insts.insert(bb, insts);
+        }
+        body
+    }
+
+    // the value and its operands: constants, arguments and other instructions
+    fn copy_value(&mut self, data: &FunctionData, value: Value) {
+        if value.is_global() || self.values.contains_key(&value) {
+            return;
+        }
+        let value_data = data.dfg().value(value).clone();
+        let operands: Vec<Value> = value_data.kind().value_uses().collect();
+        self.values.insert(value, value_data);
+        for operand in operands {
+            self.copy_value(data, operand);
+        }
+    }
+}
+
+fn inline_call(data: &mut FunctionData, call: Value, body: &Body) {
+    let call_bb = data.layout().parent_bb(call).unwrap();
+    let args = match data.dfg().value(call).kind() {
+        ValueKind::Call(call) => call.args().to_vec(),
+        _ => unreachable!(),
+    };
+
+    // ---- split the call block, the second half takes the return value ----
+    let ret_tys = if body.ret_ty.is_unit() {
+        Vec::new()
+    } else {
+        vec![body.ret_ty.clone()]
+    };
+    let cont = new_bb(data, &format!("{}_ret", body.name), ret_tys);
+    data.layout_mut()
+        .bbs_mut()
+        .cursor_mut(call_bb)
+        .insert_key_after(cont)
+        .unwrap();
+    let rest: Vec<Value> = {
+        let insts = data.layout().bbs().node(&call_bb).unwrap().insts();
+        let mut cursor = insts.cursor(call);
+        let mut rest = Vec::new();
+        cursor.move_next();
+        while let Some(&inst) = cursor.key() {
+            rest.push(inst);
+            cursor.move_next();
+        }
+        rest
+    };
+    for inst in rest {
+        data.layout_mut().bb_mut(call_bb).insts_mut().remove(&inst);
+        data.layout_mut()
+            .bb_mut(cont)
+            .insts_mut()
+            .push_key_back(inst)
+            .unwrap();
+    }
+    if let Some(&ret_value) = data.dfg().bb(cont).params().first() {
+        replace_uses(data, call, ret_value);
+    }
+    remove_inst(data, call);
+
+    // ---- clone the blocks ----
+    let mut bbs = HashMap::new();
+    let mut values = HashMap::new();
+    for (bb, name, params) in body.blocks.iter() {
+        let tys = params
+            .iter()
+            .map(|param| body.values[param].ty().clone())
+            .collect();
+        let new = new_bb(data, &format!("{}_{}", body.name, name), tys);
+        data.layout_mut()
+            .bbs_mut()
+            .cursor_mut(cont)
+            .insert_key_before(new)
+            .unwrap();
+        for (param, new_param) in params.iter().zip(data.dfg().bb(new).params().to_vec()) {
+            values.insert(*param, new_param);
+        }
+        bbs.insert(*bb, new);
+    }
+    let entry = data.layout().entry_bb().unwrap();
+    let mut cloner = Cloner {
+        data: &mut *data,
+        body,
+        args: &args,
+        values,
+        bbs,
+    };
+    for (bb, _, _) in body.blocks.iter() {
+        for &inst in body.insts[bb].iter() {
+            let new = cloner.clone_inst(inst, Some(cont));
+            let new_bb = cloner.bbs[bb];
+            // allocs go to the entry block of the caller
+            let target = if matches!(body.values[&inst].kind(), ValueKind::Alloc(_)) {
+                entry
+            } else {
+                new_bb
+            };
+            let insts = cloner.data.layout_mut().bb_mut(target).insts_mut();
+            if target == entry && target != new_bb {
+                insts.push_key_front(new).unwrap();
+            } else {
+                insts.push_key_back(new).unwrap();
+            }
+            cloner.values.insert(inst, new);
+        }
+    }
+    let callee_entry = cloner.bbs[&body.blocks[0].0];
+    let jump = data.dfg_mut().new_value().jump(callee_entry);
+    data.layout_mut()
+        .bb_mut(call_bb)
+        .insts_mut()
+        .push_key_back(jump)
+        .unwrap();
+}
+
+// copy `body` into the empty function `data`, uses of the callee parameters
+// are renamed by `params`
+pub(super) fn clone_body(data: &mut FunctionData, body: &Body, params: HashMap<Value, Value>) {
+    let mut bbs = HashMap::new();
+    let mut values = params;
+    for (bb, name, bb_params) in body.blocks.iter() {
+        let tys = bb_params
+            .iter()
+            .map(|param| body.values[param].ty().clone())
+            .collect();
+        let new = data
+            .dfg_mut()
+            .new_bb()
+            .basic_block_with_params(Some(format!("%{}", name)), tys);
+        data.layout_mut().bbs_mut().push_key_back(new).unwrap();
+        for (param, new_param) in bb_params.iter().zip(data.dfg().bb(new).params().to_vec()) {
+            values.insert(*param, new_param);
+        }
+        bbs.insert(*bb, new);
+    }
+    let mut cloner = Cloner {
+        data,
+        body,
+        args: &[],
+        values,
+        bbs,
+    };
+    for (bb, _, _) in body.blocks.iter() {
+        for &inst in body.insts[bb].iter() {
+            let new = cloner.clone_inst(inst, None);
+            let new_bb = cloner.bbs[bb];
+            cloner
+                .data
+                .layout_mut()
+                .bb_mut(new_bb)
+                .insts_mut()
+                .push_key_back(new)
+                .unwrap();
+            cloner.values.insert(inst, new);
+        }
+    }
+}
+
+struct Cloner<'a> {
+    data: &'a mut FunctionData,
+    body: &'a Body,
+    args: &'a [Value],
+    values: HashMap<Value, Value>,
+    bbs: HashMap<BasicBlock, BasicBlock>,
+}
+
+impl Cloner<'_> {
+    fn value(&mut self, value: Value) -> Value {
+        if value.is_global() {
+            return value;
+        }
+        if let Some(&new) = self.values.get(&value) {
+            return new;
+        }
+        let new = match self.body.values[&value].kind() {
+            ValueKind::FuncArgRef(arg) => self.args[arg.index()],
+            ValueKind::Integer(num) => self.data.dfg_mut().new_value().integer(num.value()),
+            ValueKind::ZeroInit(_) => {
+                let ty = self.body.values[&value].ty().clone();
+                self.data.dfg_mut().new_value().zero_init(ty)
+            }
+            ValueKind::Undef(_) => {
+                let ty = self.body.values[&value].ty().clone();
+                self.data.dfg_mut().new_value().undef(ty)
+            }
+            ValueKind::Aggregate(aggregate) => {
+                let elems = aggregate.elems().to_vec();
+                let elems = elems.into_iter().map(|elem| self.value(elem)).collect();
+                self.data.dfg_mut().new_value().aggregate(elems)
+            }
+            _ => unreachable!("operand cloned before its definition"),
+        };
+        self.values.insert(value, new);
+        new
+    }
+
+    fn values(&mut self, values: &[Value]) -> Vec<Value> {
+        values.iter().map(|&value| self.value(value)).collect()
+    }
+
+    // a copy of `inst` with the operands renamed, `ret` jumps to `cont` if given
+    fn clone_inst(&mut self, inst: Value, cont: Option<BasicBlock>) -> Value {
+        let inst_data = &self.body.values[&inst];
+        match inst_data.kind() {
+            ValueKind::Alloc(_) => {
+                let base = match inst_data.ty().kind() {
+                    TypeKind::Pointer(base) => base.clone(),
+                    _ => unreachable!(),
+                };
+                self.data.dfg_mut().new_value().alloc(base)
+            }
+            ValueKind::Load(load) => {
+                let src = self.value(load.src());
+                self.data.dfg_mut().new_value().load(src)
+            }
+            ValueKind::Store(store) => {
+                let value = self.value(store.value());
+                let dest = self.value(store.dest());
+                self.data.dfg_mut().new_value().store(value, dest)
+            }
+            ValueKind::GetPtr(get_ptr) => {
+                let src = self.value(get_ptr.src());
+                let index = self.value(get_ptr.index());
+                self.data.dfg_mut().new_value().get_ptr(src, index)
+            }
+            ValueKind::GetElemPtr(get_elem_ptr) => {
+                let src = self.value(get_elem_ptr.src());
+                let index = self.value(get_elem_ptr.index());
+                self.data.dfg_mut().new_value().get_elem_ptr(src, index)
+            }
+            ValueKind::Binary(binary) => {
+                let lhs = self.value(binary.lhs());
+                let rhs = self.value(binary.rhs());
+                self.data
+                    .dfg_mut()
+                    .new_value()
+                    .binary(binary.op(), lhs, rhs)
+            }
+            ValueKind::Branch(branch) => {
+                let cond = self.value(branch.cond());
+                let true_args = self.values(branch.true_args());
+                let false_args = self.values(branch.false_args());
+                let (true_bb, false_bb) =
+                    (self.bbs[&branch.true_bb()], self.bbs[&branch.false_bb()]);
+                self.data
+                    .dfg_mut()
+                    .new_value()
+                    .branch_with_args(cond, true_bb, false_bb, true_args, false_args)
+            }
+            ValueKind::Jump(jump) => {
+                let args = self.values(jump.args());
+                let target = self.bbs[&jump.target()];
+                self.data.dfg_mut().new_value().jump_with_args(target, args)
+            }
+            ValueKind::Call(call) => {
+                let args = self.values(call.args());
+                self.data.dfg_mut().new_value().call(call.callee(), args)
+            }
+            ValueKind::Return(ret) => {
+                let value = ret.value().map(|value| self.value(value));
+                match cont {
+                    Some(cont) => {
+                        let args = value.into_iter().collect();
+                        self.data.dfg_mut().new_value().jump_with_args(cont, args)
+                    }
+                    None => self.data.dfg_mut().new_value().ret(value),
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use super::analysis::{call_sites, calls_of};
+use super::inline::{clone_body, Body};
+use super::util::{clear_body, replace_uses};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use koopa::ir::{Function, FunctionData, Program, TypeKind, Value, ValueKind};
+use koopa::opt::ModulePass;
+use std::collections::{HashMap, HashSet};
+
+// ============ Interprocedural Constant Propagation ============
+/*
+    SysY has no function pointers, so every call site of a function is known:
+    1. functions and library declarations not reachable from main through
+       calls are removed;
+    2. a parameter receiving the same integer at every call site (or itself,
+       in a recursive call) is replaced by that integer in the body;
+    3. if every `ret` of a function returns the same integer, the results of
+       its calls are replaced by that integer in the callers;
+    4. parameters nobody reads are removed: koopa cannot change the type of a
+       function, so it is rebuilt with the smaller signature and every call drops
+       the matching arguments.
+    main keeps its signature.
+*/
+
+pub struct Ipcp;
+
+impl ModulePass for Ipcp {
+    fn run_on(&mut self, program: &mut Program) {
+        let main = match program
+            .func_layout()
+            .iter()
+            .copied()
+            .find(|&func| program.func(func).name() == "@main")
+        {
+            Some(main) => main,
+            None => return,
+        };
+        remove_dead_funcs(program, main);
+        let funcs: Vec<Function> = program
+            .func_layout()
+            .iter()
+            .copied()
+            .filter(|&func| func != main && program.func(func).layout().entry_bb().is_some())
+            .collect();
+        for &func in funcs.iter() {
+            propagate_args(program, func);
+        }
+        for &func in funcs.iter() {
+            propagate_return(program, func);
+        }
+        for func in funcs {
+            remove_dead_params(program, func);
+        }
+    }
+}
+
+fn remove_dead_funcs(program: &mut Program, main: Function) {
+    let mut reachable = HashSet::new();
+    let mut stack = vec![main];
+    while let Some(func) = stack.pop() {
+        if reachable.insert(func) {
+            stack.extend(
+                calls_of(program.func(func))
+                    .into_iter()
+                    .map(|(_, callee)| callee),
+            );
+        }
+    }
+    let dead: Vec<Function> = program
+        .func_layout()
+        .iter()
+        .copied()
+        .filter(|func| !reachable.contains(func))
+        .collect();
+    for func in dead {
+        clear_body(program.func_mut(func));
+        program.remove_func(func);
+    }
+}
+
+fn int_value(data: &FunctionData, value: Value) -> Option<i32> {
+    if value.is_global() {
+        return None;
+    }
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    }
+}
+
+fn call_args(data: &FunctionData, call: Value) -> Vec<Value> {
+    match data.dfg().value(call).kind() {
+        ValueKind::Call(call) => call.args().to_vec(),
+        _ => unreachable!(),
+    }
+}
+
+// ---- constants ----
+
+fn propagate_args(program: &mut Program, func: Function) {
+    let sites = call_sites(program, func);
+    let params = program.func(func).params().to_vec();
+    for (i, &param) in params.iter().enumerate() {
+        if program.func(func).dfg().value(param).used_by().is_empty() {
+            continue;
+        }
+        let mut constant = None;
+        for &(caller, call) in sites.iter() {
+            let data = program.func(caller);
+            let arg = call_args(data, call)[i];
+            // a recursive call passing the parameter along
+            if caller == func && arg == param {
+                continue;
+            }
+            match int_value(data, arg) {
+                Some(value) if constant.is_none_or(|constant| constant == value) => {
+                    constant = Some(value)
+                }
+                _ => {
+                    constant = None;
+                    break;
+                }
+            }
+        }
+        if let Some(constant) = constant {
+            let data = program.func_mut(func);
+            let int = data.dfg_mut().new_value().integer(constant);
+            replace_uses(data, param, int);
+        }
+    }
+}
+
+fn propagate_return(program: &mut Program, func: Function) {
+    let data = program.func(func);
+    let mut constant = None;
+    for (_, node) in data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            if let ValueKind::Return(ret) = data.dfg().value(inst).kind() {
+                match ret.value().and_then(|value| int_value(data, value)) {
+                    Some(value) if constant.is_none_or(|constant| constant == value) => {
+                        constant = Some(value)
+                    }
+                    _ => return,
+                }
+            }
+        }
+    }
+    let constant = match constant {
+        Some(constant) => constant,
+        None => return,
+    };
+    for (caller, call) in call_sites(program, func) {
+        let data = program.func_mut(caller);
+        if !data.dfg().value(call).used_by().is_empty() {
+            let int = data.dfg_mut().new_value().integer(constant);
+            replace_uses(data, call, int);
+        }
+    }
+}
+
+// ---- dead parameters ----
+
+// whether parameter `index` is read, other than being passed along unchanged
+// to the same position of a recursive call
+fn param_is_read(data: &FunctionData, func: Function, index: usize) -> bool {
+    let param = data.params()[index];
+    data.dfg()
+        .value(param)
+        .used_by()
+        .iter()
+        .any(|&user| match data.dfg().value(user).kind() {
+            ValueKind::Call(call) if call.callee() == func => call
+                .args()
+                .iter()
+                .enumerate()
+                .any(|(i, &arg)| arg == param && i != index),
+            _ => true,
+        })
+}
+
+fn remove_dead_params(program: &mut Program, func: Function) {
+    let data = program.func(func);
+    let params = data.params().to_vec();
+    let keep: Vec<usize> = (0..params.len())
+        .filter(|&i| param_is_read(data, func, i))
+        .collect();
+    if keep.len() == params.len() {
+        return;
+    }
+    let (param_tys, ret_ty) = match data.ty().kind() {
+        TypeKind::Function(param_tys, ret_ty) => (param_tys.clone(), ret_ty.clone()),
+        _ => unreachable!(),
+    };
+    let new_params = keep
+        .iter()
+        .map(|&i| {
+            let name = data.dfg().value(params[i]).name().clone();
+            (name, param_tys[i].clone())
+        })
+        .collect();
+    let new_data = FunctionData::with_param_names(data.name().to_string(), new_params, ret_ty);
+
+    // add the new function to the program first, its dfg then sees the globals
+    let new = program.new_func(new_data);
+    // recursive calls are rewritten too, so the removed parameters become unused
+    for (caller, call) in call_sites(program, func) {
+        let data = program.func_mut(caller);
+        let args = call_args(data, call);
+        let args = keep.iter().map(|&i| args[i]).collect();
+        data.dfg_mut().replace_value_with(call).call(new, args);
+    }
+    let body = Body::new(program.func(func));
+    let renames: HashMap<Value, Value> = keep
+        .iter()
+        .map(|&i| params[i])
+        .zip(program.func(new).params().to_vec())
+        .collect();
+    clone_body(program.func_mut(new), &body, renames);
+    clear_body(program.func_mut(func));
+    program.remove_func(func);
+}
@@ -0,0 +1,92 @@
+use super::analysis::{insert_preheader, preheader, Cfg, DomTree, LoopInfo};
+use super::util::move_inst_before;
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::HashSet;
+
+// ============ Loop Invariant Code Motion ============
+/*
+    Binary operations and address computations whose operands are all
+    defined outside a loop are moved to its preheader, innermost loops
+    first so that values can move out of several levels of a loop nest.
+    These instructions never trap (division follows RV32M), so they can be
+    executed speculatively even if the loop body would not run.
+*/
+
+pub struct Licm;
+
+impl FunctionPass for Licm {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        let cfg = match Cfg::new(data) {
+            Some(cfg) => cfg,
+            None => return,
+        };
+        let dom = DomTree::new(&cfg);
+        let loops = LoopInfo::new(&cfg, &dom);
+        if loops.loops.is_empty() {
+            return;
+        }
+        // insert all the preheaders first, then analyze again
+        for lp in loops.loops.iter() {
+            insert_preheader(data, &cfg, lp);
+        }
+        let cfg = Cfg::new(data).unwrap();
+        let dom = DomTree::new(&cfg);
+        let loops = LoopInfo::new(&cfg, &dom);
+
+        for lp in loops.loops.iter().rev() {
+            let pre = preheader(&cfg, lp).unwrap();
+            let terminator = *data
+                .layout()
+                .bbs()
+                .node(&pre)
+                .unwrap()
+                .insts()
+                .back_key()
+                .unwrap();
+            // values defined in the loop
+            let mut defined: HashSet<Value> = HashSet::new();
+            let body: Vec<BasicBlock> = cfg
+                .rpo
+                .iter()
+                .filter(|bb| lp.blocks.contains(*bb))
+                .copied()
+                .collect();
+            for &bb in body.iter() {
+                defined.extend(data.dfg().bb(bb).params().iter().copied());
+                defined.extend(
+                    data.layout()
+                        .bbs()
+                        .node(&bb)
+                        .unwrap()
+                        .insts()
+                        .keys()
+                        .copied(),
+                );
+            }
+            // reverse postorder, operands are hoisted before their users
+            for &bb in body.iter() {
+                let insts: Vec<Value> = data
+                    .layout()
+                    .bbs()
+                    .node(&bb)
+                    .unwrap()
+                    .insts()
+                    .keys()
+                    .copied()
+                    .collect();
+                for inst in insts {
+                    let kind = data.dfg().value(inst).kind();
+                    let movable = matches!(
+                        kind,
+                        ValueKind::Binary(_) | ValueKind::GetElemPtr(_) | ValueKind::GetPtr(_)
+                    );
+                    if movable && kind.value_uses().all(|operand| !defined.contains(&operand)) {
+                        move_inst_before(data, inst, terminator);
+                        defined.remove(&inst);
+                    }
+                }
+            }
+        }
+    }
+}
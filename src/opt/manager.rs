@@ -0,0 +1,182 @@
+use super::verify::verify;
+use super::{create_pass, is_pass, pipeline};
+use koopa::back::KoopaGenerator;
+use koopa::ir::Program;
+use koopa::opt::Pass;
+use std::io::stderr;
+use std::time::{Duration, Instant};
+
+// ============ Pass Options ============
+
+#[derive(Debug, Clone, Default)]
+pub struct PassConfig {
+    // --passes=a,b,c, replaces the -O pipeline
+    pub passes: Option<Vec<String>>,
+    // dump Koopa IR to stderr after these passes
+    pub print_after: Vec<String>,
+    pub print_after_all: bool,
+    // verify the IR from the builder and after the pipeline
+    pub verify: bool,
+    // run the verifier after every pass
+    pub verify_each: bool,
+    // report the time spent in each pass
+    pub time_passes: bool,
+    // --inline-threshold=<n>, callee size limit of the inliner
+    pub inline_threshold: Option<usize>,
+    // --unroll-factor=<n>, copies of the body in partially unrolled loops
+    pub unroll_factor: Option<usize>,
+    // --memoize, add the memoize pass to the -O pipeline
+    pub memoize: bool,
+}
+
+impl PassConfig {
+    // parse --passes=, --print-after=, --print-after-all, --verify, --verify-each, --time-passes,
+    // --inline-threshold=, --unroll-factor=, --memoize
+    pub fn parse_flag(&mut self, flag: &str) -> Result<(), String> {
+        match flag {
+            "--print-after-all" => self.print_after_all = true,
+            "--verify" => self.verify = true,
+            "--verify-each" => self.verify_each = true,
+            "--time-passes" => self.time_passes = true,
+            "--memoize" => self.memoize = true,
+            _ => {
+                if let Some(names) = flag.strip_prefix("--passes=") {
+                    self.passes = Some(parse_names(names)?);
+                } else if let Some(names) = flag.strip_prefix("--print-after=") {
+                    self.print_after.extend(parse_names(names)?);
+                } else if let Some(threshold) = flag.strip_prefix("--inline-threshold=") {
+                    let threshold = threshold
+                        .parse()
+                        .map_err(|_| format!("invalid inline threshold: {}", threshold))?;
+                    self.inline_threshold = Some(threshold);
+                } else if let Some(factor) = flag.strip_prefix("--unroll-factor=") {
+                    let factor = factor
+                        .parse()
+                        .map_err(|_| format!("invalid unroll factor: {}", factor))?;
+                    self.unroll_factor = Some(factor);
+                } else {
+                    return Err(format!("unknown pass option: {}", flag));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn parse_names(names: &str) -> Result<Vec<String>, String> {
+    names
+        .split(',')
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            if is_pass(name) {
+                Ok(name.to_string())
+            } else {
+                Err(format!("unknown pass: {}", name))
+            }
+        })
+        .collect()
+}
+
+// ============ Pass Manager ============
+
+pub struct PassManager {
+    passes: Vec<(String, Pass)>,
+    config: PassConfig,
+    timings: Vec<(String, Duration)>,
+}
+
+impl PassManager {
+    pub fn new(config: PassConfig) -> Self {
+        PassManager {
+            passes: Vec::new(),
+            config,
+            timings: Vec::new(),
+        }
+    }
+
+    // the -O<level> pipeline, or the --passes list if given
+    pub fn with_pipeline(opt_level: u8, config: PassConfig) -> Self {
+        let names = match &config.passes {
+            Some(names) => names.clone(),
+            None => {
+                let mut names: Vec<String> =
+                    pipeline(opt_level).iter().map(|name| name.to_string()).collect();
+                // after tail-rec, parameters are SSA values by then
+                if config.memoize {
+                    if let Some(i) = names.iter().position(|name| name == "tail-rec") {
+                        names.insert(i + 1, "memoize".to_string());
+                    }
+                }
+                names
+            }
+        };
+        let mut manager = PassManager::new(config);
+        for name in names {
+            let pass = create_pass(&name, &manager.config).unwrap();
+            manager.register(&name, pass);
+        }
+        manager
+    }
+
+    pub fn register(&mut self, name: &str, pass: Pass) {
+        self.passes.push((name.to_string(), pass));
+    }
+
+    pub fn pass_names(&self) -> Vec<&str> {
+        self.passes.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    // run the passes in order; fails if the verifier finds broken IR
+    pub fn run(&mut self, program: &mut Program) -> Result<(), String> {
+        if self.config.verify || self.config.verify_each {
+            verify(program).map_err(|err| format!("from the IR builder: {}", err))?;
+        }
+        for (name, pass) in self.passes.iter_mut() {
+            let start = Instant::now();
+            match pass {
+                Pass::Module(pass) => pass.run_on(program),
+                Pass::Function(pass) => {
+                    let funcs: Vec<_> = program.func_layout().to_vec();
+                    for func in funcs {
+                        let data = program.func_mut(func);
+                        // skip the library declarations
+                        if data.layout().entry_bb().is_some() {
+                            pass.run_on(func, data);
+                        }
+                    }
+                }
+            }
+            self.timings.push((name.clone(), start.elapsed()));
+
+            if self.config.print_after_all || self.config.print_after.contains(name) {
+                eprintln!("; ===== IR after {} =====", name);
+                KoopaGenerator::new(stderr())
+                    .generate_on(program)
+                    .map_err(|err| err.to_string())?;
+            }
+            if self.config.verify_each {
+                verify(program).map_err(|err| format!("after pass {}: {}", name, err))?;
+            }
+        }
+        if self.config.verify && !self.config.verify_each && !self.passes.is_empty() {
+            verify(program).map_err(|err| format!("after the pass pipeline: {}", err))?;
+        }
+        if self.config.time_passes {
+            self.report_timings();
+        }
+        Ok(())
+    }
+
+    pub fn timings(&self) -> &[(String, Duration)] {
+        &self.timings
+    }
+
+    fn report_timings(&self) {
+        let total: Duration = self.timings.iter().map(|(_, time)| *time).sum();
+        eprintln!("; ===== pass timing =====");
+        for (name, time) in self.timings.iter() {
+            eprintln!(";   {:<16}{:>10.3} ms", name, time.as_secs_f64() * 1e3);
+        }
+        eprintln!(";   {:<16}{:>10.3} ms", "total", total.as_secs_f64() * 1e3);
+    }
+}
@@ -0,0 +1,247 @@
+use super::analysis::{Cfg, DomTree};
+use super::util::{add_block_param, remove_inst, replace_uses};
+use koopa::ir::builder::ValueBuilder;
+use koopa::ir::{BasicBlock, Function, FunctionData, Type, TypeKind, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::{HashMap, HashSet};
+
+// ============ Mem2Reg ============
+/*
+    Promotes scalar allocs that are only loaded and stored to SSA values.
+    Phi nodes are Koopa block parameters, placed on the iterated dominance
+    frontier of the stores where the variable is live (pruned SSA).
+    A variable read before any store reads 0.
+*/
+
+pub struct Mem2Reg;
+
+impl FunctionPass for Mem2Reg {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        let cfg = match Cfg::new(data) {
+            Some(cfg) => cfg,
+            None => return,
+        };
+        let allocs = promotable_allocs(data);
+        if allocs.is_empty() {
+            return;
+        }
+        let promoted: HashSet<Value> = allocs.iter().copied().collect();
+        let dom = DomTree::new(&cfg);
+        let frontiers = dominance_frontiers(&cfg, &dom);
+
+        // ---- phi placement ----
+        // phis[bb] = promoted allocs that get a parameter of bb, in parameter order
+        let mut phis: HashMap<BasicBlock, Vec<Value>> = HashMap::new();
+        for &alloc in allocs.iter() {
+            let (def_blocks, live_in) = def_and_live_blocks(data, &cfg, alloc);
+            let mut placed = HashSet::new();
+            let mut worklist: Vec<BasicBlock> = cfg
+                .rpo
+                .iter()
+                .filter(|bb| def_blocks.contains(*bb))
+                .copied()
+                .collect();
+            while let Some(bb) = worklist.pop() {
+                for &frontier in frontiers.get(&bb).into_iter().flatten() {
+                    if live_in.contains(&frontier) && placed.insert(frontier) {
+                        phis.entry(frontier).or_default().push(alloc);
+                        if !def_blocks.contains(&frontier) {
+                            worklist.push(frontier);
+                        }
+                    }
+                }
+            }
+        }
+        // create the parameters after those the block already has
+        let mut params: HashMap<BasicBlock, Vec<Value>> = HashMap::new();
+        for &bb in cfg.rpo.iter() {
+            if let Some(bb_phis) = phis.get(&bb) {
+                let new_params = bb_phis
+                    .iter()
+                    .map(|_| add_block_param(data, bb, Type::get_i32()))
+                    .collect();
+                params.insert(bb, new_params);
+            }
+        }
+
+        // ---- renaming, preorder walk of the dominator tree ----
+        let zero = data.dfg_mut().new_value().integer(0);
+        let mut dead_insts = Vec::new();
+        let init: HashMap<Value, Value> = allocs.iter().map(|&alloc| (alloc, zero)).collect();
+        let mut stack = vec![(cfg.entry, init)];
+        while let Some((bb, mut defs)) = stack.pop() {
+            if let Some(bb_phis) = phis.get(&bb) {
+                for (&alloc, &param) in bb_phis.iter().zip(params[&bb].iter()) {
+                    defs.insert(alloc, param);
+                }
+            }
+            let insts: Vec<Value> = data
+                .layout()
+                .bbs()
+                .node(&bb)
+                .unwrap()
+                .insts()
+                .keys()
+                .copied()
+                .collect();
+            for &inst in insts.iter() {
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Load(load) if promoted.contains(&load.src()) => {
+                        let current = defs[&load.src()];
+                        replace_uses(data, inst, current);
+                        dead_insts.push(inst);
+                    }
+                    ValueKind::Store(store) if promoted.contains(&store.dest()) => {
+                        defs.insert(store.dest(), store.value());
+                        dead_insts.push(inst);
+                    }
+                    _ => {}
+                }
+            }
+            // pass the current values to the parameters of the successors
+            let terminator = *insts.last().unwrap();
+            let mut succs = cfg.succs(bb).to_vec();
+            succs.dedup();
+            for succ in succs {
+                if let Some(succ_phis) = phis.get(&succ) {
+                    let args: Vec<Value> = succ_phis.iter().map(|alloc| defs[alloc]).collect();
+                    add_block_args(data, terminator, succ, args);
+                }
+            }
+            for &child in dom.children(bb).iter().rev() {
+                stack.push((child, defs.clone()));
+            }
+        }
+
+        // accesses in unreachable blocks read 0
+        for (_, node) in data.layout().bbs() {
+            for &inst in node.insts().keys() {
+                match data.dfg().value(inst).kind() {
+                    ValueKind::Load(load) if promoted.contains(&load.src()) => dead_insts.push(inst),
+                    ValueKind::Store(store) if promoted.contains(&store.dest()) => {
+                        dead_insts.push(inst)
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let mut seen = HashSet::new();
+        dead_insts.retain(|inst| seen.insert(*inst));
+        for &inst in dead_insts.iter() {
+            if !data.dfg().value(inst).used_by().is_empty() {
+                replace_uses(data, inst, zero);
+            }
+        }
+        for inst in dead_insts.into_iter().chain(allocs) {
+            remove_inst(data, inst);
+        }
+    }
+}
+
+// allocs of i32 whose address never escapes
+fn promotable_allocs(data: &FunctionData) -> Vec<Value> {
+    let mut allocs = Vec::new();
+    for (_, node) in data.layout().bbs() {
+        for &inst in node.insts().keys() {
+            let inst_data = data.dfg().value(inst);
+            if !matches!(inst_data.kind(), ValueKind::Alloc(_)) {
+                continue;
+            }
+            let is_i32 = match inst_data.ty().kind() {
+                TypeKind::Pointer(base) => base.is_i32(),
+                _ => false,
+            };
+            let only_load_store = inst_data.used_by().iter().all(|&user| {
+                match data.dfg().value(user).kind() {
+                    ValueKind::Load(_) => true,
+                    ValueKind::Store(store) => store.dest() == inst && store.value() != inst,
+                    _ => false,
+                }
+            });
+            if is_i32 && only_load_store {
+                allocs.push(inst);
+            }
+        }
+    }
+    allocs
+}
+
+// blocks storing to `alloc`, and blocks where `alloc` is live on entry
+fn def_and_live_blocks(
+    data: &FunctionData,
+    cfg: &Cfg,
+    alloc: Value,
+) -> (HashSet<BasicBlock>, HashSet<BasicBlock>) {
+    let mut def_blocks = HashSet::new();
+    let mut live_in = HashSet::new();
+    for &bb in cfg.rpo.iter() {
+        let mut stored = false;
+        for &inst in data.layout().bbs().node(&bb).unwrap().insts().keys() {
+            match data.dfg().value(inst).kind() {
+                ValueKind::Store(store) if store.dest() == alloc => {
+                    stored = true;
+                    def_blocks.insert(bb);
+                }
+                // load before any store in the block
+                ValueKind::Load(load) if load.src() == alloc && !stored => {
+                    live_in.insert(bb);
+                }
+                _ => {}
+            }
+        }
+    }
+    let mut worklist: Vec<BasicBlock> = live_in.iter().copied().collect();
+    while let Some(bb) = worklist.pop() {
+        for &pred in cfg.preds(bb) {
+            if !def_blocks.contains(&pred) && live_in.insert(pred) {
+                worklist.push(pred);
+            }
+        }
+    }
+    (def_blocks, live_in)
+}
+
+fn dominance_frontiers(cfg: &Cfg, dom: &DomTree) -> HashMap<BasicBlock, Vec<BasicBlock>> {
+    let mut frontiers: HashMap<BasicBlock, Vec<BasicBlock>> = HashMap::new();
+    for &bb in cfg.rpo.iter() {
+        let preds: Vec<BasicBlock> = cfg
+            .preds(bb)
+            .iter()
+            .filter(|pred| dom.is_reachable(**pred))
+            .copied()
+            .collect();
+        if preds.len() < 2 {
+            continue;
+        }
+        let idom = dom.idom(bb).unwrap();
+        for pred in preds {
+            let mut runner = pred;
+            while runner != idom {
+                let frontier = frontiers.entry(runner).or_default();
+                if !frontier.contains(&bb) {
+                    frontier.push(bb);
+                }
+                runner = dom.idom(runner).unwrap();
+            }
+        }
+    }
+    frontiers
+}
+
+// append arguments for `target` to the jump/branch `terminator`
+fn add_block_args(data: &mut FunctionData, terminator: Value, target: BasicBlock, args: Vec<Value>) {
+    let mut term_data = data.dfg().value(terminator).clone();
+    match term_data.kind_mut() {
+        ValueKind::Jump(jump) => jump.args_mut().extend(args),
+        ValueKind::Branch(branch) => {
+            if branch.true_bb() == target {
+                branch.true_args_mut().extend(args.iter().copied());
+            }
+            if branch.false_bb() == target {
+                branch.false_args_mut().extend(args);
+            }
+        }
+        _ => unreachable!(),
+    }
+    data.dfg_mut().replace_value_with(terminator).raw(term_data);
+}
@@ -0,0 +1,225 @@
+use super::analysis::{call_graph_sccs, calls_of, pure_functions};
+use super::util::new_bb;
+use koopa::ir::builder::{GlobalInstBuilder, LocalInstBuilder, ValueBuilder};
+use koopa::ir::ValueKind;
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Program, Type, TypeKind, Value};
+use koopa::opt::ModulePass;
+use std::collections::HashSet;
+
+// ============ Memoization ============
+/*
+    Opt-in (--memoize). A recursive pure function of at most MAX_MEMO_PARAMS
+    i32 parameters returning i32 remembers its results in a global table,
+    indexed by the arguments while every one of them is in [0, dim), where
+    dim^params <= MEMO_TABLE_SIZE:
+
+        %memo_entry:  in_range, index = p0 * dim + p1 ...
+                      br in_range, %memo_lookup, %entry
+        %memo_lookup: br set[index], %memo_hit, %entry
+        %memo_hit:    ret table[index]
+        %memo_exit(r):                       every ret jumps here
+                      br in_range, %memo_save, %memo_ret
+        %memo_save:   table[index] = r, set[index] = 1, ret r
+        %memo_ret:    ret r
+*/
+
+const MAX_MEMO_PARAMS: usize = 3;
+const MEMO_TABLE_SIZE: usize = 1 << 14;
+
+pub struct Memoize;
+
+impl ModulePass for Memoize {
+    fn run_on(&mut self, program: &mut Program) {
+        let pure = pure_functions(program);
+        let recursive: HashSet<Function> = call_graph_sccs(program)
+            .into_iter()
+            .filter(|scc| {
+                scc.len() > 1
+                    || calls_of(program.func(scc[0]))
+                        .iter()
+                        .any(|&(_, callee)| callee == scc[0])
+            })
+            .flatten()
+            .collect();
+        let funcs: Vec<Function> = program.func_layout().to_vec();
+        for func in funcs {
+            if pure.contains(&func)
+                && recursive.contains(&func)
+                && is_memoizable(program.func(func))
+            {
+                memoize(program, func);
+            }
+        }
+    }
+}
+
+fn is_memoizable(data: &FunctionData) -> bool {
+    match data.ty().kind() {
+        TypeKind::Function(params, ret) => {
+            (1..=MAX_MEMO_PARAMS).contains(&params.len())
+                && params.iter().all(|param| param.is_i32())
+                && ret.is_i32()
+        }
+        _ => false,
+    }
+}
+
+// the largest dim with dim^params <= MEMO_TABLE_SIZE
+fn table_dim(params: usize) -> usize {
+    (1..)
+        .take_while(|dim: &usize| dim.pow(params as u32) <= MEMO_TABLE_SIZE)
+        .last()
+        .unwrap()
+}
+
+fn new_table(program: &mut Program, name: String, len: usize) -> Value {
+    let ty = Type::get_array(Type::get_i32(), len);
+    let init = program.new_value().zero_init(ty);
+    let table = program.new_value().global_alloc(init);
+    program.set_value_name(table, Some(name));
+    table
+}
+
+fn memoize(program: &mut Program, func: Function) {
+    let params = program.func(func).params().to_vec();
+    let dim = table_dim(params.len());
+    let len = dim.pow(params.len() as u32);
+    let name = program.func(func).name()[1..].to_string();
+    let table = new_table(program, format!("@__sysy_memo_{}", name), len);
+    let set = new_table(program, format!("@__sysy_memo_{}_set", name), len);
+    let data = program.func_mut(func);
+    let entry = data.layout().entry_bb().unwrap();
+    let rets: Vec<Value> = data
+        .layout()
+        .bbs()
+        .nodes()
+        .flat_map(|node| node.insts().keys())
+        .copied()
+        .filter(|&inst| matches!(data.dfg().value(inst).kind(), ValueKind::Return(_)))
+        .collect();
+
+    // ---- range check and index ----
+    let memo_entry = new_bb(data, "memo_entry", Vec::new());
+    data.layout_mut()
+        .bbs_mut()
+        .push_key_front(memo_entry)
+        .unwrap();
+    let mut insts = Vec::new();
+    let zero = data.dfg_mut().new_value().integer(0);
+    let dim_value = data.dfg_mut().new_value().integer(dim as i32);
+    let mut in_range = None;
+    let mut index = None;
+    for &param in params.iter() {
+        let lower = data.dfg_mut().new_value().binary(BinaryOp::Ge, param, zero);
+        let upper = data
+            .dfg_mut()
+            .new_value()
+            .binary(BinaryOp::Lt, param, dim_value);
+        let both = data
+            .dfg_mut()
+            .new_value()
+            .binary(BinaryOp::And, lower, upper);
+        insts.extend([lower, upper, both]);
+        in_range = Some(match in_range {
+            Some(prev) => {
+                let all = data.dfg_mut().new_value().binary(BinaryOp::And, prev, both);
+                insts.push(all);
+                all
+            }
+            None => both,
+        });
+        index = Some(match index {
+            Some(prev) => {
+                let scaled = data
+                    .dfg_mut()
+                    .new_value()
+                    .binary(BinaryOp::Mul, prev, dim_value);
+                let sum = data
+                    .dfg_mut()
+                    .new_value()
+                    .binary(BinaryOp::Add, scaled, param);
+                insts.extend([scaled, sum]);
+                sum
+            }
+            None => param,
+        });
+    }
+    let (in_range, index) = (in_range.unwrap(), index.unwrap());
+    let memo_lookup = new_bb(data, "memo_lookup", Vec::new());
+    let branch = data
+        .dfg_mut()
+        .new_value()
+        .branch(in_range, memo_lookup, entry);
+    insts.push(branch);
+    push_insts(data, memo_entry, insts);
+    // allocs move to the new entry block
+    let allocs: Vec<Value> = data
+        .layout()
+        .bbs()
+        .node(&entry)
+        .unwrap()
+        .insts()
+        .keys()
+        .copied()
+        .filter(|&inst| matches!(data.dfg().value(inst).kind(), ValueKind::Alloc(_)))
+        .collect();
+    for alloc in allocs.into_iter().rev() {
+        data.layout_mut().bb_mut(entry).insts_mut().remove(&alloc);
+        data.layout_mut()
+            .bb_mut(memo_entry)
+            .insts_mut()
+            .push_key_front(alloc)
+            .unwrap();
+    }
+
+    // ---- lookup ----
+    let memo_hit = new_bb(data, "memo_hit", Vec::new());
+    let set_ptr = data.dfg_mut().new_value().get_elem_ptr(set, index);
+    let is_set = data.dfg_mut().new_value().load(set_ptr);
+    let branch = data.dfg_mut().new_value().branch(is_set, memo_hit, entry);
+    push_insts(data, memo_lookup, vec![set_ptr, is_set, branch]);
+    let ptr = data.dfg_mut().new_value().get_elem_ptr(table, index);
+    let cached = data.dfg_mut().new_value().load(ptr);
+    let ret = data.dfg_mut().new_value().ret(Some(cached));
+    push_insts(data, memo_hit, vec![ptr, cached, ret]);
+
+    // ---- save the results ----
+    let memo_exit = new_bb(data, "memo_exit", vec![Type::get_i32()]);
+    let result = data.dfg().bb(memo_exit).params()[0];
+    let memo_save = new_bb(data, "memo_save", Vec::new());
+    let memo_ret = new_bb(data, "memo_ret", Vec::new());
+    let branch = data
+        .dfg_mut()
+        .new_value()
+        .branch(in_range, memo_save, memo_ret);
+    push_insts(data, memo_exit, vec![branch]);
+    let ptr = data.dfg_mut().new_value().get_elem_ptr(table, index);
+    let store = data.dfg_mut().new_value().store(result, ptr);
+    let one = data.dfg_mut().new_value().integer(1);
+    let set_ptr = data.dfg_mut().new_value().get_elem_ptr(set, index);
+    let mark = data.dfg_mut().new_value().store(one, set_ptr);
+    let ret = data.dfg_mut().new_value().ret(Some(result));
+    push_insts(data, memo_save, vec![ptr, store, set_ptr, mark, ret]);
+    let ret = data.dfg_mut().new_value().ret(Some(result));
+    push_insts(data, memo_ret, vec![ret]);
+    for ret in rets {
+        let value = match data.dfg().value(ret).kind() {
+            ValueKind::Return(ret) => ret.value().unwrap(),
+            _ => unreachable!(),
+        };
+        data.dfg_mut()
+            .replace_value_with(ret)
+            .jump_with_args(memo_exit, vec![value]);
+    }
+}
+
+// append a new block with `insts` to the layout
+fn push_insts(data: &mut FunctionData, bb: BasicBlock, insts: Vec<Value>) {
+    if data.layout().bbs().node(&bb).is_none() {
+        data.layout_mut().bbs_mut().push_key_back(bb).unwrap();
+    }
+    let bb_insts = data.layout_mut().bb_mut(bb).insts_mut();
+    for inst in insts {
+        bb_insts.push_key_back(inst).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+pub mod analysis;
+mod call_cse;
+mod const_fold;
+mod dce;
+mod dead_block;
+mod dse;
+mod globalopt;
+mod gvn;
+mod inline;
+mod ipcp;
+mod licm;
+mod manager;
+mod mem2reg;
+mod memoize;
+mod sccp;
+mod simplify_cfg;
+mod strength_reduce;
+mod tail_rec;
+mod unroll;
+pub mod util;
+mod verify;
+
+pub use call_cse::CallCse;
+pub use const_fold::{fold_binary, ConstFold};
+pub use dce::Dce;
+pub use dead_block::DeadBlockElim;
+pub use dse::DeadStoreElim;
+pub use globalopt::GlobalOpt;
+pub use gvn::Gvn;
+pub use inline::{Inliner, DEFAULT_INLINE_THRESHOLD};
+pub use ipcp::Ipcp;
+pub use licm::Licm;
+pub use manager::{PassConfig, PassManager};
+pub use mem2reg::Mem2Reg;
+pub use memoize::Memoize;
+pub use sccp::Sccp;
+pub use simplify_cfg::SimplifyCfg;
+pub use strength_reduce::StrengthReduce;
+pub use tail_rec::TailRecElim;
+pub use unroll::{Unroll, DEFAULT_UNROLL_FACTOR};
+pub use verify::verify;
+
+use koopa::opt::Pass;
+
+// ============ Pass Registry ============
+
+struct PassInfo {
+    name: &'static str,
+    description: &'static str,
+    create: fn(&PassConfig) -> Pass,
+}
+
+const PASSES: &[PassInfo] = &[
+    PassInfo {
+        name: "call-cse",
+        description: "remove calls to pure functions repeating a dominating call",
+        create: |_| Pass::Module(Box::new(CallCse)),
+    },
+    PassInfo {
+        name: "const-fold",
+        description: "fold binary operations on constants",
+        create: |_| Pass::Function(Box::new(ConstFold)),
+    },
+    PassInfo {
+        name: "dce",
+        description: "remove values without side effects that nothing uses",
+        create: |_| Pass::Function(Box::new(Dce)),
+    },
+    PassInfo {
+        name: "dead-block",
+        description: "remove blocks unreachable from the entry",
+        create: |_| Pass::Function(Box::new(DeadBlockElim)),
+    },
+    PassInfo {
+        name: "dse",
+        description: "remove stores to local allocs that are never read afterwards",
+        create: |_| Pass::Function(Box::new(DeadStoreElim)),
+    },
+    PassInfo {
+        name: "globalopt",
+        description: "turn globals into constants or locals, cache them across call-free code",
+        create: |_| Pass::Module(Box::new(GlobalOpt)),
+    },
+    PassInfo {
+        name: "gvn",
+        description: "remove redundant computations and loads",
+        create: |_| Pass::Function(Box::new(Gvn)),
+    },
+    PassInfo {
+        name: "inline",
+        description: "inline small non-recursive functions (--inline-threshold)",
+        create: |config| {
+            let threshold = config.inline_threshold.unwrap_or(DEFAULT_INLINE_THRESHOLD);
+            Pass::Module(Box::new(Inliner::new(threshold)))
+        },
+    },
+    PassInfo {
+        name: "ipcp",
+        description: "propagate constants across calls, remove dead parameters and functions",
+        create: |_| Pass::Module(Box::new(Ipcp)),
+    },
+    PassInfo {
+        name: "licm",
+        description: "hoist loop-invariant computations into loop preheaders",
+        create: |_| Pass::Function(Box::new(Licm)),
+    },
+    PassInfo {
+        name: "mem2reg",
+        description: "promote scalar allocs to SSA values with block parameters",
+        create: |_| Pass::Function(Box::new(Mem2Reg)),
+    },
+    PassInfo {
+        name: "memoize",
+        description: "cache the results of recursive pure functions (--memoize)",
+        create: |_| Pass::Module(Box::new(Memoize)),
+    },
+    PassInfo {
+        name: "sccp",
+        description: "sparse conditional constant propagation, folds constant branches",
+        create: |_| Pass::Function(Box::new(Sccp)),
+    },
+    PassInfo {
+        name: "simplify-cfg",
+        description: "merge, thread and remove blocks",
+        create: |_| Pass::Function(Box::new(SimplifyCfg)),
+    },
+    PassInfo {
+        name: "strength-reduce",
+        description: "reduce multiplications of induction variables to additions",
+        create: |_| Pass::Function(Box::new(StrengthReduce)),
+    },
+    PassInfo {
+        name: "tail-rec",
+        description: "turn tail self-recursion into loops",
+        create: |_| Pass::Function(Box::new(TailRecElim)),
+    },
+    PassInfo {
+        name: "unroll",
+        description: "unroll constant and inner loops (--unroll-factor)",
+        create: |config| {
+            let factor = config.unroll_factor.unwrap_or(DEFAULT_UNROLL_FACTOR);
+            Pass::Function(Box::new(Unroll::new(factor)))
+        },
+    },
+];
+
+pub fn create_pass(name: &str, config: &PassConfig) -> Option<Pass> {
+    PASSES
+        .iter()
+        .find(|info| info.name == name)
+        .map(|info| (info.create)(config))
+}
+
+pub fn is_pass(name: &str) -> bool {
+    PASSES.iter().any(|info| info.name == name)
+}
+
+// (name, description) of every registered pass
+pub fn pass_list() -> Vec<(&'static str, &'static str)> {
+    PASSES
+        .iter()
+        .map(|info| (info.name, info.description))
+        .collect()
+}
+
+// ============ Pipelines ============
+
+// passes selected by -O<level>, in the order they run
+pub fn pipeline(opt_level: u8) -> &'static [&'static str] {
+    match opt_level {
+        0 => &[],
+        1 => &[
+            "simplify-cfg",
+            "mem2reg",
+            "tail-rec",
+            "sccp",
+            "gvn",
+            "licm",
+            "strength-reduce",
+            "dse",
+            "dce",
+            "simplify-cfg",
+        ],
+        _ => &[
+            "simplify-cfg",
+            "mem2reg",
+            "tail-rec",
+            "sccp",
+            "dce",
+            "simplify-cfg",
+            "inline",
+            "ipcp",
+            "globalopt",
+            "mem2reg",
+            "sccp",
+            "dce",
+            "simplify-cfg",
+            "unroll",
+            "sccp",
+            "gvn",
+            "call-cse",
+            "licm",
+            "strength-reduce",
+            "dse",
+            "dce",
+            "simplify-cfg",
+        ],
+    }
+}
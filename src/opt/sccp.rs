@@ -0,0 +1,297 @@
+use super::const_fold::fold_binary;
+use super::util::{remove_block_param, remove_inst, remove_unreachable_blocks, replace_uses};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::{HashMap, HashSet};
+
+// ============ Sparse Conditional Constant Propagation ============
+// Wegman, Zadeck: "Constant Propagation with Conditional Branches"
+
+pub struct Sccp;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Lattice {
+    // no executable definition seen yet
+    Top,
+    Const(i32),
+    // not a constant
+    Bottom,
+}
+
+impl Lattice {
+    fn meet(self, other: Lattice) -> Lattice {
+        match (self, other) {
+            (Lattice::Top, x) | (x, Lattice::Top) => x,
+            (Lattice::Const(a), Lattice::Const(b)) if a == b => Lattice::Const(a),
+            _ => Lattice::Bottom,
+        }
+    }
+}
+
+impl FunctionPass for Sccp {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        let entry = match data.layout().entry_bb() {
+            Some(entry) => entry,
+            None => return,
+        };
+        let mut solver = Solver::new(data);
+        solver.run(entry);
+        let Solver {
+            values,
+            exec_blocks,
+            ..
+        } = solver;
+        rewrite(data, &values, &exec_blocks);
+    }
+}
+
+// ---- analysis ----
+
+struct Solver<'a> {
+    data: &'a FunctionData,
+    values: HashMap<Value, Lattice>,
+    exec_blocks: HashSet<BasicBlock>,
+    exec_edges: HashSet<(BasicBlock, BasicBlock)>,
+    edge_work: Vec<(BasicBlock, BasicBlock)>,
+    value_work: Vec<Value>,
+}
+
+impl<'a> Solver<'a> {
+    fn new(data: &'a FunctionData) -> Self {
+        Solver {
+            data,
+            values: HashMap::new(),
+            exec_blocks: HashSet::new(),
+            exec_edges: HashSet::new(),
+            edge_work: Vec::new(),
+            value_work: Vec::new(),
+        }
+    }
+
+    fn run(&mut self, entry: BasicBlock) {
+        self.exec_blocks.insert(entry);
+        self.visit_block(entry);
+        loop {
+            if let Some((from, to)) = self.edge_work.pop() {
+                if !self.exec_edges.insert((from, to)) {
+                    continue;
+                }
+                if self.exec_blocks.insert(to) {
+                    self.visit_block(to);
+                } else {
+                    self.visit_params(to);
+                }
+            } else if let Some(value) = self.value_work.pop() {
+                let users: Vec<Value> = self.data.dfg().value(value).used_by().iter().copied().collect();
+                for user in users {
+                    let executable = self
+                        .data
+                        .layout()
+                        .parent_bb(user)
+                        .is_some_and(|bb| self.exec_blocks.contains(&bb));
+                    if executable {
+                        self.visit_inst(user);
+                    }
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn lattice(&self, value: Value) -> Lattice {
+        if value.is_global() {
+            return Lattice::Bottom;
+        }
+        match self.data.dfg().value(value).kind() {
+            ValueKind::Integer(num) => Lattice::Const(num.value()),
+            ValueKind::FuncArgRef(_) => Lattice::Bottom,
+            _ => self.values.get(&value).copied().unwrap_or(Lattice::Top),
+        }
+    }
+
+    fn set(&mut self, value: Value, new: Lattice) {
+        let old = self.lattice(value);
+        // only moves down Top -> Const -> Bottom
+        let new = old.meet(new);
+        if new != old {
+            self.values.insert(value, new);
+            self.value_work.push(value);
+        }
+    }
+
+    fn visit_block(&mut self, bb: BasicBlock) {
+        self.visit_params(bb);
+        let insts: Vec<Value> = self
+            .data
+            .layout()
+            .bbs()
+            .node(&bb)
+            .unwrap()
+            .insts()
+            .keys()
+            .copied()
+            .collect();
+        for inst in insts {
+            self.visit_inst(inst);
+        }
+    }
+
+    // a parameter is the meet of its arguments on the executable incoming edges
+    fn visit_params(&mut self, bb: BasicBlock) {
+        let params = self.data.dfg().bb(bb).params().to_vec();
+        if params.is_empty() {
+            return;
+        }
+        let users: Vec<Value> = self.data.dfg().bb(bb).used_by().iter().copied().collect();
+        for (i, &param) in params.iter().enumerate() {
+            let mut result = Lattice::Top;
+            for &user in users.iter() {
+                let pred = match self.data.layout().parent_bb(user) {
+                    Some(pred) => pred,
+                    None => continue,
+                };
+                if !self.exec_edges.contains(&(pred, bb)) {
+                    continue;
+                }
+                for arg in block_args(self.data, user, bb) {
+                    result = result.meet(self.lattice(arg[i]));
+                }
+            }
+            self.set(param, result);
+        }
+    }
+
+    fn visit_inst(&mut self, inst: Value) {
+        let bb = self.data.layout().parent_bb(inst).unwrap();
+        let value_data = self.data.dfg().value(inst);
+        match value_data.kind() {
+            ValueKind::Binary(binary) => {
+                let lhs = self.lattice(binary.lhs());
+                let rhs = self.lattice(binary.rhs());
+                let result = match (lhs, rhs) {
+                    (Lattice::Const(a), Lattice::Const(b)) => {
+                        Lattice::Const(fold_binary(binary.op(), a, b))
+                    }
+                    // x * 0, x & 0
+                    (_, Lattice::Const(0)) | (Lattice::Const(0), _)
+                        if matches!(binary.op(), BinaryOp::Mul | BinaryOp::And) =>
+                    {
+                        Lattice::Const(0)
+                    }
+                    (Lattice::Top, _) | (_, Lattice::Top) => Lattice::Top,
+                    _ => Lattice::Bottom,
+                };
+                self.set(inst, result);
+            }
+            ValueKind::Jump(jump) => self.reach(bb, jump.target()),
+            ValueKind::Branch(branch) => match self.lattice(branch.cond()) {
+                Lattice::Top => {}
+                Lattice::Const(cond) => {
+                    let target = if cond != 0 {
+                        branch.true_bb()
+                    } else {
+                        branch.false_bb()
+                    };
+                    self.reach(bb, target);
+                }
+                Lattice::Bottom => {
+                    self.reach(bb, branch.true_bb());
+                    self.reach(bb, branch.false_bb());
+                }
+            },
+            _ => {
+                if !value_data.ty().is_unit() {
+                    self.set(inst, Lattice::Bottom);
+                }
+            }
+        }
+    }
+
+    // the edge is feasible; arguments may have changed, so revisit the parameters
+    fn reach(&mut self, from: BasicBlock, to: BasicBlock) {
+        if self.exec_edges.contains(&(from, to)) {
+            self.visit_params(to);
+        } else {
+            self.edge_work.push((from, to));
+        }
+    }
+}
+
+// the argument lists a jump/branch passes to `target`
+fn block_args(data: &FunctionData, terminator: Value, target: BasicBlock) -> Vec<&[Value]> {
+    let mut args = Vec::new();
+    match data.dfg().value(terminator).kind() {
+        ValueKind::Jump(jump) => args.push(jump.args()),
+        ValueKind::Branch(branch) => {
+            if branch.true_bb() == target {
+                args.push(branch.true_args());
+            }
+            if branch.false_bb() == target {
+                args.push(branch.false_args());
+            }
+        }
+        _ => {}
+    }
+    args
+}
+
+// ---- transformation ----
+
+fn rewrite(
+    data: &mut FunctionData,
+    values: &HashMap<Value, Lattice>,
+    exec_blocks: &HashSet<BasicBlock>,
+) {
+    // constant branches become jumps
+    let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().collect();
+    for &bb in bbs.iter().filter(|bb| exec_blocks.contains(*bb)) {
+        let terminator = *data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap();
+        let (target, args) = match data.dfg().value(terminator).kind() {
+            ValueKind::Branch(branch) => match lattice_of(data, values, branch.cond()) {
+                Lattice::Const(cond) if cond != 0 => (branch.true_bb(), branch.true_args().to_vec()),
+                Lattice::Const(_) => (branch.false_bb(), branch.false_args().to_vec()),
+                _ => continue,
+            },
+            _ => continue,
+        };
+        data.dfg_mut()
+            .replace_value_with(terminator)
+            .jump_with_args(target, args);
+    }
+    remove_unreachable_blocks(data);
+
+    // constant instructions and parameters are replaced by integers
+    let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().collect();
+    for &bb in bbs.iter() {
+        let params = data.dfg().bb(bb).params().to_vec();
+        for (i, &param) in params.iter().enumerate().rev() {
+            if let Some(Lattice::Const(value)) = values.get(&param) {
+                let constant = data.dfg_mut().new_value().integer(*value);
+                replace_uses(data, param, constant);
+                remove_block_param(data, bb, i);
+            }
+        }
+        let insts: Vec<Value> = data.layout().bbs().node(&bb).unwrap().insts().keys().copied().collect();
+        for inst in insts {
+            if let Some(Lattice::Const(value)) = values.get(&inst) {
+                if matches!(data.dfg().value(inst).kind(), ValueKind::Binary(_)) {
+                    let constant = data.dfg_mut().new_value().integer(*value);
+                    replace_uses(data, inst, constant);
+                    remove_inst(data, inst);
+                }
+            }
+        }
+    }
+}
+
+fn lattice_of(data: &FunctionData, values: &HashMap<Value, Lattice>, value: Value) -> Lattice {
+    if value.is_global() {
+        return Lattice::Bottom;
+    }
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(num) => Lattice::Const(num.value()),
+        _ => values.get(&value).copied().unwrap_or(Lattice::Bottom),
+    }
+}
@@ -0,0 +1,180 @@
+use super::util::{remove_unreachable_blocks, replace_uses};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+
+// ============ CFG Simplification ============
+/*
+    Repeats until nothing changes:
+        - removes blocks unreachable from the entry
+        - turns `br c, %b(args), %b(args)` into `jump %b(args)`
+        - threads jumps through blocks that contain only a jump
+        - merges a block into its predecessor if it is the only successor
+          of the predecessor and the predecessor is its only predecessor
+*/
+
+pub struct SimplifyCfg;
+
+impl FunctionPass for SimplifyCfg {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        if data.layout().entry_bb().is_none() {
+            return;
+        }
+        let mut changed = true;
+        while changed {
+            changed = remove_unreachable_blocks(data);
+            changed |= fold_same_target_branches(data);
+            changed |= thread_jumps(data);
+            changed |= merge_blocks(data);
+        }
+    }
+}
+
+fn terminator(data: &FunctionData, bb: BasicBlock) -> Value {
+    *data.layout().bbs().node(&bb).unwrap().insts().back_key().unwrap()
+}
+
+fn fold_same_target_branches(data: &mut FunctionData) -> bool {
+    let mut changed = false;
+    let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().collect();
+    for bb in bbs {
+        let term = terminator(data, bb);
+        let (target, args) = match data.dfg().value(term).kind() {
+            ValueKind::Branch(branch)
+                if branch.true_bb() == branch.false_bb()
+                    && branch.true_args() == branch.false_args() =>
+            {
+                (branch.true_bb(), branch.true_args().to_vec())
+            }
+            _ => continue,
+        };
+        data.dfg_mut()
+            .replace_value_with(term)
+            .jump_with_args(target, args);
+        changed = true;
+    }
+    changed
+}
+
+// redirect the predecessors of a block `jump %target(args)` to `%target`
+fn thread_jumps(data: &mut FunctionData) -> bool {
+    let entry = data.layout().entry_bb().unwrap();
+    let mut changed = false;
+    let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().collect();
+    for bb in bbs {
+        if bb == entry || data.layout().bbs().node(&bb).unwrap().insts().len() != 1 {
+            continue;
+        }
+        let (target, args) = match data.dfg().value(terminator(data, bb)).kind() {
+            ValueKind::Jump(jump) if jump.target() != bb => (jump.target(), jump.args().to_vec()),
+            _ => continue,
+        };
+        let params = data.dfg().bb(bb).params().to_vec();
+        // the parameters must not be used past the block, it goes away
+        let jump = terminator(data, bb);
+        let local = params.iter().all(|&param| {
+            data.dfg()
+                .value(param)
+                .used_by()
+                .iter()
+                .all(|&user| user == jump)
+        });
+        if !local {
+            continue;
+        }
+        let users: Vec<Value> = data.dfg().bb(bb).used_by().iter().copied().collect();
+        for user in users {
+            let mut user_data = data.dfg().value(user).clone();
+            // the parameters of bb become the arguments passed by the predecessor
+            let forward = |incoming: &[Value]| -> Vec<Value> {
+                args.iter()
+                    .map(|arg| match params.iter().position(|param| param == arg) {
+                        Some(i) => incoming[i],
+                        None => *arg,
+                    })
+                    .collect()
+            };
+            match user_data.kind_mut() {
+                ValueKind::Jump(jump) => {
+                    let new_args = forward(jump.args());
+                    *jump.args_mut() = new_args;
+                    *jump.target_mut() = target;
+                }
+                ValueKind::Branch(branch) => {
+                    // the two edges of a branch must stay distinguishable
+                    if branch.true_bb() == branch.false_bb()
+                        || branch.true_bb() == target
+                        || branch.false_bb() == target
+                    {
+                        continue;
+                    }
+                    if branch.true_bb() == bb {
+                        let new_args = forward(branch.true_args());
+                        *branch.true_args_mut() = new_args;
+                        *branch.true_bb_mut() = target;
+                    } else {
+                        let new_args = forward(branch.false_args());
+                        *branch.false_args_mut() = new_args;
+                        *branch.false_bb_mut() = target;
+                    }
+                }
+                _ => unreachable!(),
+            }
+            data.dfg_mut().replace_value_with(user).raw(user_data);
+            changed = true;
+        }
+    }
+    changed
+}
+
+// append a block to its only predecessor ending with `jump` to it
+fn merge_blocks(data: &mut FunctionData) -> bool {
+    let entry = data.layout().entry_bb().unwrap();
+    let mut changed = false;
+    let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().collect();
+    for bb in bbs {
+        if bb == entry || data.layout().bbs().node(&bb).is_none() {
+            continue;
+        }
+        let users = data.dfg().bb(bb).used_by();
+        if users.len() != 1 {
+            continue;
+        }
+        let jump = *users.iter().next().unwrap();
+        let pred = match data.layout().parent_bb(jump) {
+            Some(pred) if pred != bb => pred,
+            _ => continue,
+        };
+        let args = match data.dfg().value(jump).kind() {
+            ValueKind::Jump(jump) => jump.args().to_vec(),
+            _ => continue,
+        };
+        data.layout_mut().bb_mut(pred).insts_mut().remove(&jump);
+        data.dfg_mut().remove_value(jump);
+        let params = data.dfg().bb(bb).params().to_vec();
+        for (param, arg) in params.into_iter().zip(args) {
+            replace_uses(data, param, arg);
+        }
+        let insts: Vec<Value> = data
+            .layout()
+            .bbs()
+            .node(&bb)
+            .unwrap()
+            .insts()
+            .keys()
+            .copied()
+            .collect();
+        for inst in insts {
+            data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+            data.layout_mut()
+                .bb_mut(pred)
+                .insts_mut()
+                .push_key_back(inst)
+                .unwrap();
+        }
+        data.layout_mut().bbs_mut().remove(&bb);
+        data.dfg_mut().remove_bb(bb);
+        changed = true;
+    }
+    changed
+}
@@ -0,0 +1,149 @@
+use super::analysis::{insert_preheader, preheader, Cfg, DomTree, Loop, LoopInfo};
+use super::util::{
+    add_block_param, add_edge_arg, edge_arg, insert_inst_before, remove_inst, replace_uses,
+    step_of, terminator,
+};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Type, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::HashMap;
+
+// ============ Induction Variable Strength Reduction ============
+/*
+    A basic induction variable is a loop header parameter advanced by a
+    constant on every back edge. `i * k` with `k` loop invariant is replaced
+    by a new header parameter `j`, starting at `init * k` and advanced by
+    `step * k` on the back edges, so the loop does an add instead of a mul.
+    Arithmetic wraps, so `j == i * k` holds on every iteration. Constants
+    the backend can multiply by with a shift or two are left alone.
+*/
+
+pub struct StrengthReduce;
+
+impl FunctionPass for StrengthReduce {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        let cfg = match Cfg::new(data) {
+            Some(cfg) => cfg,
+            None => return,
+        };
+        let dom = DomTree::new(&cfg);
+        let loops = LoopInfo::new(&cfg, &dom);
+        if loops.loops.is_empty() {
+            return;
+        }
+        for lp in loops.loops.iter() {
+            insert_preheader(data, &cfg, lp);
+        }
+        let cfg = Cfg::new(data).unwrap();
+        let dom = DomTree::new(&cfg);
+        let loops = LoopInfo::new(&cfg, &dom);
+        // only parameters and instructions are added, the loops stay the same
+        for lp in loops.loops.iter() {
+            let pre = preheader(&cfg, lp).unwrap();
+            reduce_loop(data, lp, pre);
+        }
+    }
+}
+
+fn reduce_loop(data: &mut FunctionData, lp: &Loop, pre: BasicBlock) {
+    let header = lp.header;
+    let pre_jump = terminator(data, pre);
+    let params = data.dfg().bb(header).params().to_vec();
+    for (index, &param) in params.iter().enumerate() {
+        // the constant added on each back edge
+        let mut steps = Vec::new();
+        for &latch in lp.latches.iter() {
+            let jump = terminator(data, latch);
+            match edge_arg(data, jump, header, index).and_then(|arg| step_of(data, arg, param)) {
+                Some(step) => steps.push((jump, step)),
+                None => break,
+            }
+        }
+        if steps.len() != lp.latches.len() {
+            continue;
+        }
+        let init = edge_arg(data, pre_jump, header, index).unwrap();
+
+        // multiplications in the loop, grouped by the invariant factor;
+        // layout order keeps the output deterministic
+        let mut muls: HashMap<Value, Vec<Value>> = HashMap::new();
+        let mut factors = Vec::new();
+        for (bb, node) in data.layout().bbs() {
+            if !lp.blocks.contains(bb) {
+                continue;
+            }
+            for &inst in node.insts().keys() {
+                let factor = match data.dfg().value(inst).kind() {
+                    ValueKind::Binary(bin) if bin.op() == BinaryOp::Mul => {
+                        if bin.lhs() == param {
+                            bin.rhs()
+                        } else if bin.rhs() == param {
+                            bin.lhs()
+                        } else {
+                            continue;
+                        }
+                    }
+                    _ => continue,
+                };
+                if factor != param && lp.is_invariant(data, factor) && !is_cheap(data, factor) {
+                    if !muls.contains_key(&factor) {
+                        factors.push(factor);
+                    }
+                    muls.entry(factor).or_default().push(inst);
+                }
+            }
+        }
+
+        for factor in factors {
+            let reduced = add_block_param(data, header, Type::get_i32());
+            let start = mul_before(data, pre_jump, init, factor);
+            add_edge_arg(data, pre_jump, header, start);
+            for &(jump, step) in steps.iter() {
+                let step = data.dfg_mut().new_value().integer(step);
+                let step = mul_before(data, pre_jump, step, factor);
+                let next = data
+                    .dfg_mut()
+                    .new_value()
+                    .binary(BinaryOp::Add, reduced, step);
+                insert_inst_before(data, next, jump);
+                add_edge_arg(data, jump, header, next);
+            }
+            for &mul in muls[&factor].iter() {
+                replace_uses(data, mul, reduced);
+                remove_inst(data, mul);
+            }
+        }
+    }
+}
+
+// constants the backend multiplies by with shifts and adds,
+// cheaper than another loop-carried value
+fn is_cheap(data: &FunctionData, factor: Value) -> bool {
+    match data.dfg().value(factor).kind() {
+        ValueKind::Integer(int) => {
+            let bits = int.value() as u32;
+            let low = 1u32.checked_shl(bits.trailing_zeros()).unwrap_or(0);
+            bits.count_ones() <= 2
+                || bits.wrapping_neg().is_power_of_two()
+                || bits.wrapping_add(low).is_power_of_two()
+        }
+        _ => false,
+    }
+}
+
+// lhs * rhs, folded if both are constants, otherwise computed before `before`
+fn mul_before(data: &mut FunctionData, before: Value, lhs: Value, rhs: Value) -> Value {
+    let int = |value: Value| match data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    };
+    match (int(lhs), int(rhs)) {
+        (Some(lhs), Some(rhs)) => data.dfg_mut().new_value().integer(lhs.wrapping_mul(rhs)),
+        (Some(1), _) => rhs,
+        _ => {
+            let mul = data.dfg_mut().new_value().binary(BinaryOp::Mul, lhs, rhs);
+            insert_inst_before(data, mul, before);
+            mul
+        }
+    }
+}
@@ -0,0 +1,117 @@
+use super::util::{move_inst_before, new_bb, points_into_frame, remove_inst, replace_uses};
+use koopa::ir::builder::{BasicBlockBuilder, LocalInstBuilder, ValueBuilder};
+use koopa::ir::{Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+
+// ============ Tail Recursion Elimination ============
+/*
+    `%r = call @self(args); ret %r` becomes a jump back to the old entry
+    block, which turns into a loop header with one parameter per function
+    parameter. A new entry block holds the allocs and enters the loop with
+    the real arguments. Calls passing pointers into the frame are kept, as
+    the locals they point to would be shared by all iterations.
+*/
+
+pub struct TailRecElim;
+
+impl FunctionPass for TailRecElim {
+    fn run_on(&mut self, func: Function, data: &mut FunctionData) {
+        let tail_calls = tail_self_calls(func, data);
+        if tail_calls.is_empty() {
+            return;
+        }
+        let header = data.layout().entry_bb().unwrap();
+
+        // ---- header parameters replace the function parameters ----
+        let tys: Vec<_> = data
+            .params()
+            .iter()
+            .map(|&param| data.dfg().value(param).ty().clone())
+            .collect();
+        let tmp = data.dfg_mut().new_bb().basic_block_with_params(None, tys);
+        let params = std::mem::take(data.dfg_mut().bb_mut(tmp).params_mut());
+        data.dfg_mut().remove_bb(tmp);
+        data.dfg_mut()
+            .bb_mut(header)
+            .params_mut()
+            .extend(params.iter().copied());
+        let func_params = data.params().to_vec();
+        for (&func_param, &param) in func_params.iter().zip(params.iter()) {
+            replace_uses(data, func_param, param);
+        }
+
+        // ---- new entry ----
+        let entry = new_bb(data, "tailrec", Vec::new());
+        data.layout_mut().bbs_mut().push_key_front(entry).unwrap();
+        let jump = data
+            .dfg_mut()
+            .new_value()
+            .jump_with_args(header, func_params);
+        data.layout_mut()
+            .bb_mut(entry)
+            .insts_mut()
+            .push_key_back(jump)
+            .unwrap();
+        let allocs: Vec<Value> = data
+            .layout()
+            .bbs()
+            .node(&header)
+            .unwrap()
+            .insts()
+            .keys()
+            .filter(|&&inst| matches!(data.dfg().value(inst).kind(), ValueKind::Alloc(_)))
+            .copied()
+            .collect();
+        for alloc in allocs {
+            move_inst_before(data, alloc, jump);
+        }
+
+        // ---- tail calls become jumps ----
+        for (call, ret) in tail_calls {
+            let args = match data.dfg().value(call).kind() {
+                ValueKind::Call(call) => call.args().to_vec(),
+                _ => unreachable!(),
+            };
+            data.dfg_mut()
+                .replace_value_with(ret)
+                .jump_with_args(header, args);
+            remove_inst(data, call);
+        }
+    }
+}
+
+// (call, ret) pairs where a block ends with a call to `func` whose result is returned
+fn tail_self_calls(func: Function, data: &FunctionData) -> Vec<(Value, Value)> {
+    let mut calls = Vec::new();
+    for (_, node) in data.layout().bbs() {
+        let ret = match node.insts().back_key() {
+            Some(&ret) => ret,
+            None => continue,
+        };
+        let call = match node.insts().cursor(ret).prev_key() {
+            Some(&call) => call,
+            None => continue,
+        };
+        let is_self_call =
+            matches!(data.dfg().value(call).kind(), ValueKind::Call(c) if c.callee() == func);
+        let returns_call = match data.dfg().value(ret).kind() {
+            ValueKind::Return(ret) => match ret.value() {
+                Some(value) => value == call,
+                None => data.dfg().value(call).used_by().is_empty(),
+            },
+            _ => false,
+        };
+        if !is_self_call || !returns_call || data.dfg().value(call).used_by().len() > 1 {
+            continue;
+        }
+        // the loop reuses the frame, so no pointer into it may be passed
+        let args = match data.dfg().value(call).kind() {
+            ValueKind::Call(call) => call.args(),
+            _ => unreachable!(),
+        };
+        if args.iter().all(|&arg| !points_into_frame(data, arg)) {
+            calls.push((call, ret));
+        }
+    }
+    calls
+}
@@ -0,0 +1,503 @@
+use super::analysis::{insert_preheader, preheader, Cfg, DomTree, LoopInfo};
+use super::util::{
+    edge_arg, insert_inst_before, new_bb, remove_unreachable_blocks, step_of, terminator,
+};
+use koopa::ir::builder::{LocalInstBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, BinaryOp, Function, FunctionData, Value, ValueKind};
+use koopa::opt::FunctionPass;
+use std::collections::HashMap;
+
+// ============ Loop Unrolling ============
+/*
+    Works on innermost loops in the shape `WhileStmt` lowering leaves after
+    mem2reg: the header `%while` tests `i <op> n` and either enters the body
+    or leaves the loop, and the body (`%loop` ...) only leaves through the
+    single back edge. `i` is a header parameter advanced by a constant step,
+    `n` is loop invariant.
+
+    - Full unrolling: with a constant trip count and at most
+      FULL_UNROLL_SIZE instructions after unrolling, the preheader enters a
+      chain of copies whose headers jump straight into their bodies, and the
+      original header only exits. Repeated so that small nests unroll
+      completely.
+    - Partial unrolling: `factor` copies form a new loop, entered through a
+      block testing whether a whole round is left, `i <op> n - (factor - 1) * step`.
+      The original loop runs the remaining iterations. The test has a block
+      of its own, as the header may have effects that must not run twice.
+
+    Copied header tests that are no longer used are left to dce.
+*/
+
+pub const DEFAULT_UNROLL_FACTOR: usize = 4;
+
+// instructions of a fully unrolled loop
+const FULL_UNROLL_SIZE: usize = 200;
+// instructions of a partially unrolled loop
+const PARTIAL_UNROLL_SIZE: usize = 80;
+// rounds of full unrolling, one per nesting level
+const MAX_ROUNDS: usize = 4;
+
+pub struct Unroll {
+    factor: usize,
+}
+
+impl Unroll {
+    pub fn new(factor: usize) -> Self {
+        Unroll { factor }
+    }
+}
+
+impl FunctionPass for Unroll {
+    fn run_on(&mut self, _func: Function, data: &mut FunctionData) {
+        for _ in 0..MAX_ROUNDS {
+            let mut changed = false;
+            for lp in candidates(data) {
+                match trip_count(data, &lp) {
+                    Some(trips) if trips > 0 && trips * lp.size <= FULL_UNROLL_SIZE => {
+                        full_unroll(data, &lp, trips);
+                        changed = true;
+                    }
+                    _ => {}
+                }
+            }
+            if !changed {
+                break;
+            }
+            remove_unreachable_blocks(data);
+        }
+        if self.factor < 2 {
+            return;
+        }
+        for lp in candidates(data) {
+            let enough_trips = trip_count(data, &lp).is_none_or(|trips| trips >= 2 * self.factor);
+            if enough_trips && lp.size * self.factor <= PARTIAL_UNROLL_SIZE {
+                partial_unroll(data, &lp, self.factor);
+            }
+        }
+    }
+}
+
+// an innermost loop in the supported shape
+struct CountedLoop {
+    header: BasicBlock,
+    preheader: BasicBlock,
+    // blocks in reverse postorder, the header first
+    blocks: Vec<BasicBlock>,
+    // the test is `params[index] <op> bound`
+    index: usize,
+    op: BinaryOp,
+    bound: Value,
+    step: i32,
+    init: Value,
+    size: usize,
+}
+
+fn candidates(data: &mut FunctionData) -> Vec<CountedLoop> {
+    let cfg = match Cfg::new(data) {
+        Some(cfg) => cfg,
+        None => return Vec::new(),
+    };
+    let dom = DomTree::new(&cfg);
+    let loops = LoopInfo::new(&cfg, &dom);
+    for lp in loops.loops.iter() {
+        insert_preheader(data, &cfg, lp);
+    }
+    let cfg = Cfg::new(data).unwrap();
+    let dom = DomTree::new(&cfg);
+    let loops = LoopInfo::new(&cfg, &dom);
+
+    let mut candidates = Vec::new();
+    for (i, lp) in loops.loops.iter().enumerate() {
+        let innermost = loops.loops.iter().all(|other| other.parent != Some(i));
+        if !innermost || lp.latches.len() != 1 {
+            continue;
+        }
+        let header = lp.header;
+        // the header enters the body or leaves
+        let cond = match data.dfg().value(terminator(data, header)).kind() {
+            ValueKind::Branch(branch)
+                if lp.blocks.contains(&branch.true_bb())
+                    && !lp.blocks.contains(&branch.false_bb()) =>
+            {
+                branch.cond()
+            }
+            _ => continue,
+        };
+        let exits_from_body = lp
+            .blocks
+            .iter()
+            .any(|&bb| bb != header && cfg.succs(bb).iter().any(|succ| !lp.blocks.contains(succ)));
+        if exits_from_body {
+            continue;
+        }
+        // the test, with the induction variable on the left
+        let params = data.dfg().bb(header).params();
+        let (op, lhs, rhs) = match data.dfg().value(cond).kind() {
+            ValueKind::Binary(bin) => (bin.op(), bin.lhs(), bin.rhs()),
+            _ => continue,
+        };
+        let (index, op, bound) = match (
+            params.iter().position(|&param| param == lhs),
+            params.iter().position(|&param| param == rhs),
+        ) {
+            (Some(index), _) if lp.is_invariant(data, rhs) => (index, op, rhs),
+            (_, Some(index)) if lp.is_invariant(data, lhs) => match swap_operands(op) {
+                Some(op) => (index, op, lhs),
+                None => continue,
+            },
+            _ => continue,
+        };
+        let latch = terminator(data, lp.latches[0]);
+        let step = match edge_arg(data, latch, header, index)
+            .and_then(|arg| step_of(data, arg, params[index]))
+        {
+            Some(step) => step,
+            None => continue,
+        };
+        let counts_up = matches!(op, BinaryOp::Lt | BinaryOp::Le) && step > 0;
+        let counts_down = matches!(op, BinaryOp::Gt | BinaryOp::Ge) && step < 0;
+        if !counts_up && !counts_down {
+            continue;
+        }
+        let pre = preheader(&cfg, lp).unwrap();
+        let init = edge_arg(data, terminator(data, pre), header, index).unwrap();
+
+        let blocks: Vec<BasicBlock> = cfg
+            .rpo
+            .iter()
+            .filter(|bb| lp.blocks.contains(*bb))
+            .copied()
+            .collect();
+        let insts: Vec<Value> = blocks
+            .iter()
+            .flat_map(|bb| data.layout().bbs().node(bb).unwrap().insts().keys())
+            .copied()
+            .collect();
+        // an alloc in the loop is the same slot in every iteration, its copies would not be
+        if insts
+            .iter()
+            .any(|&inst| matches!(data.dfg().value(inst).kind(), ValueKind::Alloc(_)))
+        {
+            continue;
+        }
+        candidates.push(CountedLoop {
+            header,
+            preheader: pre,
+            blocks,
+            index,
+            op,
+            bound,
+            step,
+            init,
+            size: insts.len(),
+        });
+    }
+    candidates
+}
+
+// `a <op> b` as `b <op'> a`
+fn swap_operands(op: BinaryOp) -> Option<BinaryOp> {
+    match op {
+        BinaryOp::Lt => Some(BinaryOp::Gt),
+        BinaryOp::Le => Some(BinaryOp::Ge),
+        BinaryOp::Gt => Some(BinaryOp::Lt),
+        BinaryOp::Ge => Some(BinaryOp::Le),
+        _ => None,
+    }
+}
+
+fn int_value(data: &FunctionData, value: Value) -> Option<i32> {
+    match data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    }
+}
+
+// iterations of a loop with constant bounds, None if unknown or if the
+// induction variable would overflow
+fn trip_count(data: &FunctionData, lp: &CountedLoop) -> Option<usize> {
+    let init = int_value(data, lp.init)? as i64;
+    let bound = int_value(data, lp.bound)? as i64;
+    let step = lp.step as i64;
+    // distance to the first value failing the test
+    let distance = match lp.op {
+        BinaryOp::Lt => bound - init,
+        BinaryOp::Le => bound - init + 1,
+        BinaryOp::Gt => init - bound,
+        BinaryOp::Ge => init - bound + 1,
+        _ => unreachable!(),
+    };
+    if distance <= 0 {
+        return Some(0);
+    }
+    let trips = (distance + step.abs() - 1) / step.abs();
+    let last = init + trips * step;
+    if last < i32::MIN as i64 || last > i32::MAX as i64 {
+        return None;
+    }
+    Some(trips as usize)
+}
+
+// ---- transformations ----
+
+fn full_unroll(data: &mut FunctionData, lp: &CountedLoop, trips: usize) {
+    let headers = clone_loop(data, lp, trips, lp.header);
+    let pre_jump = terminator(data, lp.preheader);
+    let args = match data.dfg().value(pre_jump).kind() {
+        ValueKind::Jump(jump) => jump.args().to_vec(),
+        _ => unreachable!(),
+    };
+    data.dfg_mut()
+        .replace_value_with(pre_jump)
+        .jump_with_args(headers[0], args);
+    // the original header is reached after the last iteration and leaves,
+    // the original body becomes unreachable
+    let branch = terminator(data, lp.header);
+    let (exit, exit_args) = match data.dfg().value(branch).kind() {
+        ValueKind::Branch(branch) => (branch.false_bb(), branch.false_args().to_vec()),
+        _ => unreachable!(),
+    };
+    data.dfg_mut()
+        .replace_value_with(branch)
+        .jump_with_args(exit, exit_args);
+}
+
+fn partial_unroll(data: &mut FunctionData, lp: &CountedLoop, factor: usize) {
+    // the induction variable of the last copy is i + (factor - 1) * step
+    let offset = (factor as i64 - 1) * lp.step as i64;
+    let pre_jump = terminator(data, lp.preheader);
+    let (limit, enough) = match int_value(data, lp.bound) {
+        Some(bound) => match i32::try_from(bound as i64 - offset) {
+            Ok(limit) => (data.dfg_mut().new_value().integer(limit), None),
+            // no round fits
+            Err(_) => return,
+        },
+        None => {
+            let offset = match i32::try_from(offset) {
+                Ok(offset) => data.dfg_mut().new_value().integer(offset),
+                Err(_) => return,
+            };
+            let limit = data
+                .dfg_mut()
+                .new_value()
+                .binary(BinaryOp::Sub, lp.bound, offset);
+            insert_inst_before(data, limit, pre_jump);
+            // n - offset must not wrap around
+            let op = if lp.step > 0 {
+                BinaryOp::Lt
+            } else {
+                BinaryOp::Gt
+            };
+            let enough = data.dfg_mut().new_value().binary(op, limit, lp.bound);
+            insert_inst_before(data, enough, pre_jump);
+            (limit, Some(enough))
+        }
+    };
+
+    // tests for a whole round, otherwise goes on in the original loop
+    let header_data = data.dfg().bb(lp.header);
+    let name = header_data.name().as_ref().map_or("bb", |name| &name[1..]);
+    let name = format!("{}_round", name);
+    let tys = header_data
+        .params()
+        .iter()
+        .map(|&param| data.dfg().value(param).ty().clone())
+        .collect();
+    let round = new_bb(data, &name, tys);
+    data.layout_mut()
+        .bbs_mut()
+        .cursor_mut(lp.header)
+        .insert_key_before(round)
+        .unwrap();
+    let headers = clone_loop(data, lp, factor, round);
+    let params = data.dfg().bb(round).params().to_vec();
+    let test = data
+        .dfg_mut()
+        .new_value()
+        .binary(lp.op, params[lp.index], limit);
+    let branch = data.dfg_mut().new_value().branch_with_args(
+        test,
+        headers[0],
+        lp.header,
+        params.clone(),
+        params,
+    );
+    let insts = data.layout_mut().bb_mut(round).insts_mut();
+    insts.push_key_back(test).unwrap();
+    insts.push_key_back(branch).unwrap();
+
+    let args = match data.dfg().value(pre_jump).kind() {
+        ValueKind::Jump(jump) => jump.args().to_vec(),
+        _ => unreachable!(),
+    };
+    match enough {
+        Some(enough) => data
+            .dfg_mut()
+            .replace_value_with(pre_jump)
+            .branch_with_args(enough, round, lp.header, args.clone(), args),
+        None => data
+            .dfg_mut()
+            .replace_value_with(pre_jump)
+            .jump_with_args(round, args),
+    };
+}
+
+// `count` copies of the loop before its header, each header jumping into its
+// body without the test. The back edge of copy k goes to copy k + 1, the one
+// of the last copy to `last_target`. Returns the headers of the copies.
+fn clone_loop(
+    data: &mut FunctionData,
+    lp: &CountedLoop,
+    count: usize,
+    last_target: BasicBlock,
+) -> Vec<BasicBlock> {
+    // create the blocks first, back edges point to the next copy
+    let mut copies: Vec<HashMap<BasicBlock, BasicBlock>> = Vec::new();
+    for _ in 0..count {
+        let mut bbs = HashMap::new();
+        for &bb in lp.blocks.iter() {
+            let bb_data = data.dfg().bb(bb);
+            let name = bb_data.name().as_ref().map_or("bb", |name| &name[1..]);
+            let name = format!("{}_unroll", name);
+            let tys = bb_data
+                .params()
+                .iter()
+                .map(|&param| data.dfg().value(param).ty().clone())
+                .collect();
+            let new = new_bb(data, &name, tys);
+            data.layout_mut()
+                .bbs_mut()
+                .cursor_mut(lp.header)
+                .insert_key_before(new)
+                .unwrap();
+            bbs.insert(bb, new);
+        }
+        copies.push(bbs);
+    }
+    let headers: Vec<BasicBlock> = copies.iter().map(|bbs| bbs[&lp.header]).collect();
+
+    for (k, bbs) in copies.iter().enumerate() {
+        let next = headers.get(k + 1).copied().unwrap_or(last_target);
+        let mut targets = bbs.clone();
+        targets.insert(lp.header, next);
+        let mut cloner = Cloner {
+            data: &mut *data,
+            values: HashMap::new(),
+            targets,
+        };
+        for &bb in lp.blocks.iter() {
+            let params = cloner.data.dfg().bb(bb).params().to_vec();
+            let new_params = cloner.data.dfg().bb(bbs[&bb]).params().to_vec();
+            cloner.values.extend(params.into_iter().zip(new_params));
+        }
+        for &bb in lp.blocks.iter() {
+            let insts: Vec<Value> = cloner
+                .data
+                .layout()
+                .bbs()
+                .node(&bb)
+                .unwrap()
+                .insts()
+                .keys()
+                .copied()
+                .collect();
+            let last = insts.last().copied();
+            for inst in insts {
+                let new = if bb == lp.header && Some(inst) == last {
+                    // the header goes into the body unconditionally
+                    cloner.header_jump(inst)
+                } else {
+                    cloner.clone_inst(inst)
+                };
+                cloner
+                    .data
+                    .layout_mut()
+                    .bb_mut(bbs[&bb])
+                    .insts_mut()
+                    .push_key_back(new)
+                    .unwrap();
+                cloner.values.insert(inst, new);
+            }
+        }
+    }
+    headers
+}
+
+struct Cloner<'a> {
+    data: &'a mut FunctionData,
+    values: HashMap<Value, Value>,
+    targets: HashMap<BasicBlock, BasicBlock>,
+}
+
+impl Cloner<'_> {
+    // values defined outside the loop are shared
+    fn value(&self, value: Value) -> Value {
+        self.values.get(&value).copied().unwrap_or(value)
+    }
+
+    fn values(&self, values: &[Value]) -> Vec<Value> {
+        values.iter().map(|&value| self.value(value)).collect()
+    }
+
+    fn target(&self, bb: BasicBlock) -> BasicBlock {
+        self.targets.get(&bb).copied().unwrap_or(bb)
+    }
+
+    fn header_jump(&mut self, branch: Value) -> Value {
+        let (body, args) = match self.data.dfg().value(branch).kind() {
+            ValueKind::Branch(branch) => (
+                self.target(branch.true_bb()),
+                self.values(branch.true_args()),
+            ),
+            _ => unreachable!(),
+        };
+        self.data.dfg_mut().new_value().jump_with_args(body, args)
+    }
+
+    fn clone_inst(&mut self, inst: Value) -> Value {
+        match self.data.dfg().value(inst).kind().clone() {
+            ValueKind::Load(load) => {
+                let src = self.value(load.src());
+                self.data.dfg_mut().new_value().load(src)
+            }
+            ValueKind::Store(store) => {
+                let (value, dest) = (self.value(store.value()), self.value(store.dest()));
+                self.data.dfg_mut().new_value().store(value, dest)
+            }
+            ValueKind::GetPtr(get_ptr) => {
+                let (src, index) = (self.value(get_ptr.src()), self.value(get_ptr.index()));
+                self.data.dfg_mut().new_value().get_ptr(src, index)
+            }
+            ValueKind::GetElemPtr(gep) => {
+                let (src, index) = (self.value(gep.src()), self.value(gep.index()));
+                self.data.dfg_mut().new_value().get_elem_ptr(src, index)
+            }
+            ValueKind::Binary(bin) => {
+                let (lhs, rhs) = (self.value(bin.lhs()), self.value(bin.rhs()));
+                self.data.dfg_mut().new_value().binary(bin.op(), lhs, rhs)
+            }
+            ValueKind::Branch(branch) => {
+                let cond = self.value(branch.cond());
+                let (true_bb, false_bb) = (
+                    self.target(branch.true_bb()),
+                    self.target(branch.false_bb()),
+                );
+                let true_args = self.values(branch.true_args());
+                let false_args = self.values(branch.false_args());
+                self.data
+                    .dfg_mut()
+                    .new_value()
+                    .branch_with_args(cond, true_bb, false_bb, true_args, false_args)
+            }
+            ValueKind::Jump(jump) => {
+                let (target, args) = (self.target(jump.target()), self.values(jump.args()));
+                self.data.dfg_mut().new_value().jump_with_args(target, args)
+            }
+            ValueKind::Call(call) => {
+                let args = self.values(call.args());
+                self.data.dfg_mut().new_value().call(call.callee(), args)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
@@ -0,0 +1,333 @@
+use koopa::ir::builder::{BasicBlockBuilder, ValueBuilder};
+use koopa::ir::{BasicBlock, BinaryOp, FunctionData, Type, TypeKind, Value, ValueKind};
+use std::collections::HashSet;
+
+// ============ IR Helpers ============
+
+// successors of `bb`, following its terminator
+pub fn successors(data: &FunctionData, bb: BasicBlock) -> Vec<BasicBlock> {
+    let last = match data.layout().bbs().node(&bb).unwrap().insts().back_key() {
+        Some(last) => *last,
+        None => return Vec::new(),
+    };
+    match data.dfg().value(last).kind() {
+        ValueKind::Jump(jump) => vec![jump.target()],
+        ValueKind::Branch(branch) => vec![branch.true_bb(), branch.false_bb()],
+        _ => Vec::new(),
+    }
+}
+
+// replace every use of `old` with `new`, koopa has no replace-all-uses of its own
+pub fn replace_uses(data: &mut FunctionData, old: Value, new: Value) {
+    let users: Vec<Value> = data.dfg().value(old).used_by().iter().copied().collect();
+    for user in users {
+        let mut user_data = data.dfg().value(user).clone();
+        substitute(user_data.kind_mut(), old, new);
+        data.dfg_mut().replace_value_with(user).raw(user_data);
+    }
+}
+
+fn substitute(kind: &mut ValueKind, old: Value, new: Value) {
+    let swap = |value: &mut Value| {
+        if *value == old {
+            *value = new;
+        }
+    };
+    match kind {
+        ValueKind::Load(load) => swap(load.src_mut()),
+        ValueKind::Store(store) => {
+            swap(store.value_mut());
+            swap(store.dest_mut());
+        }
+        ValueKind::GetPtr(get_ptr) => {
+            swap(get_ptr.src_mut());
+            swap(get_ptr.index_mut());
+        }
+        ValueKind::GetElemPtr(get_elem_ptr) => {
+            swap(get_elem_ptr.src_mut());
+            swap(get_elem_ptr.index_mut());
+        }
+        ValueKind::Binary(binary) => {
+            swap(binary.lhs_mut());
+            swap(binary.rhs_mut());
+        }
+        ValueKind::Branch(branch) => {
+            swap(branch.cond_mut());
+            branch.true_args_mut().iter_mut().for_each(swap);
+            branch.false_args_mut().iter_mut().for_each(swap);
+        }
+        ValueKind::Jump(jump) => jump.args_mut().iter_mut().for_each(swap),
+        ValueKind::Call(call) => call.args_mut().iter_mut().for_each(swap),
+        ValueKind::Return(ret) => {
+            if let Some(value) = ret.value_mut() {
+                swap(value);
+            }
+        }
+        _ => {}
+    }
+}
+
+// a new block outside the layout; the backend uses block names as labels, which must be
+// unique in the whole program, so it is qualified with the function name
+pub fn new_bb(data: &mut FunctionData, base: &str, param_tys: Vec<Type>) -> BasicBlock {
+    let func = data.name()[1..].to_string();
+    let taken: HashSet<String> = data
+        .dfg()
+        .bbs()
+        .values()
+        .filter_map(|bb_data| bb_data.name().clone())
+        .collect();
+    let name = (1..)
+        .map(|i| format!("%{}_{}_{}", base, func, i))
+        .find(|name| !taken.contains(name))
+        .unwrap();
+    data.dfg_mut()
+        .new_bb()
+        .basic_block_with_params(Some(name), param_tys)
+}
+
+// move an instruction right before `before`, possibly in another block
+pub fn move_inst_before(data: &mut FunctionData, inst: Value, before: Value) {
+    let bb = data.layout().parent_bb(inst).unwrap();
+    data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+    let target = data.layout().parent_bb(before).unwrap();
+    data.layout_mut()
+        .bb_mut(target)
+        .insts_mut()
+        .cursor_mut(before)
+        .insert_key_before(inst)
+        .unwrap();
+}
+
+// insert a new instruction right before `before`
+pub fn insert_inst_before(data: &mut FunctionData, inst: Value, before: Value) {
+    let bb = data.layout().parent_bb(before).unwrap();
+    data.layout_mut()
+        .bb_mut(bb)
+        .insts_mut()
+        .cursor_mut(before)
+        .insert_key_before(inst)
+        .unwrap();
+}
+
+// append a parameter to `bb`; every jump/branch to it must then get an argument
+pub fn add_block_param(data: &mut FunctionData, bb: BasicBlock, ty: Type) -> Value {
+    // koopa only creates parameters with a new block, so borrow a temporary one
+    // with one more parameter than `bb`, so the last one gets the right index
+    let mut tys: Vec<Type> = data
+        .dfg()
+        .bb(bb)
+        .params()
+        .iter()
+        .map(|&param| data.dfg().value(param).ty().clone())
+        .collect();
+    tys.push(ty);
+    let tmp = data.dfg_mut().new_bb().basic_block_with_params(None, tys);
+    let param = data.dfg_mut().bb_mut(tmp).params_mut().pop().unwrap();
+    data.dfg_mut().remove_bb(tmp);
+    data.dfg_mut().bb_mut(bb).params_mut().push(param);
+    param
+}
+
+// append an argument to the edges of `user` going to `target`
+pub fn add_edge_arg(data: &mut FunctionData, user: Value, target: BasicBlock, arg: Value) {
+    let mut user_data = data.dfg().value(user).clone();
+    match user_data.kind_mut() {
+        ValueKind::Jump(jump) => jump.args_mut().push(arg),
+        ValueKind::Branch(branch) => {
+            if branch.true_bb() == target {
+                branch.true_args_mut().push(arg);
+            }
+            if branch.false_bb() == target {
+                branch.false_args_mut().push(arg);
+            }
+        }
+        _ => unreachable!(),
+    }
+    data.dfg_mut().replace_value_with(user).raw(user_data);
+}
+
+// the last instruction of `bb`
+pub fn terminator(data: &FunctionData, bb: BasicBlock) -> Value {
+    *data
+        .layout()
+        .bbs()
+        .node(&bb)
+        .unwrap()
+        .insts()
+        .back_key()
+        .unwrap()
+}
+
+// argument `index` passed by `jump` to `target`, None for a branch with both edges to it
+pub fn edge_arg(
+    data: &FunctionData,
+    jump: Value,
+    target: BasicBlock,
+    index: usize,
+) -> Option<Value> {
+    match data.dfg().value(jump).kind() {
+        ValueKind::Jump(jump) => Some(jump.args()[index]),
+        ValueKind::Branch(branch) if branch.true_bb() != branch.false_bb() => {
+            if branch.true_bb() == target {
+                Some(branch.true_args()[index])
+            } else {
+                Some(branch.false_args()[index])
+            }
+        }
+        _ => None,
+    }
+}
+
+// `step` if `value` is `param + step` or `param - (-step)`
+pub fn step_of(data: &FunctionData, value: Value, param: Value) -> Option<i32> {
+    let int = |value: Value| match data.dfg().value(value).kind() {
+        ValueKind::Integer(int) => Some(int.value()),
+        _ => None,
+    };
+    match data.dfg().value(value).kind() {
+        ValueKind::Binary(bin) => match bin.op() {
+            BinaryOp::Add if bin.lhs() == param => int(bin.rhs()),
+            BinaryOp::Add if bin.rhs() == param => int(bin.lhs()),
+            BinaryOp::Sub if bin.lhs() == param => int(bin.rhs()).map(i32::wrapping_neg),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+// whether a pointer may point into the stack frame of the function:
+// derived from an alloc, or from a block parameter that might be
+pub fn points_into_frame(data: &FunctionData, mut ptr: Value) -> bool {
+    loop {
+        if ptr.is_global() {
+            return false;
+        }
+        let ptr_data = data.dfg().value(ptr);
+        if !matches!(ptr_data.ty().kind(), TypeKind::Pointer(_)) {
+            return false;
+        }
+        ptr = match ptr_data.kind() {
+            ValueKind::GetElemPtr(gep) => gep.src(),
+            ValueKind::GetPtr(gp) => gp.src(),
+            ValueKind::Alloc(_) | ValueKind::BlockArgRef(_) => return true,
+            _ => return false,
+        };
+    }
+}
+
+// remove an unused instruction from its block and the dfg
+pub fn remove_inst(data: &mut FunctionData, inst: Value) {
+    let bb = data.layout().parent_bb(inst).unwrap();
+    data.layout_mut().bb_mut(bb).insts_mut().remove(&inst);
+    data.dfg_mut().remove_value(inst);
+}
+
+// remove the blocks unreachable from the entry, returns whether any was removed
+pub fn remove_unreachable_blocks(data: &mut FunctionData) -> bool {
+    let entry = match data.layout().entry_bb() {
+        Some(entry) => entry,
+        None => return false,
+    };
+    // reachable blocks, following jump/branch targets
+    let mut reachable = HashSet::new();
+    let mut stack = vec![entry];
+    while let Some(bb) = stack.pop() {
+        if !reachable.insert(bb) {
+            continue;
+        }
+        stack.extend(successors(data, bb));
+    }
+    let dead: Vec<BasicBlock> = data
+        .layout()
+        .bbs()
+        .keys()
+        .filter(|bb| !reachable.contains(*bb))
+        .copied()
+        .collect();
+    if dead.is_empty() {
+        return false;
+    }
+    let mut dead_insts = Vec::new();
+    for bb in dead.iter() {
+        let (_, node) = data.layout_mut().bbs_mut().remove(bb).unwrap();
+        dead_insts.extend(node.insts().keys().copied());
+    }
+    // values are only used inside dead blocks
+    remove_dead_values(data, dead_insts);
+    for bb in dead {
+        data.dfg_mut().remove_bb(bb);
+    }
+    true
+}
+
+// remove values that are out of the layout and only used by each other,
+// users are removed before their operands
+pub fn remove_dead_values(data: &mut FunctionData, mut values: Vec<Value>) {
+    while !values.is_empty() {
+        let (unused, used): (Vec<_>, Vec<_>) = values
+            .into_iter()
+            .partition(|&value| data.dfg().value(value).used_by().is_empty());
+        assert!(!unused.is_empty(), "dead value used by live code");
+        for value in unused {
+            data.dfg_mut().remove_value(value);
+        }
+        values = used;
+    }
+}
+
+// remove parameter `index` of `bb` and the matching argument of every jump/branch to it;
+// the parameter must be unused
+pub fn remove_block_param(data: &mut FunctionData, bb: BasicBlock, index: usize) {
+    let param = detach_block_param(data, bb, index);
+    data.dfg_mut().remove_value(param);
+}
+
+// like `remove_block_param`, but the parameter stays in the dfg and may still have users
+pub fn detach_block_param(data: &mut FunctionData, bb: BasicBlock, index: usize) -> Value {
+    let users: Vec<Value> = data.dfg().bb(bb).used_by().iter().copied().collect();
+    for user in users {
+        let mut user_data = data.dfg().value(user).clone();
+        match user_data.kind_mut() {
+            ValueKind::Jump(jump) => {
+                jump.args_mut().remove(index);
+            }
+            ValueKind::Branch(branch) => {
+                if branch.true_bb() == bb {
+                    branch.true_args_mut().remove(index);
+                }
+                if branch.false_bb() == bb {
+                    branch.false_args_mut().remove(index);
+                }
+            }
+            _ => unreachable!(),
+        }
+        data.dfg_mut().replace_value_with(user).raw(user_data);
+    }
+    let param = data.dfg_mut().bb_mut(bb).params_mut().remove(index);
+    // the later parameters move down one index
+    let later: Vec<Value> = data.dfg().bb(bb).params()[index..].to_vec();
+    for later_param in later {
+        let mut param_data = data.dfg().value(later_param).clone();
+        if let ValueKind::BlockArgRef(arg) = param_data.kind_mut() {
+            *arg.index_mut() -= 1;
+        }
+        data.dfg_mut().replace_value_with(later_param).raw(param_data);
+    }
+    param
+}
+
+// drop every block and instruction of `data` before the function is removed,
+// as program.remove_func does not update the used_by of the globals
+pub fn clear_body(data: &mut FunctionData) {
+    let bbs: Vec<BasicBlock> = data.layout().bbs().keys().copied().collect();
+    let mut insts = Vec::new();
+    for bb in bbs.iter() {
+        let (_, node) = data.layout_mut().bbs_mut().remove(bb).unwrap();
+        insts.extend(node.insts().keys().copied());
+    }
+    remove_dead_values(data, insts);
+    for bb in bbs {
+        data.dfg_mut().remove_bb(bb);
+    }
+}
@@ -0,0 +1,348 @@
+use super::analysis::{Cfg, DomTree};
+use koopa::ir::{BasicBlock, FunctionData, Program, Type, TypeKind, Value, ValueKind};
+use std::collections::HashMap;
+
+// ============ Verifier ============
+/*
+    Checks every function body:
+        - each block is non-empty and ends with its only terminator
+        - every operand is defined, and its definition dominates the use
+        - operand types of load/store/getptr/getelemptr/binary/branch/call/ret
+        - jump/branch arguments match the parameters of the target block
+        - the index of each block parameter is its position in the block
+        - referenced blocks are laid out in the function, the entry has no predecessors
+*/
+
+pub fn verify(program: &Program) -> Result<(), String> {
+    for &func in program.func_layout() {
+        let data = program.func(func);
+        if data.layout().entry_bb().is_none() {
+            continue;
+        }
+        Verifier::new(program, data)
+            .run()
+            .map_err(|err| format!("in function {}: {}", data.name(), err))?;
+    }
+    Ok(())
+}
+
+struct Verifier<'a> {
+    program: &'a Program,
+    data: &'a FunctionData,
+    cfg: Cfg,
+    dom: DomTree,
+    // block and position of every instruction
+    def_site: HashMap<Value, (BasicBlock, usize)>,
+    // owner block of every block parameter
+    param_owner: HashMap<Value, BasicBlock>,
+}
+
+impl<'a> Verifier<'a> {
+    fn new(program: &'a Program, data: &'a FunctionData) -> Self {
+        let cfg = Cfg::new(data).unwrap();
+        let dom = DomTree::new(&cfg);
+        let mut def_site = HashMap::new();
+        let mut param_owner = HashMap::new();
+        for (&bb, node) in data.layout().bbs() {
+            for (i, &inst) in node.insts().keys().enumerate() {
+                def_site.insert(inst, (bb, i));
+            }
+            for &param in data.dfg().bb(bb).params() {
+                param_owner.insert(param, bb);
+            }
+        }
+        Verifier {
+            program,
+            data,
+            cfg,
+            dom,
+            def_site,
+            param_owner,
+        }
+    }
+
+    fn run(&self) -> Result<(), String> {
+        let entry = self.cfg.entry;
+        if !self.cfg.preds(entry).is_empty() {
+            return Err(format!(
+                "entry block {} has predecessors",
+                self.bb_name(entry)
+            ));
+        }
+        for &bb in self.data.dfg().bbs().keys() {
+            if self.data.layout().bbs().node(&bb).is_none()
+                && !self.data.dfg().bb(bb).used_by().is_empty()
+            {
+                return Err(format!(
+                    "block {} is referenced but not in the layout",
+                    self.bb_name(bb)
+                ));
+            }
+        }
+        for (&bb, node) in self.data.layout().bbs() {
+            self.check_params(bb)?;
+            let last = match node.insts().back_key() {
+                Some(&last) => last,
+                None => return Err(format!("block {} is empty", self.bb_name(bb))),
+            };
+            for (i, &inst) in node.insts().keys().enumerate() {
+                let is_terminator = matches!(
+                    self.data.dfg().value(inst).kind(),
+                    ValueKind::Jump(_) | ValueKind::Branch(_) | ValueKind::Return(_)
+                );
+                let at = || format!("block {}, instruction {}", self.bb_name(bb), i);
+                if is_terminator && inst != last {
+                    return Err(format!("{}: terminator in the middle of a block", at()));
+                }
+                if !is_terminator && inst == last {
+                    return Err(format!("{}: block does not end with a terminator", at()));
+                }
+                self.check_operands(bb, i, inst)
+                    .and_then(|_| self.check_types(inst))
+                    .map_err(|err| format!("{} ({}): {}", at(), self.inst_name(inst), err))?;
+            }
+        }
+        Ok(())
+    }
+
+    // operands must be defined before the use on every path from the entry
+    fn check_operands(&self, bb: BasicBlock, pos: usize, inst: Value) -> Result<(), String> {
+        let kind = self.data.dfg().value(inst).kind();
+        for operand in kind.value_uses() {
+            if operand.is_global() {
+                continue;
+            }
+            let operand_data = match self.data.dfg().values().get(&operand) {
+                Some(operand_data) => operand_data,
+                None => return Err("operand does not exist".to_string()),
+            };
+            match operand_data.kind() {
+                ValueKind::FuncArgRef(_) => {}
+                ValueKind::BlockArgRef(_) => {
+                    let owner = match self.param_owner.get(&operand) {
+                        Some(&owner) => owner,
+                        None => return Err("parameter of a removed block".to_string()),
+                    };
+                    if self.dom.is_reachable(bb) && !self.dom.dominates(owner, bb) {
+                        return Err(format!(
+                            "parameter of block {} does not dominate this use",
+                            self.bb_name(owner)
+                        ));
+                    }
+                }
+                kind if kind.is_const() => {}
+                _ => {
+                    let (def_bb, def_pos) = match self.def_site.get(&operand) {
+                        Some(&site) => site,
+                        None => return Err("operand is not in the layout".to_string()),
+                    };
+                    let dominated = if def_bb == bb {
+                        def_pos < pos
+                    } else {
+                        !self.dom.is_reachable(bb) || self.dom.dominates(def_bb, bb)
+                    };
+                    if !dominated {
+                        return Err(format!(
+                            "operand {} does not dominate this use",
+                            self.inst_name(operand)
+                        ));
+                    }
+                }
+            }
+        }
+        for target in kind.bb_uses() {
+            if self.data.layout().bbs().node(&target).is_none() {
+                return Err("target block is not in the layout".to_string());
+            }
+            if target == self.cfg.entry {
+                return Err("jump to the entry block".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    fn check_types(&self, inst: Value) -> Result<(), String> {
+        let i32_ty = Type::get_i32();
+        let value_data = self.data.dfg().value(inst);
+        let ty = value_data.ty();
+        match value_data.kind() {
+            ValueKind::Alloc(_) if !matches!(ty.kind(), TypeKind::Pointer(_)) => {
+                return Err("alloc must have a pointer type".to_string());
+            }
+            ValueKind::Load(load) => {
+                let pointee = self.pointee(load.src(), "load source")?;
+                if pointee != *ty {
+                    return Err(format!("load of {} has type {}", pointee, ty));
+                }
+            }
+            ValueKind::Store(store) => {
+                let pointee = self.pointee(store.dest(), "store destination")?;
+                let value_ty = self.ty(store.value());
+                if pointee != value_ty {
+                    return Err(format!("store of {} to *{}", value_ty, pointee));
+                }
+            }
+            ValueKind::GetPtr(get_ptr) => {
+                self.pointee(get_ptr.src(), "getptr source")?;
+                self.expect(get_ptr.index(), &i32_ty, "getptr index")?;
+                if self.ty(get_ptr.src()) != *ty {
+                    return Err(format!("getptr result has type {}", ty));
+                }
+            }
+            ValueKind::GetElemPtr(get_elem_ptr) => {
+                let elem = match self.pointee(get_elem_ptr.src(), "getelemptr source")?.kind() {
+                    TypeKind::Array(elem, _) => elem.clone(),
+                    other => return Err(format!("getelemptr source points to {}", other)),
+                };
+                self.expect(get_elem_ptr.index(), &i32_ty, "getelemptr index")?;
+                if *ty != Type::get_pointer(elem.clone()) {
+                    return Err(format!("getelemptr result has type {}, expected *{}", ty, elem));
+                }
+            }
+            ValueKind::Binary(binary) => {
+                self.expect(binary.lhs(), &i32_ty, "binary lhs")?;
+                self.expect(binary.rhs(), &i32_ty, "binary rhs")?;
+            }
+            ValueKind::Branch(branch) => {
+                self.expect(branch.cond(), &i32_ty, "branch condition")?;
+                self.check_args(branch.true_bb(), branch.true_args())?;
+                self.check_args(branch.false_bb(), branch.false_args())?;
+            }
+            ValueKind::Jump(jump) => self.check_args(jump.target(), jump.args())?,
+            ValueKind::Call(call) => {
+                let callee = self.program.func(call.callee());
+                let (params, ret) = match callee.ty().kind() {
+                    TypeKind::Function(params, ret) => (params, ret),
+                    _ => unreachable!(),
+                };
+                if params.len() != call.args().len() {
+                    return Err(format!(
+                        "{} expects {} arguments, got {}",
+                        callee.name(),
+                        params.len(),
+                        call.args().len()
+                    ));
+                }
+                for (i, (&arg, param)) in call.args().iter().zip(params).enumerate() {
+                    self.expect(arg, param, &format!("argument {} of {}", i, callee.name()))?;
+                }
+                if ty != ret {
+                    return Err(format!("call result has type {}, expected {}", ty, ret));
+                }
+            }
+            ValueKind::Return(ret) => {
+                let func_ret = match self.data.ty().kind() {
+                    TypeKind::Function(_, ret) => ret.clone(),
+                    _ => unreachable!(),
+                };
+                match ret.value() {
+                    Some(value) => self.expect(value, &func_ret, "return value")?,
+                    None if !func_ret.is_unit() => {
+                        return Err(format!("missing return value of type {}", func_ret))
+                    }
+                    None => {}
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn check_params(&self, bb: BasicBlock) -> Result<(), String> {
+        for (i, &param) in self.data.dfg().bb(bb).params().iter().enumerate() {
+            match self.data.dfg().value(param).kind() {
+                ValueKind::BlockArgRef(arg) if arg.index() == i => {}
+                ValueKind::BlockArgRef(arg) => {
+                    return Err(format!(
+                        "parameter {} of block {} has index {}",
+                        i,
+                        self.bb_name(bb),
+                        arg.index()
+                    ))
+                }
+                _ => {
+                    return Err(format!(
+                        "parameter {} of block {} is not a block argument",
+                        i,
+                        self.bb_name(bb)
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn check_args(&self, target: BasicBlock, args: &[Value]) -> Result<(), String> {
+        let params = self.data.dfg().bb(target).params();
+        if params.len() != args.len() {
+            return Err(format!(
+                "block {} expects {} arguments, got {}",
+                self.bb_name(target),
+                params.len(),
+                args.len()
+            ));
+        }
+        for (i, (&arg, &param)) in args.iter().zip(params).enumerate() {
+            let param_ty = self.ty(param);
+            self.expect(
+                arg,
+                &param_ty,
+                &format!("argument {} of block {}", i, self.bb_name(target)),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn expect(&self, value: Value, expected: &Type, what: &str) -> Result<(), String> {
+        let ty = self.ty(value);
+        if ty != *expected {
+            return Err(format!("{} has type {}, expected {}", what, ty, expected));
+        }
+        Ok(())
+    }
+
+    fn pointee(&self, value: Value, what: &str) -> Result<Type, String> {
+        match self.ty(value).kind() {
+            TypeKind::Pointer(pointee) => Ok(pointee.clone()),
+            _ => Err(format!("{} has non-pointer type {}", what, self.ty(value))),
+        }
+    }
+
+    fn ty(&self, value: Value) -> Type {
+        if value.is_global() {
+            self.program.borrow_value(value).ty().clone()
+        } else {
+            self.data.dfg().value(value).ty().clone()
+        }
+    }
+
+    fn bb_name(&self, bb: BasicBlock) -> String {
+        self.data
+            .dfg()
+            .bbs()
+            .get(&bb)
+            .and_then(|bb_data| bb_data.name().clone())
+            .unwrap_or_else(|| "<unnamed>".to_string())
+    }
+
+    fn inst_name(&self, inst: Value) -> String {
+        let value_data = self.data.dfg().value(inst);
+        let kind = match value_data.kind() {
+            ValueKind::Alloc(_) => "alloc",
+            ValueKind::Load(_) => "load",
+            ValueKind::Store(_) => "store",
+            ValueKind::GetPtr(_) => "getptr",
+            ValueKind::GetElemPtr(_) => "getelemptr",
+            ValueKind::Binary(_) => "binary",
+            ValueKind::Branch(_) => "br",
+            ValueKind::Jump(_) => "jump",
+            ValueKind::Call(_) => "call",
+            ValueKind::Return(_) => "ret",
+            _ => "value",
+        };
+        match value_data.name() {
+            Some(name) => format!("{} {}", kind, name),
+            None => kind.to_string(),
+        }
+    }
+}
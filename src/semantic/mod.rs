@@ -0,0 +1,682 @@
+use crate::ast::decl::*;
+use crate::ast::exp::*;
+use crate::ast::stmt::*;
+use crate::ast::*;
+use crate::ir::{ConstEnv, ConstI32Eval};
+use std::collections::HashMap;
+
+/*
+    Semantic checks run before IR generation, so that the IR builder
+    only sees well-formed programs:
+    - every identifier / function is defined, and defined once per scope
+    - constant expressions (array sizes, const and global initializers) are constant
+    - subscripts, call arguments and operands have matching shapes
+    - array initializer lists have no excess elements
+    - no assignment to constants, break/continue only inside loops
+    - return statements match the function type, `int main()` exists
+*/
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SemanticError {
+    pub pos: Pos,
+    pub message: String,
+}
+
+pub fn check(ast: &CompUnit) -> Result<(), Vec<SemanticError>> {
+    let mut checker = Checker::new();
+    checker.check_comp_unit(ast);
+    if checker.errors.is_empty() {
+        Ok(())
+    } else {
+        Err(checker.errors)
+    }
+}
+
+// ============ Symbols ============
+
+// sizes of the array dimensions, empty for int; None for the first dimension
+// of an array parameter and for sizes that are not valid constants
+type Dims = Vec<Option<usize>>;
+
+#[derive(Clone)]
+enum Symbol {
+    // scalar constants keep their value for constant evaluation
+    Const { dims: Dims, value: Option<i32> },
+    Var { dims: Dims },
+}
+
+impl Symbol {
+    fn dims(&self) -> &Dims {
+        match self {
+            Symbol::Const { dims, .. } | Symbol::Var { dims } => dims,
+        }
+    }
+}
+
+struct FuncSig {
+    returns_value: bool,
+    params: Vec<Dims>,
+}
+
+// shape of an expression value: `Some(dims)` int or array, `None` void
+type Shape = Option<Dims>;
+
+// two array shapes match if their sizes past the first dimension agree
+fn same_shape(param: &Dims, arg: &Dims) -> bool {
+    param.len() == arg.len()
+        && param
+            .iter()
+            .zip(arg)
+            .skip(1)
+            .all(|(a, b)| a.is_none() || b.is_none() || a == b)
+}
+
+// ============ Initializers ============
+
+// constant and variable initializer lists are laid out the same way
+trait InitList: Sized {
+    // the nested list, None for an expression
+    fn list(&self) -> Option<&[Self]>;
+}
+
+impl InitList for InitVal {
+    fn list(&self) -> Option<&[Self]> {
+        match self {
+            InitVal::Exp(_) => None,
+            InitVal::Array(init) => Some(init),
+        }
+    }
+}
+
+impl InitList for ConstInitVal {
+    fn list(&self) -> Option<&[Self]> {
+        match self {
+            ConstInitVal::ConstExp(_) => None,
+            ConstInitVal::ConstArray(init) => Some(init),
+        }
+    }
+}
+
+// whether `init` fits in an array of `size`, following ir::util::init_to_array:
+// a nested list fills the trailing dimensions its position is aligned to
+fn init_fits<T: InitList>(init: &[T], size: &[usize]) -> bool {
+    let total: usize = size.iter().product();
+    let mut len = 0;
+    for val in init {
+        if len >= total {
+            return false;
+        }
+        match val.list() {
+            None => len += 1,
+            Some(init) => {
+                let mut sub_size = Vec::new();
+                let mut rest = len;
+                for &dim in size.iter().skip(1).rev() {
+                    if !rest.is_multiple_of(dim) {
+                        break;
+                    }
+                    sub_size.insert(0, dim);
+                    rest /= dim;
+                }
+                if !init_fits(init, &sub_size) {
+                    return false;
+                }
+                len += sub_size.iter().product::<usize>();
+            }
+        }
+    }
+    true
+}
+
+struct Checker {
+    scopes: Vec<HashMap<String, Symbol>>,
+    funcs: HashMap<String, FuncSig>,
+    errors: Vec<SemanticError>,
+    // return type of the current function, loop nesting depth
+    returns_value: bool,
+    loop_depth: usize,
+}
+
+impl ConstEnv for Checker {
+    fn const_value(&self, ident: &str) -> Result<i32, String> {
+        match self.lookup(ident) {
+            Some(Symbol::Const {
+                value: Some(value), ..
+            }) => Ok(value),
+            Some(_) => Err(format!("{} cannot be evaluated during compilation", ident)),
+            None => Err(format!("{} is not defined", ident)),
+        }
+    }
+}
+
+impl Checker {
+    fn new() -> Self {
+        let mut funcs = HashMap::new();
+        // library functions, see ir::util::init_lib_decl
+        let lib_funcs: [(&str, bool, Vec<Dims>); 8] = [
+            ("getint", true, vec![]),
+            ("getch", true, vec![]),
+            ("getarray", true, vec![vec![None]]),
+            ("putint", false, vec![vec![]]),
+            ("putch", false, vec![vec![]]),
+            ("putarray", false, vec![vec![], vec![None]]),
+            ("starttime", false, vec![]),
+            ("stoptime", false, vec![]),
+        ];
+        for (name, returns_value, params) in lib_funcs {
+            funcs.insert(
+                name.to_string(),
+                FuncSig {
+                    returns_value,
+                    params,
+                },
+            );
+        }
+        Checker {
+            scopes: vec![HashMap::new()],
+            funcs,
+            errors: Vec::new(),
+            returns_value: false,
+            loop_depth: 0,
+        }
+    }
+
+    fn error(&mut self, pos: Pos, message: String) {
+        self.errors.push(SemanticError { pos, message });
+    }
+
+    fn lookup(&self, ident: &str) -> Option<Symbol> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(ident))
+            .cloned()
+    }
+
+    fn define(&mut self, ident: &str, pos: Pos, symbol: Symbol) {
+        let redefined = self.scopes.last().unwrap().contains_key(ident)
+            || (self.scopes.len() == 1 && self.funcs.contains_key(ident));
+        if redefined {
+            self.error(pos, format!("redefinition of '{}'", ident));
+        }
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(ident.to_string(), symbol);
+    }
+
+    fn const_eval(&mut self, exp: &Exp) -> Option<i32> {
+        match exp.get_const_i32(&*self) {
+            Ok(value) => Some(value),
+            Err(message) => {
+                self.error(
+                    exp.get_pos(),
+                    format!("expression is not a compile-time constant: {}", message),
+                );
+                None
+            }
+        }
+    }
+
+    fn check_dims(&mut self, index: &[ConstExp]) -> Dims {
+        let mut dims = Vec::new();
+        for size in index {
+            let len = self.const_eval(&size.exp);
+            if let Some(len) = len.filter(|&len| len <= 0) {
+                self.error(
+                    size.exp.get_pos(),
+                    format!("array size must be positive, found {}", len),
+                );
+            }
+            dims.push(len.filter(|&len| len > 0).map(|len| len as usize));
+        }
+        dims
+    }
+
+    fn check_init_size<T: InitList>(&mut self, init: &[T], dims: &Dims, ident: &str, pos: Pos) {
+        // sizes already reported as invalid are not checked again
+        let size: Option<Vec<usize>> = dims.iter().copied().collect();
+        if size.is_some_and(|size| !init_fits(init, &size)) {
+            self.error(
+                pos,
+                format!("excess elements in initializer of '{}'", ident),
+            );
+        }
+    }
+
+    // ---- declarations ----
+
+    fn check_comp_unit(&mut self, ast: &CompUnit) {
+        for item in ast.items.iter() {
+            match item {
+                CompItem::Decl(decl) => self.check_decl(decl),
+                CompItem::FuncDef(func_def) => self.check_func_def(func_def),
+            }
+        }
+        match self.funcs.get("main") {
+            Some(main) if main.returns_value && main.params.is_empty() => {}
+            Some(_) => {
+                let pos = ast
+                    .items
+                    .iter()
+                    .find_map(|item| match item {
+                        CompItem::FuncDef(func) if func.ident == "main" => Some(func.pos),
+                        _ => None,
+                    })
+                    .unwrap_or(0);
+                self.error(pos, "'main' must be declared as 'int main()'".to_string());
+            }
+            None => self.error(0, "undefined reference to 'main'".to_string()),
+        }
+    }
+
+    fn check_decl(&mut self, decl: &Decl) {
+        match decl {
+            Decl::ConstDecl(const_decl) => {
+                for const_def in const_decl.const_defs.iter() {
+                    self.check_const_def(const_def);
+                }
+            }
+            Decl::VarDecl(var_decl) => {
+                for var_def in var_decl.var_defs.iter() {
+                    self.check_var_def(var_def);
+                }
+            }
+        }
+    }
+
+    fn check_const_def(&mut self, const_def: &ConstDef) {
+        let dims = self.check_dims(&const_def.index);
+        let value = match const_def.const_init_val.as_ref() {
+            ConstInitVal::ConstExp(exp) if const_def.index.is_empty() => self.const_eval(&exp.exp),
+            ConstInitVal::ConstExp(exp) => {
+                self.const_eval(&exp.exp);
+                self.error(
+                    const_def.pos,
+                    format!(
+                        "array '{}' must be initialized with a list",
+                        const_def.ident
+                    ),
+                );
+                None
+            }
+            ConstInitVal::ConstArray(init) => {
+                if const_def.index.is_empty() {
+                    self.error(
+                        const_def.pos,
+                        format!("scalar '{}' initialized with a list", const_def.ident),
+                    );
+                }
+                self.check_const_init_list(init);
+                self.check_init_size(init, &dims, &const_def.ident, const_def.pos);
+                None
+            }
+        };
+        let symbol = Symbol::Const { dims, value };
+        self.define(&const_def.ident, const_def.pos, symbol);
+    }
+
+    fn check_const_init_list(&mut self, init: &[ConstInitVal]) {
+        for val in init {
+            match val {
+                ConstInitVal::ConstExp(exp) => {
+                    self.const_eval(&exp.exp);
+                }
+                ConstInitVal::ConstArray(init) => self.check_const_init_list(init),
+            }
+        }
+    }
+
+    fn check_var_def(&mut self, var_def: &VarDef) {
+        let (ident, pos, index) = match var_def {
+            VarDef::VarDef { ident, pos, index } => (ident, *pos, index),
+            VarDef::VarDefInit {
+                ident, pos, index, ..
+            } => (ident, *pos, index),
+        };
+        let dims = self.check_dims(index);
+        if let VarDef::VarDefInit { init_val, .. } = var_def {
+            match init_val.as_ref() {
+                InitVal::Exp(exp) => {
+                    if !index.is_empty() {
+                        self.error(
+                            pos,
+                            format!("array '{}' must be initialized with a list", ident),
+                        );
+                    }
+                    self.check_init_exp(exp);
+                }
+                InitVal::Array(init) => {
+                    if index.is_empty() {
+                        self.error(pos, format!("scalar '{}' initialized with a list", ident));
+                    }
+                    self.check_init_list(init);
+                    self.check_init_size(init, &dims, ident, pos);
+                }
+            }
+        }
+        // the initializer cannot see the variable itself
+        self.define(ident, pos, Symbol::Var { dims });
+    }
+
+    fn check_init_list(&mut self, init: &[InitVal]) {
+        for val in init {
+            match val {
+                InitVal::Exp(exp) => self.check_init_exp(exp),
+                InitVal::Array(init) => self.check_init_list(init),
+            }
+        }
+    }
+
+    fn check_init_exp(&mut self, exp: &Exp) {
+        // globals are initialized at compile time
+        if self.scopes.len() == 1 {
+            self.const_eval(exp);
+        } else {
+            self.check_int_exp(exp);
+        }
+    }
+
+    fn check_func_def(&mut self, func_def: &FuncDef) {
+        let mut params = Vec::new();
+        for param in func_def.func_f_params.iter() {
+            // `int a[][n]...`, the first size is omitted
+            let dims = match param.get_size() {
+                Some(size) => {
+                    let mut dims = vec![None];
+                    dims.extend(self.check_dims(size));
+                    dims
+                }
+                None => Vec::new(),
+            };
+            params.push(dims);
+        }
+        let returns_value = matches!(func_def.return_type, BType::Int);
+        if self.funcs.contains_key(&func_def.ident) || self.scopes[0].contains_key(&func_def.ident)
+        {
+            self.error(
+                func_def.pos,
+                format!("redefinition of '{}'", func_def.ident),
+            );
+        }
+        self.funcs.insert(
+            func_def.ident.clone(),
+            FuncSig {
+                returns_value,
+                params: params.clone(),
+            },
+        );
+        // parameters and the function body share one scope
+        self.scopes.push(HashMap::new());
+        for (param, dims) in func_def.func_f_params.iter().zip(params) {
+            if let BType::Void = param.get_btype() {
+                self.error(
+                    param.get_pos(),
+                    format!("parameter '{}' has void type", param.get_ident()),
+                );
+            }
+            self.define(param.get_ident(), param.get_pos(), Symbol::Var { dims });
+        }
+        self.returns_value = returns_value;
+        self.check_block_items(&func_def.block);
+        self.scopes.pop();
+    }
+
+    // ---- statements ----
+
+    fn check_block_items(&mut self, block: &Block) {
+        for item in block.block_items.iter() {
+            match item {
+                BlockItem::Decl(decl, _) => self.check_decl(decl),
+                BlockItem::Stmt(stmt, pos) => self.check_stmt(stmt, *pos),
+            }
+        }
+    }
+
+    // `pos` is the start of the statement
+    fn check_stmt(&mut self, stmt: &Stmt, pos: Pos) {
+        match stmt {
+            Stmt::AssignStmt(lval, exp) => {
+                match self.lookup(&lval.ident) {
+                    Some(Symbol::Const { .. }) => self.error(
+                        lval.pos,
+                        format!("cannot assign to constant '{}'", lval.ident),
+                    ),
+                    Some(symbol) if symbol.dims().len() != lval.index.len() => {
+                        self.error(lval.pos, format!("cannot assign to array '{}'", lval.ident))
+                    }
+                    _ => {}
+                }
+                self.check_lval(lval);
+                self.check_int_exp(exp);
+            }
+            Stmt::ExpStmt(exp) => {
+                if let Some(exp) = exp.as_ref() {
+                    self.check_exp(exp);
+                }
+            }
+            Stmt::BlockStmt(block) => {
+                self.scopes.push(HashMap::new());
+                self.check_block_items(block);
+                self.scopes.pop();
+            }
+            Stmt::IfStmt(cond, then_stmt, else_stmt) => {
+                self.check_int_exp(cond);
+                self.check_stmt(then_stmt, pos);
+                if let Some(else_stmt) = else_stmt {
+                    self.check_stmt(else_stmt, pos);
+                }
+            }
+            Stmt::WhileStmt(cond, body) => {
+                self.check_int_exp(cond);
+                self.loop_depth += 1;
+                self.check_stmt(body, pos);
+                self.loop_depth -= 1;
+            }
+            Stmt::BreakStmt | Stmt::ContinueStmt => {
+                if self.loop_depth == 0 {
+                    let keyword = if let Stmt::BreakStmt = stmt {
+                        "break"
+                    } else {
+                        "continue"
+                    };
+                    self.error(pos, format!("'{}' statement not in loop", keyword));
+                }
+            }
+            Stmt::ReturnStmt(exp) => match (exp.as_ref(), self.returns_value) {
+                (Some(exp), true) => self.check_int_exp(exp),
+                (Some(exp), false) => {
+                    self.check_exp(exp);
+                    self.error(
+                        exp.get_pos(),
+                        "void function should not return a value".to_string(),
+                    );
+                }
+                (None, true) => {
+                    self.error(pos, "non-void function should return a value".to_string())
+                }
+                (None, false) => {}
+            },
+        }
+    }
+
+    // ---- expressions ----
+
+    fn check_int_exp(&mut self, exp: &Exp) {
+        let shape = self.check_exp(exp);
+        self.expect_int(shape, exp.get_pos());
+    }
+
+    fn expect_int(&mut self, shape: Shape, pos: Pos) {
+        match shape {
+            Some(dims) if dims.is_empty() => {}
+            Some(_) => self.error(pos, "array used where an int is expected".to_string()),
+            None => self.error(pos, "void value used where an int is expected".to_string()),
+        }
+    }
+
+    fn check_exp(&mut self, exp: &Exp) -> Shape {
+        match exp {
+            Exp::LOrExp(exp, pos) => self.check_lor_exp(exp, *pos),
+        }
+    }
+
+    fn check_lval(&mut self, lval: &LVal) -> Shape {
+        for index in lval.index.iter() {
+            self.check_int_exp(index);
+        }
+        match self.lookup(&lval.ident) {
+            Some(symbol) if lval.index.len() > symbol.dims().len() => {
+                self.error(
+                    lval.pos,
+                    format!("too many subscripts for '{}'", lval.ident),
+                );
+                Some(Vec::new())
+            }
+            Some(symbol) => Some(symbol.dims()[lval.index.len()..].to_vec()),
+            None => {
+                self.error(lval.pos, format!("'{}' is not defined", lval.ident));
+                Some(Vec::new())
+            }
+        }
+    }
+
+    fn check_primary_exp(&mut self, exp: &PrimaryExp) -> Shape {
+        match exp {
+            PrimaryExp::BracketExp(exp) => self.check_exp(exp),
+            PrimaryExp::LVal(lval) => self.check_lval(lval),
+            PrimaryExp::Number(_) => Some(Vec::new()),
+        }
+    }
+
+    fn check_unary_exp(&mut self, exp: &UnaryExp, pos: Pos) -> Shape {
+        match exp {
+            UnaryExp::PrimaryExp(exp) => self.check_primary_exp(exp),
+            UnaryExp::UnaryExp(_, exp) => {
+                let shape = self.check_unary_exp(exp, pos);
+                self.expect_int(shape, pos);
+                Some(Vec::new())
+            }
+            UnaryExp::FuncCallExp(ident, args, pos) => {
+                let shapes: Vec<_> = args
+                    .iter()
+                    .map(|arg| (self.check_exp(arg), arg.get_pos()))
+                    .collect();
+                let sig = match self.funcs.get(ident) {
+                    Some(sig) => sig,
+                    None => {
+                        self.error(*pos, format!("function '{}' is not defined", ident));
+                        return Some(Vec::new());
+                    }
+                };
+                let returns_value = sig.returns_value;
+                if sig.params.len() != shapes.len() {
+                    let message = format!(
+                        "function '{}' expects {} arguments, found {}",
+                        ident,
+                        sig.params.len(),
+                        shapes.len()
+                    );
+                    self.error(*pos, message);
+                } else {
+                    let mismatched: Vec<_> = sig
+                        .params
+                        .iter()
+                        .zip(shapes)
+                        .filter(|(dims, (shape, _))| {
+                            !shape.as_ref().is_some_and(|shape| same_shape(dims, shape))
+                        })
+                        .map(|(_, (_, pos))| pos)
+                        .collect();
+                    for arg_pos in mismatched {
+                        let message = format!("argument type mismatch in call to '{}'", ident);
+                        self.error(arg_pos, message);
+                    }
+                }
+                if returns_value {
+                    Some(Vec::new())
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    fn check_mul_exp(&mut self, exp: &MulExp, pos: Pos) -> Shape {
+        match exp {
+            MulExp::UnaryExp(exp) => self.check_unary_exp(exp, pos),
+            MulExp::MulExp(lhs, _, rhs, op_pos) => {
+                let shape = self.check_mul_exp(lhs, pos);
+                self.expect_int(shape, pos);
+                let shape = self.check_unary_exp(rhs, *op_pos);
+                self.expect_int(shape, *op_pos);
+                Some(Vec::new())
+            }
+        }
+    }
+
+    fn check_add_exp(&mut self, exp: &AddExp, pos: Pos) -> Shape {
+        match exp {
+            AddExp::MulExp(exp) => self.check_mul_exp(exp, pos),
+            AddExp::AddExp(lhs, _, rhs) => {
+                let shape = self.check_add_exp(lhs, pos);
+                self.expect_int(shape, pos);
+                let shape = self.check_mul_exp(rhs, pos);
+                self.expect_int(shape, pos);
+                Some(Vec::new())
+            }
+        }
+    }
+
+    fn check_rel_exp(&mut self, exp: &RelExp, pos: Pos) -> Shape {
+        match exp {
+            RelExp::AddExp(exp) => self.check_add_exp(exp, pos),
+            RelExp::RelExp(lhs, _, rhs) => {
+                let shape = self.check_rel_exp(lhs, pos);
+                self.expect_int(shape, pos);
+                let shape = self.check_add_exp(rhs, pos);
+                self.expect_int(shape, pos);
+                Some(Vec::new())
+            }
+        }
+    }
+
+    fn check_eq_exp(&mut self, exp: &EqExp, pos: Pos) -> Shape {
+        match exp {
+            EqExp::RelExp(exp) => self.check_rel_exp(exp, pos),
+            EqExp::EqExp(lhs, _, rhs) => {
+                let shape = self.check_eq_exp(lhs, pos);
+                self.expect_int(shape, pos);
+                let shape = self.check_rel_exp(rhs, pos);
+                self.expect_int(shape, pos);
+                Some(Vec::new())
+            }
+        }
+    }
+
+    fn check_land_exp(&mut self, exp: &LAndExp, pos: Pos) -> Shape {
+        match exp {
+            LAndExp::EqExp(exp) => self.check_eq_exp(exp, pos),
+            LAndExp::LAndExp(lhs, rhs) => {
+                let shape = self.check_land_exp(lhs, pos);
+                self.expect_int(shape, pos);
+                let shape = self.check_eq_exp(rhs, pos);
+                self.expect_int(shape, pos);
+                Some(Vec::new())
+            }
+        }
+    }
+
+    fn check_lor_exp(&mut self, exp: &LOrExp, pos: Pos) -> Shape {
+        match exp {
+            LOrExp::LAndExp(exp) => self.check_land_exp(exp, pos),
+            LOrExp::LOrExp(lhs, rhs) => {
+                let shape = self.check_lor_exp(lhs, pos);
+                self.expect_int(shape, pos);
+                let shape = self.check_land_exp(rhs, pos);
+                self.expect_int(shape, pos);
+                Some(Vec::new())
+            }
+        }
+    }
+}
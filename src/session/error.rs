@@ -0,0 +1,54 @@
+use crate::ast::Pos;
+use crate::semantic::SemanticError;
+use std::fmt;
+use std::io;
+
+// ============ Compile Error ============
+
+#[derive(Debug)]
+pub enum CompileError {
+    // syntax error at `pos`
+    Parse { pos: Pos, message: String },
+    // all errors found by the semantic checks
+    Semantic(Vec<SemanticError>),
+    // the IR builder rejected a program the checks accepted
+    Ir(String),
+    // the verifier found broken IR
+    Pass(String),
+    // warnings were reported under -Werror
+    Warnings(usize),
+    Io(io::Error),
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::Parse { message, .. } => write!(f, "{}", message),
+            CompileError::Semantic(errors) => match errors.first() {
+                Some(error) if errors.len() == 1 => write!(f, "{}", error.message),
+                _ => write!(f, "{} semantic errors", errors.len()),
+            },
+            CompileError::Ir(message) => write!(f, "{}", message),
+            CompileError::Pass(message) => write!(f, "invalid IR {}", message),
+            CompileError::Warnings(count) => {
+                write!(f, "{} warnings treated as errors", count)
+            }
+            CompileError::Io(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CompileError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for CompileError {
+    fn from(err: io::Error) -> Self {
+        CompileError::Io(err)
+    }
+}
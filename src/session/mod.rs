@@ -0,0 +1,240 @@
+mod error;
+pub use error::CompileError;
+
+use crate::asm::{koopa_to_asm, AsmConfig, RegAllocKind};
+use crate::ast::{CompUnit, LineIndex, Pos};
+use crate::ir::{compile, SanitizeConfig};
+use crate::opt::{PassConfig, PassManager};
+use crate::semantic;
+use crate::sysy::CompUnitParser;
+use crate::warning::{Warning, WarningConfig};
+use koopa::back::KoopaGenerator;
+use koopa::ir::{Program, Type};
+use koopa::opt::Pass;
+use lalrpop_util::ParseError;
+use std::io::Write;
+
+/*
+    Embedding API:
+
+        let compiler = Compiler::new(Options::default());
+        let mut session = compiler.session("a.sy", source);
+        let program = session.compile()?;      // parse, check, build IR, run passes
+        session.emit_asm(&program, &mut out)?;
+
+    Each step is also available on its own (parse / check / build_ir / run_passes).
+*/
+
+// ============ Options ============
+
+#[derive(Clone, Default)]
+pub struct Options {
+    pub warnings: WarningConfig,
+    pub sanitize: SanitizeConfig,
+    pub asm: AsmConfig,
+    // -O<level>, selects the pass pipeline
+    pub opt_level: u8,
+    pub passes: PassConfig,
+}
+
+// ============ Compiler ============
+
+pub struct Compiler {
+    options: Options,
+}
+
+impl Compiler {
+    pub fn new(mut options: Options) -> Self {
+        // RV32
+        Type::set_ptr_size(4);
+        if options.asm.tail_calls.is_none() {
+            options.asm.tail_calls = Some(options.opt_level >= 1);
+        }
+        if options.asm.regalloc.is_none() {
+            options.asm.regalloc = Some(if options.opt_level >= 2 {
+                RegAllocKind::GraphColoring
+            } else {
+                RegAllocKind::Greedy
+            });
+        }
+        Compiler { options }
+    }
+
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn session(&self, file_name: &str, source: &str) -> Session {
+        let pass_manager =
+            PassManager::with_pipeline(self.options.opt_level, self.options.passes.clone());
+        Session {
+            options: self.options.clone(),
+            file_name: file_name.to_string(),
+            source: source.to_string(),
+            line_index: LineIndex::new(source),
+            warnings: Vec::new(),
+            pass_manager,
+        }
+    }
+}
+
+// ============ Session ============
+// Compilation of one source file
+
+pub struct Session {
+    options: Options,
+    file_name: String,
+    source: String,
+    line_index: LineIndex,
+    warnings: Vec<Warning>,
+    pass_manager: PassManager,
+}
+
+impl Session {
+    pub fn options(&self) -> &Options {
+        &self.options
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    pub fn file_name(&self) -> &str {
+        &self.file_name
+    }
+
+    pub fn parse(&self) -> Result<CompUnit, CompileError> {
+        CompUnitParser::new()
+            .parse(&self.source)
+            .map_err(|err| match err {
+                ParseError::InvalidToken { location } => CompileError::Parse {
+                    pos: location,
+                    message: "invalid token".to_string(),
+                },
+                ParseError::UnrecognizedEOF { location, expected } => CompileError::Parse {
+                    pos: location,
+                    message: format!(
+                        "unexpected end of file, expected one of {}",
+                        expected.join(", ")
+                    ),
+                },
+                ParseError::UnrecognizedToken {
+                    token: (pos, token, _),
+                    expected,
+                } => CompileError::Parse {
+                    pos,
+                    message: format!(
+                        "unexpected '{}', expected one of {}",
+                        token,
+                        expected.join(", ")
+                    ),
+                },
+                ParseError::ExtraToken {
+                    token: (pos, token, _),
+                } => CompileError::Parse {
+                    pos,
+                    message: format!("unexpected '{}'", token),
+                },
+                ParseError::User {
+                    error: (pos, message),
+                } => CompileError::Parse {
+                    pos,
+                    message: message.to_string(),
+                },
+            })
+    }
+
+    pub fn check(&self, ast: &CompUnit) -> Result<(), CompileError> {
+        semantic::check(ast).map_err(CompileError::Semantic)
+    }
+
+    // generate Koopa IR, warnings are kept in the session
+    pub fn build_ir(&mut self, ast: &CompUnit) -> Result<Program, CompileError> {
+        let (program, mut warnings) = compile(
+            ast,
+            self.line_index.clone(),
+            self.options.warnings.clone(),
+            self.options.sanitize,
+        )
+        .map_err(CompileError::Ir)?;
+        let has_errors = warnings.has_errors();
+        self.warnings = warnings.take_warnings();
+        if has_errors {
+            return Err(CompileError::Warnings(self.warnings.len()));
+        }
+        Ok(program)
+    }
+
+    // appended after the -O pipeline
+    pub fn register_pass(&mut self, name: &str, pass: Pass) {
+        self.pass_manager.register(name, pass);
+    }
+
+    pub fn pass_manager(&self) -> &PassManager {
+        &self.pass_manager
+    }
+
+    pub fn run_passes(&mut self, program: &mut Program) -> Result<(), CompileError> {
+        self.pass_manager.run(program).map_err(CompileError::Pass)
+    }
+
+    // parse, check, build IR and run the passes (the -O pipeline, then registered ones)
+    pub fn compile(&mut self) -> Result<Program, CompileError> {
+        let ast = self.parse()?;
+        self.check(&ast)?;
+        let mut program = self.build_ir(&ast)?;
+        self.run_passes(&mut program)?;
+        Ok(program)
+    }
+
+    pub fn emit_ast(&self, ast: &CompUnit, out: &mut dyn Write) -> Result<(), CompileError> {
+        writeln!(out, "{:#?}", ast)?;
+        Ok(())
+    }
+
+    pub fn emit_koopa(&self, program: &Program, out: &mut dyn Write) -> Result<(), CompileError> {
+        let mut generator = KoopaGenerator::new(out);
+        generator.generate_on(program)?;
+        Ok(())
+    }
+
+    pub fn emit_asm(&self, program: &Program, out: &mut dyn Write) -> Result<(), CompileError> {
+        out.write_all(koopa_to_asm(program, &self.options.asm).as_bytes())?;
+        Ok(())
+    }
+
+    // ---- diagnostics ----
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    pub fn line_col(&self, pos: Pos) -> (usize, usize) {
+        self.line_index.line_col(pos)
+    }
+
+    pub fn render_warning(&self, warning: &Warning) -> String {
+        warning.format(
+            &self.file_name,
+            &self.line_index,
+            self.options.warnings.werror,
+        )
+    }
+
+    // one "file:line:col: error: message" line per error
+    pub fn render_error(&self, err: &CompileError) -> String {
+        let located = |pos: Pos, message: &str| {
+            let (line, col) = self.line_col(pos);
+            format!("{}:{}:{}: error: {}", self.file_name, line, col, message)
+        };
+        match err {
+            CompileError::Parse { pos, message } => located(*pos, message),
+            CompileError::Semantic(errors) => errors
+                .iter()
+                .map(|error| located(error.pos, &error.message))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            _ => format!("{}: error: {}", self.file_name, err),
+        }
+    }
+}
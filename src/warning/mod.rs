@@ -0,0 +1,208 @@
+use crate::ast::{LineIndex, Pos};
+
+// ============ Lints ============
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedParameter,
+    UnusedFunction,
+    Uninitialized,
+    UnreachableCode,
+    ReturnType,
+    Shadow,
+    ConstantCondition,
+    ArrayBounds,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 9] = [
+        Lint::UnusedVariable,
+        Lint::UnusedParameter,
+        Lint::UnusedFunction,
+        Lint::Uninitialized,
+        Lint::UnreachableCode,
+        Lint::ReturnType,
+        Lint::Shadow,
+        Lint::ConstantCondition,
+        Lint::ArrayBounds,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::UnusedFunction => "unused-function",
+            Lint::Uninitialized => "uninitialized",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::ReturnType => "return-type",
+            Lint::Shadow => "shadow",
+            Lint::ConstantCondition => "constant-condition",
+            Lint::ArrayBounds => "array-bounds",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "local variable or constant that is never read",
+            Lint::UnusedParameter => "function parameter that is never read",
+            Lint::UnusedFunction => "function that is never called",
+            Lint::Uninitialized => "local variable read before any assignment",
+            Lint::UnreachableCode => "statement after return, break or continue",
+            Lint::ReturnType => "control reaches the end of a non-void function",
+            Lint::Shadow => "declaration hides a variable of an outer scope",
+            Lint::ConstantCondition => {
+                "if condition always true/false, while condition always false"
+            }
+            Lint::ArrayBounds => "array subscript is a constant out of bounds",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Lint> {
+        Lint::ALL.iter().find(|lint| lint.name() == name).copied()
+    }
+
+    // -Wshadow and -Wunused-parameter are noisy on typical SysY code, so they are opt-in
+    pub fn enabled_by_default(&self) -> bool {
+        !matches!(self, Lint::Shadow | Lint::UnusedParameter)
+    }
+}
+
+// ============ Warning Config ============
+
+#[derive(Clone)]
+pub struct WarningConfig {
+    enabled: [bool; Lint::ALL.len()],
+    pub werror: bool,
+}
+
+impl WarningConfig {
+    pub fn new() -> Self {
+        let mut enabled = [false; Lint::ALL.len()];
+        for lint in Lint::ALL.iter() {
+            enabled[*lint as usize] = lint.enabled_by_default();
+        }
+        WarningConfig {
+            enabled,
+            werror: false,
+        }
+    }
+
+    pub fn is_enabled(&self, lint: Lint) -> bool {
+        self.enabled[lint as usize]
+    }
+
+    pub fn set(&mut self, lint: Lint, enabled: bool) {
+        self.enabled[lint as usize] = enabled;
+    }
+
+    pub fn set_all(&mut self, enabled: bool) {
+        self.enabled = [enabled; Lint::ALL.len()];
+    }
+
+    // parse one of -W<lint>, -Wno-<lint>, -Wall, -Wnone (-w), -Werror, -Wno-error
+    pub fn parse_flag(&mut self, flag: &str) -> Result<(), String> {
+        if flag == "-w" {
+            self.set_all(false);
+            return Ok(());
+        }
+        let name = match flag.strip_prefix("-W") {
+            Some(name) => name,
+            None => return Err(format!("not a warning flag: {}", flag)),
+        };
+        match name {
+            "all" => self.set_all(true),
+            "none" => self.set_all(false),
+            "error" => self.werror = true,
+            "no-error" => self.werror = false,
+            _ => {
+                let (name, enabled) = match name.strip_prefix("no-") {
+                    Some(name) => (name, false),
+                    None => (name, true),
+                };
+                match Lint::from_name(name) {
+                    Some(lint) => self.set(lint, enabled),
+                    None => return Err(format!("unknown warning option: {}", flag)),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for WarningConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// ============ Warning ============
+
+pub struct Warning {
+    pub lint: Lint,
+    pub pos: Pos,
+    pub message: String,
+}
+
+impl Warning {
+    pub fn format(&self, file: &str, line_index: &LineIndex, werror: bool) -> String {
+        let (line, col) = line_index.line_col(self.pos);
+        if werror {
+            format!(
+                "{}:{}:{}: error: {} [-Werror={}]",
+                file,
+                line,
+                col,
+                self.message,
+                self.lint.name()
+            )
+        } else {
+            format!(
+                "{}:{}:{}: warning: {} [-W{}]",
+                file,
+                line,
+                col,
+                self.message,
+                self.lint.name()
+            )
+        }
+    }
+}
+
+// ============ Warning Emitter ============
+// Collects the warnings reported while compiling
+
+pub struct WarningEmitter {
+    pub config: WarningConfig,
+    warnings: Vec<Warning>,
+}
+
+impl WarningEmitter {
+    pub fn new(config: WarningConfig) -> Self {
+        WarningEmitter {
+            config,
+            warnings: Vec::new(),
+        }
+    }
+
+    pub fn warn(&mut self, lint: Lint, pos: Pos, message: String) {
+        if self.config.is_enabled(lint) {
+            self.warnings.push(Warning { lint, pos, message });
+        }
+    }
+
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    // warnings sorted by source position
+    pub fn take_warnings(&mut self) -> Vec<Warning> {
+        let mut warnings = std::mem::take(&mut self.warnings);
+        warnings.sort_by_key(|w| w.pos);
+        warnings
+    }
+
+    pub fn has_errors(&self) -> bool {
+        self.config.werror && !self.warnings.is_empty()
+    }
+}
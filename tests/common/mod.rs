@@ -0,0 +1,337 @@
+use koopa::ir::{BinaryOp, Function, FunctionData, Program, Type, Value, ValueKind};
+use std::collections::HashMap;
+
+// ============ Koopa Interpreter ============
+/*
+    Runs a Koopa program with the SysY library, so that the output of the
+    optimized IR can be compared with the unoptimized one. Memory is a list
+    of objects (globals and allocs) of 4-byte cells; a pointer is an object
+    and a cell index into it.
+*/
+
+// steps before a program is taken to loop forever
+const MAX_STEPS: usize = 100_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Val {
+    Int(i32),
+    Ptr(usize, usize),
+}
+
+impl Val {
+    fn int(self) -> i32 {
+        match self {
+            Val::Int(value) => value,
+            Val::Ptr(..) => panic!("pointer used as an int"),
+        }
+    }
+
+    fn ptr(self) -> (usize, usize) {
+        match self {
+            Val::Ptr(object, cell) => (object, cell),
+            Val::Int(_) => panic!("int used as a pointer"),
+        }
+    }
+
+    fn offset(self, cells: i64) -> Val {
+        let (object, cell) = self.ptr();
+        Val::Ptr(object, (cell as i64 + cells) as usize)
+    }
+}
+
+// (stdout, return value of main)
+pub fn run(program: &Program, input: &str) -> (String, i32) {
+    let mut machine = Machine {
+        program,
+        memory: Vec::new(),
+        globals: HashMap::new(),
+        input: input.as_bytes().to_vec(),
+        input_pos: 0,
+        output: String::new(),
+        steps: 0,
+    };
+    for &global in program.inst_layout() {
+        let init = match program.borrow_value(global).kind() {
+            ValueKind::GlobalAlloc(alloc) => alloc.init(),
+            _ => unreachable!(),
+        };
+        let mut cells = Vec::new();
+        machine.flatten(None, init, &mut cells);
+        machine.globals.insert(global, machine.memory.len());
+        machine.memory.push(cells);
+    }
+    let main = program
+        .funcs()
+        .iter()
+        .find(|(_, data)| data.name() == "@main")
+        .map(|(&func, _)| func)
+        .expect("no main");
+    let ret = machine.call(main, Vec::new()).unwrap().int();
+    (machine.output, ret)
+}
+
+fn cells(ty: &Type) -> usize {
+    ty.size() / 4
+}
+
+struct Machine<'a> {
+    program: &'a Program,
+    memory: Vec<Vec<Val>>,
+    globals: HashMap<Value, usize>,
+    input: Vec<u8>,
+    input_pos: usize,
+    output: String,
+    steps: usize,
+}
+
+impl Machine<'_> {
+    // the cells of an initializer, a global one if `data` is None
+    fn flatten(&self, data: Option<&FunctionData>, value: Value, out: &mut Vec<Val>) {
+        let (kind, ty) = match data {
+            Some(data) => {
+                let value_data = data.dfg().value(value);
+                (value_data.kind().clone(), value_data.ty().clone())
+            }
+            None => {
+                let value_data = self.program.borrow_value(value);
+                (value_data.kind().clone(), value_data.ty().clone())
+            }
+        };
+        match kind {
+            ValueKind::Integer(int) => out.push(Val::Int(int.value())),
+            ValueKind::ZeroInit(_) | ValueKind::Undef(_) => {
+                out.extend(std::iter::repeat_n(Val::Int(0), cells(&ty)))
+            }
+            ValueKind::Aggregate(aggregate) => {
+                for &elem in aggregate.elems() {
+                    self.flatten(data, elem, out);
+                }
+            }
+            kind => panic!("unexpected initializer {:?}", kind),
+        }
+    }
+
+    fn eval(&self, data: &FunctionData, locals: &HashMap<Value, Val>, value: Value) -> Val {
+        if value.is_global() {
+            return Val::Ptr(self.globals[&value], 0);
+        }
+        match data.dfg().value(value).kind() {
+            ValueKind::Integer(int) => Val::Int(int.value()),
+            ValueKind::Undef(_) => Val::Int(0),
+            _ => *locals
+                .get(&value)
+                .unwrap_or_else(|| panic!("{:?} used before its definition", value)),
+        }
+    }
+
+    fn load(&self, ptr: Val) -> Val {
+        let (object, cell) = ptr.ptr();
+        self.memory[object][cell]
+    }
+
+    fn store(&mut self, ptr: Val, value: Val) {
+        let (object, cell) = ptr.ptr();
+        self.memory[object][cell] = value;
+    }
+
+    fn call(&mut self, func: Function, args: Vec<Val>) -> Option<Val> {
+        let data = self.program.func(func);
+        let mut bb = match data.layout().entry_bb() {
+            Some(entry) => entry,
+            None => return self.library(&data.name()[1..], &args),
+        };
+        let mut locals: HashMap<Value, Val> = data.params().iter().copied().zip(args).collect();
+        loop {
+            let insts: Vec<Value> = data
+                .layout()
+                .bbs()
+                .node(&bb)
+                .unwrap()
+                .insts()
+                .keys()
+                .copied()
+                .collect();
+            let mut next = None;
+            for inst in insts {
+                self.steps += 1;
+                assert!(self.steps < MAX_STEPS, "too many steps");
+                let value_data = data.dfg().value(inst);
+                let result = match value_data.kind() {
+                    ValueKind::Alloc(_) => {
+                        let ty = match value_data.ty().kind() {
+                            koopa::ir::TypeKind::Pointer(base) => base.clone(),
+                            _ => unreachable!(),
+                        };
+                        self.memory.push(vec![Val::Int(0); cells(&ty)]);
+                        Some(Val::Ptr(self.memory.len() - 1, 0))
+                    }
+                    ValueKind::Load(load) => Some(self.load(self.eval(data, &locals, load.src()))),
+                    ValueKind::Store(store) => {
+                        let dest = self.eval(data, &locals, store.dest());
+                        let is_value = store.value().is_global()
+                            || !matches!(
+                                data.dfg().value(store.value()).kind(),
+                                ValueKind::Aggregate(_) | ValueKind::ZeroInit(_)
+                            );
+                        if is_value {
+                            let value = self.eval(data, &locals, store.value());
+                            self.store(dest, value);
+                        } else {
+                            let mut cells = Vec::new();
+                            self.flatten(Some(data), store.value(), &mut cells);
+                            for (i, cell) in cells.into_iter().enumerate() {
+                                self.store(dest.offset(i as i64), cell);
+                            }
+                        }
+                        None
+                    }
+                    ValueKind::GetPtr(gp) => {
+                        let src = self.eval(data, &locals, gp.src());
+                        let index = self.eval(data, &locals, gp.index()).int();
+                        Some(src.offset(index as i64 * self.step(value_data.ty())))
+                    }
+                    ValueKind::GetElemPtr(gep) => {
+                        let src = self.eval(data, &locals, gep.src());
+                        let index = self.eval(data, &locals, gep.index()).int();
+                        Some(src.offset(index as i64 * self.step(value_data.ty())))
+                    }
+                    ValueKind::Binary(bin) => {
+                        let lhs = self.eval(data, &locals, bin.lhs()).int();
+                        let rhs = self.eval(data, &locals, bin.rhs()).int();
+                        Some(Val::Int(binary(bin.op(), lhs, rhs)))
+                    }
+                    ValueKind::Call(call) => {
+                        let args = call
+                            .args()
+                            .iter()
+                            .map(|&arg| self.eval(data, &locals, arg))
+                            .collect();
+                        self.call(call.callee(), args)
+                    }
+                    ValueKind::Jump(jump) => {
+                        next = Some((jump.target(), jump.args().to_vec()));
+                        None
+                    }
+                    ValueKind::Branch(branch) => {
+                        next = Some(if self.eval(data, &locals, branch.cond()).int() != 0 {
+                            (branch.true_bb(), branch.true_args().to_vec())
+                        } else {
+                            (branch.false_bb(), branch.false_args().to_vec())
+                        });
+                        None
+                    }
+                    ValueKind::Return(ret) => {
+                        return ret.value().map(|value| self.eval(data, &locals, value));
+                    }
+                    kind => panic!("unexpected instruction {:?}", kind),
+                };
+                if let Some(result) = result {
+                    locals.insert(inst, result);
+                }
+            }
+            let (target, args) = next.expect("block without a terminator");
+            let args: Vec<Val> = args
+                .iter()
+                .map(|&arg| self.eval(data, &locals, arg))
+                .collect();
+            locals.extend(data.dfg().bb(target).params().iter().copied().zip(args));
+            bb = target;
+        }
+    }
+
+    // cells between consecutive elements of a pointer of type `ty`
+    fn step(&self, ty: &Type) -> i64 {
+        match ty.kind() {
+            koopa::ir::TypeKind::Pointer(base) => cells(base) as i64,
+            _ => unreachable!(),
+        }
+    }
+
+    fn library(&mut self, name: &str, args: &[Val]) -> Option<Val> {
+        match name {
+            "getint" => Some(Val::Int(self.read_int())),
+            "getch" => {
+                let ch = self.input.get(self.input_pos).map_or(-1, |&ch| ch as i32);
+                self.input_pos += 1;
+                Some(Val::Int(ch))
+            }
+            "getarray" => {
+                let len = self.read_int();
+                for i in 0..len {
+                    let value = self.read_int();
+                    self.store(args[0].offset(i as i64), Val::Int(value));
+                }
+                Some(Val::Int(len))
+            }
+            "putint" => {
+                self.output += &args[0].int().to_string();
+                None
+            }
+            "putch" => {
+                self.output.push(args[0].int() as u8 as char);
+                None
+            }
+            "putarray" => {
+                let len = args[0].int();
+                self.output += &format!("{}:", len);
+                for i in 0..len {
+                    let value = self.load(args[1].offset(i as i64)).int();
+                    self.output += &format!(" {}", value);
+                }
+                self.output.push('\n');
+                None
+            }
+            "starttime" | "stoptime" => None,
+            "__sysy_memset" => {
+                for i in 0..args[2].int() {
+                    self.store(args[0].offset(i as i64), args[1]);
+                }
+                None
+            }
+            "__sysy_memcpy" => {
+                for i in 0..args[2].int() {
+                    let value = self.load(args[1].offset(i as i64));
+                    self.store(args[0].offset(i as i64), value);
+                }
+                None
+            }
+            _ => panic!("call to unknown function {}", name),
+        }
+    }
+
+    fn read_int(&mut self) -> i32 {
+        let text = String::from_utf8_lossy(&self.input[self.input_pos..]).into_owned();
+        let start = text.len() - text.trim_start().len();
+        let digits = text[start..]
+            .char_indices()
+            .take_while(|&(i, ch)| ch.is_ascii_digit() || (i == 0 && ch == '-'))
+            .count();
+        self.input_pos += start + digits;
+        text[start..start + digits].parse().unwrap_or(0)
+    }
+}
+
+// RV32M semantics, like the constant folding
+fn binary(op: BinaryOp, lhs: i32, rhs: i32) -> i32 {
+    match op {
+        BinaryOp::NotEq => (lhs != rhs) as i32,
+        BinaryOp::Eq => (lhs == rhs) as i32,
+        BinaryOp::Gt => (lhs > rhs) as i32,
+        BinaryOp::Lt => (lhs < rhs) as i32,
+        BinaryOp::Ge => (lhs >= rhs) as i32,
+        BinaryOp::Le => (lhs <= rhs) as i32,
+        BinaryOp::Add => lhs.wrapping_add(rhs),
+        BinaryOp::Sub => lhs.wrapping_sub(rhs),
+        BinaryOp::Mul => lhs.wrapping_mul(rhs),
+        BinaryOp::Div if rhs == 0 => -1,
+        BinaryOp::Div => lhs.wrapping_div(rhs),
+        BinaryOp::Mod if rhs == 0 => lhs,
+        BinaryOp::Mod => lhs.wrapping_rem(rhs),
+        BinaryOp::And => lhs & rhs,
+        BinaryOp::Or => lhs | rhs,
+        BinaryOp::Xor => lhs ^ rhs,
+        BinaryOp::Shl => lhs.wrapping_shl(rhs as u32),
+        BinaryOp::Shr => (lhs as u32).wrapping_shr(rhs as u32) as i32,
+        BinaryOp::Sar => lhs.wrapping_shr(rhs as u32),
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::path::Path;
+use sysy_compiler::asm::RegAllocKind;
+use sysy_compiler::{Compiler, Options};
+
+// ============ Deterministic Codegen ============
+/*
+    Every program of tests/corpus is compiled twice under each configuration,
+    with a fresh Compiler each time so that every HashMap gets a new seed.
+    The Koopa IR and the assembly must come out byte-identical.
+*/
+
+fn corpus() -> Vec<(String, String)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/corpus");
+    let mut files: Vec<_> = fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "sy"))
+        .collect();
+    files.sort();
+    files
+        .into_iter()
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, fs::read_to_string(&path).unwrap())
+        })
+        .collect()
+}
+
+// (koopa, asm)
+fn compile(options: &Options, name: &str, source: &str) -> (Vec<u8>, Vec<u8>) {
+    let compiler = Compiler::new(options.clone());
+    let mut session = compiler.session(name, source);
+    let program = session
+        .compile()
+        .unwrap_or_else(|err| panic!("{}: {}", name, session.render_error(&err)));
+    let mut koopa = Vec::new();
+    session.emit_koopa(&program, &mut koopa).unwrap();
+    let mut asm = Vec::new();
+    session.emit_asm(&program, &mut asm).unwrap();
+    (koopa, asm)
+}
+
+fn assert_deterministic(options: Options) {
+    for (name, source) in corpus() {
+        let (koopa, asm) = compile(&options, &name, &source);
+        let (koopa_again, asm_again) = compile(&options, &name, &source);
+        assert!(
+            koopa == koopa_again,
+            "{}: Koopa IR differs between runs",
+            name
+        );
+        assert!(asm == asm_again, "{}: assembly differs between runs", name);
+    }
+}
+
+fn options(opt_level: u8, regalloc: RegAllocKind) -> Options {
+    let mut options = Options {
+        opt_level,
+        ..Options::default()
+    };
+    options.asm.regalloc = Some(regalloc);
+    options
+}
+
+#[test]
+fn greedy_is_deterministic() {
+    for opt_level in 0..=2 {
+        assert_deterministic(options(opt_level, RegAllocKind::Greedy));
+    }
+}
+
+#[test]
+fn linear_scan_is_deterministic() {
+    for opt_level in 0..=2 {
+        assert_deterministic(options(opt_level, RegAllocKind::LinearScan));
+    }
+}
+
+#[test]
+fn graph_coloring_is_deterministic() {
+    for opt_level in 0..=2 {
+        assert_deterministic(options(opt_level, RegAllocKind::GraphColoring));
+    }
+}
+
+#[test]
+fn sanitized_is_deterministic() {
+    let mut options = options(2, RegAllocKind::GraphColoring);
+    options.sanitize.parse_flag("-fsanitize=all").unwrap();
+    options.asm.frame_pointer = true;
+    assert_deterministic(options);
+}
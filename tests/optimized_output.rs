@@ -0,0 +1,99 @@
+mod common;
+
+use sysy_compiler::{Compiler, Options};
+
+// ============ Optimized Output ============
+/*
+    Programs that were miscompiled by an optimization are run through the
+    Koopa interpreter of tests/common, and must print the same output at
+    every optimization level.
+*/
+
+// (stdout, return value of main)
+fn run(opt_level: u8, source: &str, input: &str) -> (String, i32) {
+    let (source, input) = (source.to_string(), input.to_string());
+    // the interpreter recurses with the program, give it room
+    std::thread::Builder::new()
+        .stack_size(256 << 20)
+        .spawn(move || {
+            let options = Options {
+                opt_level,
+                ..Options::default()
+            };
+            let compiler = Compiler::new(options);
+            let mut session = compiler.session("test.sy", &source);
+            let program = session
+                .compile()
+                .unwrap_or_else(|err| panic!("{}", session.render_error(&err)));
+            common::run(&program, &input)
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+fn assert_output(source: &str, input: &str, expected: &str) {
+    let (output, ret) = run(0, source, input);
+    assert_eq!(output, expected, "-O0");
+    for opt_level in [1, 2] {
+        assert_eq!(
+            run(opt_level, source, input),
+            (output.clone(), ret),
+            "-O{}",
+            opt_level
+        );
+    }
+}
+
+// mem2reg run again on blocks that already have parameters
+#[test]
+fn existing_block_params() {
+    assert_output(
+        "int g;
+        int f(int k) {
+            int i = 0;
+            int m = k;
+            while (i < 10) {
+                g = g + i;
+                if (m > 5) m = k;
+                i = i + 1;
+            }
+            return m;
+        }
+        int main() {
+            putint(f(3) + g);
+            putch(10);
+            return 0;
+        }",
+        "",
+        "48\n",
+    );
+}
+
+// a partially unrolled loop whose header has side effects
+#[test]
+fn unrolled_header_effects() {
+    assert_output(
+        "int sum(int n, int acc) {
+            putint(n);
+            putch(32);
+            if (n > 0) return sum(n - 1, acc + n);
+            return acc;
+        }
+        int main() { putint(sum(getint(), 0)); }",
+        "10",
+        "10 9 8 7 6 5 4 3 2 1 0 55",
+    );
+}
+
+// the same element reached through getptr and getelemptr
+#[test]
+fn aliasing_paths() {
+    assert_output(
+        "int g[10];
+        int k(int a[]) { int x = g[1]; a[1] = 5; return x + g[1]; }
+        int main() { putint(k(g)); putch(10); return 0; }",
+        "",
+        "5\n",
+    );
+}
@@ -0,0 +1,101 @@
+use sysy_compiler::{CompileError, Compiler, Options};
+
+// ============ Semantic Errors ============
+/*
+    Programs the IR builder cannot handle must be rejected by the semantic
+    checks with an error, instead of panicking later.
+*/
+
+fn compile(source: &str) -> Result<(), CompileError> {
+    let compiler = Compiler::new(Options::default());
+    let mut session = compiler.session("test.sy", source);
+    session.compile().map(|_| ())
+}
+
+// the messages of the semantic errors, panics on any other result
+fn semantic_errors(source: &str) -> Vec<String> {
+    match compile(source) {
+        Err(CompileError::Semantic(errors)) => errors.into_iter().map(|e| e.message).collect(),
+        Err(err) => panic!("not a semantic error: {:?}", err),
+        Ok(()) => panic!("accepted:\n{}", source),
+    }
+}
+
+fn assert_accepted(source: &str) {
+    if let Err(err) = compile(source) {
+        panic!("rejected: {:?}\n{}", err, source);
+    }
+}
+
+#[test]
+fn excess_initializers() {
+    let sources = [
+        "int a[2] = {1, 2, 3};\nint main() { return 0; }",
+        "int main() { int a[2] = {1, 2, 3}; return a[0]; }",
+        "const int a[2][2] = {{1, 2}, {3, 4}, 5};\nint main() { return 0; }",
+        "int main() { int a[2][2] = {{1, 2, 3}}; return 0; }",
+        "int main() { int a[2][3] = {1, 2, 3, {4}, 5}; return 0; }",
+        // an unaligned nested list initializes a single element
+        "int main() { int a[2][2] = {1, {2, 3}}; return 0; }",
+    ];
+    for source in sources {
+        assert_eq!(
+            semantic_errors(source),
+            ["excess elements in initializer of 'a'"],
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn full_initializers() {
+    assert_accepted(
+        "int a[2][3] = {{1, 2, 3}, {4, 5, 6}};
+        const int b[2][2][2] = {1, {2}, {3, 4}, 5};
+        int main() {
+            int c[3][2] = {1, 2, {3}, 4, 5};
+            int d[2][2] = {};
+            return a[1][2] + b[1][0][0] + c[2][1] + d[1][1];
+        }",
+    );
+}
+
+#[test]
+fn array_argument_sizes() {
+    let sources = [
+        "int f(int a[][3]) { return a[0][0]; }
+        int main() { int b[2][4]; return f(b); }",
+        "int f(int a[][3]) { return a[0][0]; }
+        int main() { int b[4][2][4]; return f(b[1]); }",
+        "int f(int a[][3]) { return a[0][0]; }
+        int g(int a[][4]) { return f(a); }
+        int main() { return 0; }",
+        "int f(int a[][2][3]) { return 0; }
+        int main() { int b[2][3][2]; return f(b); }",
+    ];
+    for source in sources {
+        assert_eq!(
+            semantic_errors(source),
+            ["argument type mismatch in call to 'f'"],
+            "{}",
+            source
+        );
+    }
+}
+
+#[test]
+fn matching_array_arguments() {
+    assert_accepted(
+        "const int N = 3;
+        int f(int a[][N]) { return a[0][0]; }
+        int g(int a[][3]) { return f(a); }
+        int main() {
+            int b[2][3];
+            int c[4][2][3];
+            int d[5];
+            putarray(5, d);
+            return f(b) + g(c[1]) + getarray(c[0][1]);
+        }",
+    );
+}